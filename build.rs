@@ -0,0 +1,22 @@
+use std::{env, process::Command};
+
+/// Returns the minor version of the used Rust compiler.
+fn rustc_minor_version() -> Option<u32> {
+    let rustc = env::var_os("RUSTC")?;
+    let output = Command::new(rustc).arg("--version").output().ok()?;
+    let version = String::from_utf8(output.stdout).ok()?;
+    // The version has the form `rustc 1.59.0 (9d1b2106e 2022-02-23)`.
+    version.split('.').nth(1)?.parse().ok()
+}
+
+fn main() {
+    // Declares the cfg flag allowing to disable mocking (see crate docs). Older Cargo versions
+    // do not support this instruction and ignore it.
+    println!("cargo:rustc-check-cfg=cfg(mimicry_disabled)");
+
+    // `#[diagnostic::*]` attributes are supported since Rust 1.78.
+    println!("cargo:rustc-check-cfg=cfg(diagnostic_attrs)");
+    if rustc_minor_version().map_or(false, |minor| minor >= 78) {
+        println!("cargo:rustc-cfg=diagnostic_attrs");
+    }
+}
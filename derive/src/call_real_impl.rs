@@ -4,20 +4,20 @@ use darling::FromMeta;
 use proc_macro::TokenStream;
 use quote::{quote, ToTokens};
 use syn::{
-    parse::Error as SynError, spanned::Spanned, Data, DataStruct, DeriveInput, Field, Fields,
-    Generics, Ident, Index, Type, TypePath,
+    parse::Error as SynError, parse_quote, spanned::Spanned, Data, DataStruct, DeriveInput, Field,
+    Fields, Generics, Ident, Index, Path, Type, TypePath,
 };
 
 use crate::utils::find_meta_attrs;
 
 #[derive(Debug)]
-enum FieldIdent {
+pub(crate) enum FieldIdent {
     Named(Ident),
     Unnamed(Index),
 }
 
 impl FieldIdent {
-    fn new(idx: usize, field: &Field) -> Self {
+    pub(crate) fn new(idx: usize, field: &Field) -> Self {
         field
             .ident
             .clone()
@@ -34,6 +34,15 @@ impl ToTokens for FieldIdent {
     }
 }
 
+#[derive(Debug, Default, FromMeta)]
+#[darling(allow_unknown_fields)]
+struct ContainerAttrs {
+    #[darling(rename = "crate")]
+    krate: Option<Path>,
+    #[darling(rename = "mut", default)]
+    mutable: bool,
+}
+
 #[derive(Debug, Default, FromMeta)]
 struct FieldAttrs {
     #[darling(default)]
@@ -41,7 +50,8 @@ struct FieldAttrs {
 }
 
 #[derive(Debug)]
-struct CallReal {
+pub(crate) struct CallReal {
+    cr: Path,
     generics: Generics,
     ident: Ident,
     switch_field: FieldIdent,
@@ -52,12 +62,24 @@ impl CallReal {
         let fields = if let Data::Struct(DataStruct { fields, .. }) = &input.data {
             fields
         } else {
-            let message = "can only derive `CallReal` for structs";
+            let message = "can only derive `CallReal` for structs; for other mock states, \
+                use the `Mut` wrapper (`#[mock(mut)]`) or implement `CheckRealCall` manually";
             return Err(SynError::new(input.span(), message));
         };
 
+        let attrs = find_meta_attrs("mock", None, &input.attrs).map_or_else(
+            || Ok(ContainerAttrs::default()),
+            |meta| ContainerAttrs::from_nested_meta(&meta),
+        )?;
+        if attrs.mutable {
+            let message = "`CallReal` should not be derived for `mut` mock states; \
+                `Mut` wrapper already implements `CallReal`, so use `this.call_real()` \
+                in mock impls instead";
+            return Err(SynError::new(input.ident.span(), message));
+        }
         let switch_field = Self::detect_switch_field(fields)?;
         Ok(Self {
+            cr: attrs.krate.unwrap_or_else(|| parse_quote!(mimicry)),
             generics: input.generics.clone(),
             ident: input.ident.clone(),
             switch_field,
@@ -106,7 +128,7 @@ impl CallReal {
         }
     }
 
-    fn is_switch(ty: &Type) -> bool {
+    pub(crate) fn is_switch(ty: &Type) -> bool {
         if let Type::Path(TypePath { path, .. }) = ty {
             path.segments
                 .last()
@@ -117,13 +139,14 @@ impl CallReal {
     }
 
     fn impl_call_real(&self) -> impl ToTokens {
+        let cr = &self.cr;
         let ident = &self.ident;
         let field = &self.switch_field;
         let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
 
         quote! {
-            impl #impl_generics mimicry::CallReal for #ident #ty_generics #where_clause {
-                fn access_switch<R>(&self, action: impl FnOnce(&RealCallSwitch) -> R) -> R {
+            impl #impl_generics #cr::CallReal for #ident #ty_generics #where_clause {
+                fn access_switch<R>(&self, action: impl FnOnce(&#cr::RealCallSwitch) -> R) -> R {
                     action(&self.#field)
                 }
             }
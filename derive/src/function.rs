@@ -1,22 +1,48 @@
 //! Mocked function attribute.
 
-use darling::FromMeta;
+use darling::{
+    util::{PathList, SpannedValue},
+    FromMeta,
+};
 use proc_macro::TokenStream;
-use proc_macro2::Span;
+use proc_macro2::{Span, TokenTree};
 use quote::{quote, quote_spanned, ToTokens};
 use syn::{
-    parse::Error as SynError, parse::Parser, punctuated::Punctuated, spanned::Spanned,
-    token::Comma, FnArg, Ident, Item, ItemFn, ItemImpl, NestedMeta, Pat, PatIdent, Path, Signature,
+    parse::Error as SynError,
+    parse::Parser,
+    punctuated::Punctuated,
+    spanned::Spanned,
+    token::Comma,
+    visit_mut::{self, VisitMut},
+    Expr, FnArg, GenericArgument, GenericParam, Ident, Item, ItemFn, ItemImpl, Lifetime,
+    NestedMeta, Pat, PatIdent, Path, PathArguments, ReturnType, Signature, Stmt, Type, TypeGroup,
+    TypeParamBound, TypeParen, TypePtr, TypeReference, TypeSlice,
 };
 
-use std::mem;
+use std::{collections::HashSet, mem};
 
-use crate::utils::{find_meta_attrs, receiver_span};
+use crate::utils::{find_cfg_predicates, find_meta_attrs, receiver_span};
 
 #[derive(Debug, FromMeta)]
 struct FunctionAttrs {
     using: Path,
-    rename: Option<String>,
+    rename: Option<SpannedValue<String>>,
+    #[darling(rename = "crate")]
+    krate: Option<Path>,
+    instance: Option<String>,
+    #[darling(default)]
+    strict: bool,
+    #[darling(default)]
+    variants: PathList,
+    /// Methods to mock in an impl block. If empty, all methods are mocked.
+    #[darling(default)]
+    methods: PathList,
+    /// Type that the type param of a generic function is pinned to.
+    #[darling(rename = "for")]
+    pinned_type: Option<SpannedValue<Type>>,
+    /// Whether the mock state has a lifetime param and is set via `with_scoped_mock()`.
+    #[darling(default)]
+    scoped: bool,
 }
 
 impl FunctionAttrs {
@@ -31,12 +57,164 @@ impl FunctionAttrs {
         let ident_string = spec.replace("{}", &ident_string);
         Ident::new(&ident_string, ident.span())
     }
+
+    fn crate_path(&self) -> Path {
+        self.krate
+            .clone()
+            .unwrap_or_else(|| syn::parse_quote!(mimicry))
+    }
+}
+
+/// Type param of a generic function pinned to a concrete type via the `for` attr.
+/// Only calls with the pinned type are routed to the (non-generic) mock impl.
+#[derive(Debug)]
+struct PinnedType {
+    param: Ident,
+    ty: Type,
+}
+
+impl PinnedType {
+    fn new(ty: &SpannedValue<Type>, sig: &Signature) -> Result<Self, SynError> {
+        let returns_impl_trait = match &sig.output {
+            ReturnType::Type(_, ty) => contains_impl_trait(ty),
+            ReturnType::Default => false,
+        };
+        if sig.asyncness.is_some() || returns_impl_trait {
+            let message = "`for` attr is not supported for async functions \
+                and functions returning `impl Trait`";
+            return Err(SynError::new(ty.span(), message));
+        }
+
+        let mut type_params = vec![];
+        let mut has_const_params = false;
+        for param in &sig.generics.params {
+            match param {
+                GenericParam::Type(param) => type_params.push(param.ident.clone()),
+                GenericParam::Const(_) => has_const_params = true,
+                GenericParam::Lifetime(_) => { /* do nothing */ }
+            }
+        }
+        if type_params.len() != 1 || has_const_params {
+            let message = "`for` attr requires a function with a single type param \
+                and no const params";
+            return Err(SynError::new(ty.span(), message));
+        }
+        let this = Self {
+            param: type_params.pop().unwrap(),
+            ty: (**ty).clone(),
+        };
+
+        let arg_types = sig.inputs.iter().filter_map(|arg| match arg {
+            FnArg::Typed(arg) => Some(arg.ty.as_ref()),
+            FnArg::Receiver(_) => None,
+        });
+        let output = match &sig.output {
+            ReturnType::Type(_, ty) => Some(ty.as_ref()),
+            ReturnType::Default => None,
+        };
+        for checked_ty in arg_types.chain(output) {
+            if this.is_mentioned_in(checked_ty) && !Self::is_castable(checked_ty) {
+                let message = "types mentioning the pinned type param must not contain \
+                    references (other than a top-level one), lifetimes or `impl Trait`";
+                return Err(SynError::new_spanned(checked_ty, message));
+            }
+        }
+        Ok(this)
+    }
+
+    fn is_mentioned_in(&self, ty: &Type) -> bool {
+        let mut mentioned_idents = HashSet::new();
+        collect_idents(ty.to_token_stream(), &mut mentioned_idents);
+        mentioned_idents.contains(&self.param.to_string())
+    }
+
+    /// Checks whether values of the type can be cast using `Any` (i.e., the type is `'static`
+    /// or is a reference to a `'static` type, provided that the type param is `'static`).
+    fn is_castable(ty: &Type) -> bool {
+        fn has_refs_or_impl(tokens: proc_macro2::TokenStream) -> bool {
+            tokens.into_iter().any(|token| match token {
+                TokenTree::Punct(punct) => matches!(punct.as_char(), '&' | '\''),
+                TokenTree::Ident(ident) => ident == "impl",
+                TokenTree::Group(group) => has_refs_or_impl(group.stream()),
+                TokenTree::Literal(_) => false,
+            })
+        }
+
+        let ty = match ty {
+            Type::Reference(reference) => reference.elem.as_ref(),
+            _ => ty,
+        };
+        !has_refs_or_impl(ty.to_token_stream())
+    }
+
+    /// Replaces the type param with the pinned type.
+    fn substitute(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        ReplaceParam(self).visit_type_mut(&mut ty);
+        ty
+    }
+
+    /// Casts `value` from the generic type `ty` to the pinned type, or vice versa
+    /// if `to_generic` is set.
+    fn cast(
+        &self,
+        cr: &Path,
+        value: impl ToTokens,
+        ty: &Type,
+        to_generic: bool,
+    ) -> proc_macro2::TokenStream {
+        if !self.is_mentioned_in(ty) {
+            return value.into_token_stream();
+        }
+        let (cast_fn, ty) = match ty {
+            Type::Reference(reference) if reference.mutability.is_some() => {
+                (quote!(cast_pinned_mut), reference.elem.as_ref())
+            }
+            Type::Reference(reference) => (quote!(cast_pinned_ref), reference.elem.as_ref()),
+            _ => (quote!(cast_pinned), ty),
+        };
+        let pinned_ty = self.substitute(ty);
+        if to_generic {
+            quote!(#cr::#cast_fn::<#pinned_ty, #ty>(#value))
+        } else {
+            quote!(#cr::#cast_fn::<#ty, #pinned_ty>(#value))
+        }
+    }
+}
+
+struct ReplaceParam<'a>(&'a PinnedType);
+
+impl VisitMut for ReplaceParam<'_> {
+    fn visit_type_mut(&mut self, ty: &mut Type) {
+        if let Type::Path(path) = ty {
+            if path.qself.is_none() && path.path.is_ident(&self.0.param) {
+                *ty = self.0.ty.clone();
+                return;
+            }
+        }
+        visit_mut::visit_type_mut(self, ty);
+    }
 }
 
 #[derive(Debug)]
 pub struct FunctionWrapper {
+    cr: Path,
     state: Path,
+    instance: Option<String>,
+    strict: bool,
     mock_fn: Ident,
+    /// Variants of the mock impl selected via `MockVariant`.
+    variants: Vec<Ident>,
+    /// Generic params explicitly passed to the mock impl.
+    explicit_generics: Option<Vec<Ident>>,
+    /// Type param pinned to a concrete type via the `for` attr.
+    pinned_type: Option<PinnedType>,
+    /// Output type checked for mock impls of sync functions to provide better diagnostics.
+    checked_output: Option<Type>,
+    /// `'static` version of the state type for scoped mocks.
+    scoped_key: Option<Path>,
+    /// Original function emitted verbatim if mocking is disabled.
+    original: ItemFn,
     function: ItemFn,
     receiver: Option<Span>,
     arg_patterns: Vec<Pat>,
@@ -54,21 +232,73 @@ impl FunctionWrapper {
 
     fn new(attrs: FunctionAttrs, mut function: ItemFn) -> Result<Self, SynError> {
         Self::can_process(&function.sig)?;
+        let original = function.clone();
+        if let Some(path) = attrs.methods.first() {
+            let message = "`methods` attr is only supported for impl blocks";
+            return Err(SynError::new_spanned(path, message));
+        }
+        if attrs.scoped {
+            Self::check_scoped(&attrs, &function.sig)?;
+        }
 
+        let cr = attrs.crate_path();
         let mut state = attrs.using;
-        let mock_fn = Self::split_off_function(&mut state).unwrap_or_else(|| {
+        let mock_fn = if let Some(mock_fn) = Self::split_off_function(&mut state) {
             if let Some(spec) = &attrs.rename {
-                FunctionAttrs::rename(spec, &function.sig.ident)
-            } else {
-                function.sig.ident.clone()
+                let message = "`rename` cannot be used together with a function specified \
+                    in the `using` attr; remove `rename` or the function from `using`";
+                return Err(SynError::new(spec.span(), message));
             }
-        });
+            mock_fn
+        } else if let Some(spec) = &attrs.rename {
+            FunctionAttrs::rename(spec, &function.sig.ident)
+        } else {
+            function.sig.ident.clone()
+        };
+        let variants = attrs
+            .variants
+            .iter()
+            .map(|path| {
+                path.get_ident().cloned().ok_or_else(|| {
+                    let message = "mock variant must be an identifier";
+                    SynError::new_spanned(path, message)
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let pinned_type = attrs
+            .pinned_type
+            .as_ref()
+            .map(|ty| PinnedType::new(ty, &function.sig))
+            .transpose()?;
+        // The mock impl for a pinned type is not generic.
+        let explicit_generics = if pinned_type.is_some() {
+            None
+        } else {
+            Self::explicit_generics(&function.sig)
+        };
         let receiver = function.sig.inputs.first().and_then(receiver_span);
         let (arg_patterns, args) = Self::take_arg_patterns(receiver.is_some(), &mut function.sig);
+        let checked_output = Self::checked_output(&function.sig);
+        let scoped_key = if attrs.scoped {
+            let key = Self::with_lifetime(&state, &syn::parse_quote!('static))?;
+            state = Self::with_lifetime(&state, &syn::parse_quote!('_))?;
+            Some(key)
+        } else {
+            None
+        };
 
         Ok(Self {
+            cr,
             state,
+            instance: attrs.instance,
+            strict: attrs.strict,
             mock_fn,
+            variants,
+            explicit_generics,
+            pinned_type,
+            checked_output,
+            scoped_key,
+            original,
             function,
             receiver,
             arg_patterns,
@@ -76,6 +306,101 @@ impl FunctionWrapper {
         })
     }
 
+    fn check_scoped(attrs: &FunctionAttrs, sig: &Signature) -> Result<(), SynError> {
+        if let Some(async_token) = &sig.asyncness {
+            let message = "async functions cannot be mocked with scoped mock states";
+            return Err(SynError::new(async_token.span(), message));
+        }
+        if let ReturnType::Type(_, ty) = &sig.output {
+            if matches!(ty.as_ref(), Type::Never(_)) {
+                let message = "diverging functions cannot be mocked with scoped mock states";
+                return Err(SynError::new(ty.span(), message));
+            }
+        }
+
+        let unsupported_attr = if attrs.instance.is_some() {
+            Some("instance")
+        } else if !attrs.variants.is_empty() {
+            Some("variants")
+        } else if attrs.pinned_type.is_some() {
+            Some("for")
+        } else {
+            None
+        };
+        if let Some(attr) = unsupported_attr {
+            let message = format!("`{attr}` attr cannot be used together with `scoped`");
+            return Err(SynError::new(attrs.using.span(), message));
+        }
+        Ok(())
+    }
+
+    /// Specifies the lifetime arg for the scoped state path.
+    fn with_lifetime(state: &Path, lifetime: &Lifetime) -> Result<Path, SynError> {
+        let mut state = state.clone();
+        let last_segment = state.segments.last_mut().unwrap();
+        if !last_segment.arguments.is_empty() {
+            let message = "scoped mock state must be specified without generic args";
+            return Err(SynError::new_spanned(&last_segment.arguments, message));
+        }
+        last_segment.arguments = PathArguments::AngleBracketed(syn::parse_quote!(<#lifetime>));
+        Ok(state)
+    }
+
+    /// Returns type and const generic params of the function if some of them cannot be inferred
+    /// from the args or the return type (e.g., `fn size<T>() -> usize`). In this case, the params
+    /// need to be passed to the mock impl explicitly.
+    fn explicit_generics(sig: &Signature) -> Option<Vec<Ident>> {
+        let params: Vec<_> = sig
+            .generics
+            .params
+            .iter()
+            .filter_map(|param| match param {
+                GenericParam::Type(param) => Some(param.ident.clone()),
+                GenericParam::Const(param) => Some(param.ident.clone()),
+                GenericParam::Lifetime(_) => None,
+            })
+            .collect();
+
+        let mut inferred_idents = HashSet::new();
+        for arg in &sig.inputs {
+            if let FnArg::Typed(arg) = arg {
+                collect_inferred_idents(&arg.ty, &mut inferred_idents);
+            }
+        }
+        if let ReturnType::Type(_, ty) = &sig.output {
+            collect_inferred_idents(ty, &mut inferred_idents);
+        }
+
+        let has_uninferred_params = params.iter().any(|param| !inferred_idents.contains(param));
+        if has_uninferred_params {
+            Some(params)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the output type of a sync function, which is checked against the output
+    /// of the mock impl. Types containing `impl Trait` are not checked since they cannot be used
+    /// as generic args.
+    fn checked_output(sig: &Signature) -> Option<Type> {
+        if sig.asyncness.is_some() {
+            return None;
+        }
+        let ty = match &sig.output {
+            ReturnType::Default => return Some(syn::parse_quote!(())),
+            ReturnType::Type(_, ty) => ty.as_ref(),
+        };
+        if matches!(ty, Type::Never(_)) {
+            // The never type cannot be named in generic args on stable Rust.
+            return None;
+        }
+        if contains_impl_trait(ty) {
+            None
+        } else {
+            Some(ty.clone())
+        }
+    }
+
     fn split_off_function(path: &mut Path) -> Option<Ident> {
         let last_segment = path.segments.last()?.ident.to_string();
         if last_segment.starts_with(|ch: char| ch.is_ascii_uppercase()) {
@@ -98,7 +423,9 @@ impl FunctionWrapper {
             .enumerate()
             .skip(usize::from(skip_receiver));
         let iter = iter.map(|(i, arg)| {
-            let span = arg.span();
+            // Use mixed-site hygiene so that the generated idents cannot be referenced
+            // (or shadowed) by the function body.
+            let span = Span::mixed_site().located_at(arg.span());
             if let FnArg::Typed(pat_type) = arg {
                 let ident = Ident::new(&format!("__arg{i}"), span);
                 let simple_pat = Box::new(Pat::Ident(PatIdent {
@@ -135,46 +462,365 @@ impl FunctionWrapper {
         }
     }
 
-    fn routing_logic(&self) -> impl ToTokens {
+    /// Generates an expression calling the specified mock impl method.
+    /// Assumes that the `mock_ref` local is defined.
+    fn mock_output(&self, ident: &Ident) -> proc_macro2::TokenStream {
         let recv = self
             .receiver
             .as_ref()
             .map(|receiver| quote_spanned!(*receiver=> self,));
-        let args = &self.args;
+        let cr = &self.cr;
+        let args: Vec<_> = if let Some(pinned) = &self.pinned_type {
+            let arg_types = self.function.sig.inputs.iter().filter_map(|arg| match arg {
+                FnArg::Typed(arg) => Some(arg.ty.as_ref()),
+                FnArg::Receiver(_) => None,
+            });
+            self.args
+                .iter()
+                .zip(arg_types)
+                .map(|(arg, ty)| pinned.cast(cr, arg, ty, false))
+                .collect()
+        } else {
+            self.args.iter().map(ToTokens::to_token_stream).collect()
+        };
+        let args = &args;
         let state = &self.state;
-        let mock_fn = &self.mock_fn;
+        let is_async = self.function.sig.asyncness.is_some();
 
-        if self.function.sig.asyncness.is_some() {
-            quote! {
+        let method = if let Some(generics) = &self.explicit_generics {
+            quote!(#ident::<#(#generics,)*>)
+        } else {
+            quote!(#ident)
+        };
+        if is_async {
+            let fn_path = self.fn_path();
+            return quote_spanned! {Span::mixed_site()=>
+                #cr::CalledFunctionScope::new(
+                    #fn_path,
+                    <#state>::#method(mock_ref, #recv #(#args,)*),
+                ).await
+            };
+        }
+        let output = quote_spanned! {Span::mixed_site()=>
+            <#state>::#method(&*mock_ref, #recv #(#args,)*)
+        };
+        if let Some(ty) = &self.checked_output {
+            let span = Span::mixed_site().located_at(ty.span());
+            let checked_ty = self
+                .pinned_type
+                .as_ref()
+                .map_or_else(|| ty.clone(), |pinned| pinned.substitute(ty));
+            // Binding the output to a typed local keeps coercions (e.g., `&mut T` -> `&T`)
+            // and points type mismatches to the output type of the mocked function.
+            let checked_ty = quote_spanned!(span=> #checked_ty);
+            let output = quote_spanned! {Span::mixed_site()=>
                 {
-                    let instance = <#state as mimicry::Mock>::instance();
-                    let should_call_real = mimicry::GetMock::get(instance)
-                        .map_or(true, |mock_ref| mimicry::CheckRealCall::should_call_real(&*mock_ref));
-                    if !should_call_real {
-                        let mock_ref = mimicry::MockRef::<#state>::new(instance);
-                        return #state::#mock_fn(mock_ref, #recv #(#args,)*).await;
+                    let output: #checked_ty = #output;
+                    output
+                }
+            };
+            if let Some(pinned) = &self.pinned_type {
+                pinned.cast(cr, output, ty, true)
+            } else {
+                output
+            }
+        } else {
+            output
+        }
+    }
+
+    /// Generates statements dispatching a call to the mock impl (or one of its variants).
+    /// Assumes that `mock_ref` and, if there are variants, `variant` locals are defined.
+    fn mock_dispatch(&self) -> proc_macro2::TokenStream {
+        // If the function returns `!`, the mock impl call diverges, so the `return` is unreachable.
+        let is_diverging = matches!(
+            &self.function.sig.output,
+            ReturnType::Type(_, ty) if matches!(ty.as_ref(), Type::Never(_))
+        );
+        let allow_unreachable = is_diverging
+            .then(|| quote!(#[allow(unreachable_code, clippy::diverging_sub_expression)]));
+
+        let variants = self.variants.iter().map(|variant| {
+            let name = variant.to_string();
+            let method = Ident::new(&format!("{}_{name}", self.mock_fn), variant.span());
+            let output = self.mock_output(&method);
+            quote_spanned! {Span::mixed_site()=>
+                if variant == Some(#name) {
+                    #allow_unreachable
+                    return #output;
+                }
+            }
+        });
+        let output = self.mock_output(&self.mock_fn);
+        quote_spanned! {Span::mixed_site()=>
+            #(#variants)*
+            #allow_unreachable
+            return #output;
+        }
+    }
+
+    fn fn_path(&self) -> proc_macro2::TokenStream {
+        let name = &self.function.sig.ident;
+        quote!(concat!(module_path!(), "::", stringify!(#name)))
+    }
+
+    fn scoped_routing_logic(
+        &self,
+        key: &Path,
+        (trace_mock_call, trace_real_call): (proc_macro2::TokenStream, proc_macro2::TokenStream),
+        strict_check: Option<&proc_macro2::TokenStream>,
+    ) -> proc_macro2::TokenStream {
+        let cr = &self.cr;
+        let fn_path = self.fn_path();
+        // The mock impl is called within a closure, so that it receives the state
+        // with a call-local lifetime.
+        let output = self.mock_output(&self.mock_fn);
+        quote_spanned! {Span::mixed_site()=>
+            {
+                if #cr::has_active_mocks() {
+                    let should_mock = #cr::with_scoped_state::<#key, _>(|mock_ref| {
+                        !#cr::CheckRealCall::should_call_real(mock_ref)
+                    });
+                    if should_mock == Some(true) {
+                        #trace_mock_call
+                        let _called_fn = #cr::CalledFunctionGuard::new(#fn_path);
+                        return #cr::call_scoped_mock::<#key, _>(move |mock_ref| #output);
                     }
                 }
+                #trace_real_call
+                #strict_check
             }
+        }
+    }
+
+    fn routing_logic(&self) -> impl ToTokens {
+        let cr = &self.cr;
+        let state = &self.state;
+        let instance = if let Some(name) = &self.instance {
+            quote!(<#state as #cr::Mock>::instance_named(#name))
         } else {
+            quote!(<#state as #cr::Mock>::instance())
+        };
+        let fn_path = self.fn_path();
+        let trace_call = |mocked: bool| {
             quote! {
+                #cr::trace_call(#fn_path, core::any::type_name::<#state>(), #mocked);
+            }
+        };
+        let (trace_mock_call, trace_real_call) = (trace_call(true), trace_call(false));
+        let strict_check = if self.strict {
+            Some(quote!(#cr::check_strict_mode(#fn_path);))
+        } else {
+            None
+        };
+        if let Some(key) = &self.scoped_key {
+            let trace_calls = (trace_mock_call, trace_real_call);
+            return self.scoped_routing_logic(key, trace_calls, strict_check.as_ref());
+        }
+
+        let record_dispatch = |mocked: bool| {
+            quote_spanned! {Span::mixed_site()=>
+                #cr::record_dispatch::<#state, _>(instance, { fn fn_id() {} fn_id }, #mocked);
+            }
+        };
+        let (record_mock_call, record_real_call) = (record_dispatch(true), record_dispatch(false));
+        let get_variant = if self.variants.is_empty() {
+            None
+        } else {
+            Some(quote_spanned! {Span::mixed_site()=>
+                let variant = #cr::MockVariant::mock_variant(&*mock_ref);
+            })
+        };
+        let pinned_check = self.pinned_type.as_ref().map(|pinned| {
+            let (param, ty) = (&pinned.param, &pinned.ty);
+            quote!(&& #cr::is_pinned_type::<#param, #ty>())
+        });
+        let mock_dispatch = self.mock_dispatch();
+
+        // Locals in the generated code use mixed-site hygiene, so that they cannot collide
+        // with the idents in the mocked function.
+        if self.function.sig.asyncness.is_some() {
+            // The variant is determined while the state is locked, but the mock impl is called
+            // after the lock is released.
+            let (init_variant, get_variant) = if self.variants.is_empty() {
+                (None, None)
+            } else {
+                let init = quote_spanned!(Span::mixed_site()=> let mut variant = None;);
+                let get = quote_spanned! {Span::mixed_site()=>
+                    variant = #cr::MockVariant::mock_variant(&*mock_ref);
+                };
+                (Some(init), Some(get))
+            };
+            quote_spanned! {Span::mixed_site()=>
                 {
-                    let instance = <#state as mimicry::Mock>::instance();
-                    if let Some(mock_ref) = mimicry::GetMock::get(instance) {
-                        if !mimicry::CheckRealCall::should_call_real(&*mock_ref) {
-                            return #state::#mock_fn(&*mock_ref, #recv #(#args,)*);
+                    if #cr::has_active_mocks() #pinned_check {
+                        let instance = #instance;
+                        #init_variant
+                        let should_call_real = #cr::get_mock::<#state>(instance).map(|mock_ref| {
+                            #get_variant
+                            #cr::CheckRealCall::should_call_real(&*mock_ref)
+                        });
+                        if let Some(should_call_real) = should_call_real {
+                            if !should_call_real {
+                                #cr::CountCalls::record_call(instance);
+                                #record_mock_call
+                                #trace_mock_call
+                                let mock_ref = #cr::MockRef::<#state>::new(instance);
+                                #mock_dispatch
+                            }
+                            #record_real_call
                         }
                     }
+                    #trace_real_call
+                    #strict_check
+                }
+            }
+        } else {
+            quote_spanned! {Span::mixed_site()=>
+                {
+                    if #cr::has_active_mocks() #pinned_check {
+                        let instance = #instance;
+                        if let Some(mock_ref) = #cr::get_mock::<#state>(instance) {
+                            if !#cr::CheckRealCall::should_call_real(&*mock_ref) {
+                                #cr::CountCalls::record_call(instance);
+                                #record_mock_call
+                                #trace_mock_call
+                                let _called_fn = #cr::CalledFunctionGuard::new(#fn_path);
+                                #get_variant
+                                #mock_dispatch
+                            }
+                            #record_real_call
+                        }
+                    }
+                    #trace_real_call
+                    #strict_check
+                }
+            }
+        }
+    }
+}
+
+fn collect_idents(tokens: proc_macro2::TokenStream, idents: &mut HashSet<String>) {
+    for token in tokens {
+        match token {
+            TokenTree::Ident(ident) => {
+                idents.insert(ident.to_string());
+            }
+            TokenTree::Group(group) => collect_idents(group.stream(), idents),
+            TokenTree::Literal(_) | TokenTree::Punct(_) => { /* do nothing */ }
+        }
+    }
+}
+
+/// Collects idents in the type that may refer to generic params inferred from the type,
+/// e.g., `T` in `Vec<T>` or `N` in `[u8; N]`. Params mentioned only in projections
+/// (e.g., `T::Output`), or in bounds of `impl Trait` (e.g., `impl AsRef<T>`) are not inferred.
+fn collect_inferred_idents(ty: &Type, idents: &mut HashSet<Ident>) {
+    match ty {
+        Type::Array(array) => {
+            collect_inferred_idents(&array.elem, idents);
+            collect_inferred_const(&array.len, idents);
+        }
+        Type::BareFn(function) => {
+            for input in &function.inputs {
+                collect_inferred_idents(&input.ty, idents);
+            }
+            if let ReturnType::Type(_, ty) = &function.output {
+                collect_inferred_idents(ty, idents);
+            }
+        }
+        Type::Group(TypeGroup { elem, .. })
+        | Type::Paren(TypeParen { elem, .. })
+        | Type::Ptr(TypePtr { elem, .. })
+        | Type::Reference(TypeReference { elem, .. })
+        | Type::Slice(TypeSlice { elem, .. }) => collect_inferred_idents(elem, idents),
+        Type::Tuple(tuple) => {
+            for elem in &tuple.elems {
+                collect_inferred_idents(elem, idents);
+            }
+        }
+        Type::Path(path) if path.qself.is_none() => collect_inferred_from_path(&path.path, idents),
+        Type::TraitObject(object) => {
+            for bound in &object.bounds {
+                if let TypeParamBound::Trait(bound) = bound {
+                    collect_inferred_from_path(&bound.path, idents);
                 }
             }
         }
+        _ => { /* do nothing */ }
     }
 }
 
+fn collect_inferred_from_path(path: &Path, idents: &mut HashSet<Ident>) {
+    if let Some(ident) = path.get_ident() {
+        idents.insert(ident.clone());
+        return;
+    }
+    // Only generic args of the last segment are inferred; generic args of other segments
+    // (e.g., `T` in `Wrapper<T>::Assoc`) and projections (e.g., `T::Output`) are not.
+    let last_segment = match path.segments.last() {
+        Some(segment) => segment,
+        None => return,
+    };
+    match &last_segment.arguments {
+        PathArguments::AngleBracketed(args) => {
+            for arg in &args.args {
+                match arg {
+                    GenericArgument::Type(ty) => collect_inferred_idents(ty, idents),
+                    GenericArgument::Binding(binding) => {
+                        collect_inferred_idents(&binding.ty, idents);
+                    }
+                    GenericArgument::Const(expr) => collect_inferred_const(expr, idents),
+                    _ => { /* do nothing */ }
+                }
+            }
+        }
+        PathArguments::Parenthesized(args) => {
+            for input in &args.inputs {
+                collect_inferred_idents(input, idents);
+            }
+            if let ReturnType::Type(_, ty) = &args.output {
+                collect_inferred_idents(ty, idents);
+            }
+        }
+        PathArguments::None => { /* do nothing */ }
+    }
+}
+
+fn collect_inferred_const(expr: &Expr, idents: &mut HashSet<Ident>) {
+    match expr {
+        Expr::Path(path) if path.qself.is_none() => {
+            if let Some(ident) = path.path.get_ident() {
+                idents.insert(ident.clone());
+            }
+        }
+        Expr::Block(block) => {
+            if let [Stmt::Expr(expr)] = block.block.stmts.as_slice() {
+                collect_inferred_const(expr, idents);
+            }
+        }
+        _ => { /* do nothing */ }
+    }
+}
+
+/// Checks whether the type contains `impl Trait` (e.g., `Result<impl Iterator<Item = u8>, E>`).
+fn contains_impl_trait(ty: &Type) -> bool {
+    let mut mentioned_idents = HashSet::new();
+    collect_idents(ty.to_token_stream(), &mut mentioned_idents);
+    mentioned_idents.contains("impl")
+}
+
 impl ToTokens for FunctionWrapper {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         let wrapper = self.wrap(self.routing_logic());
-        tokens.extend(quote!(#wrapper));
+        let original = &self.original;
+        let cr = &self.cr;
+        tokens.extend(quote! {
+            #cr::select_mocked_item! {
+                mocked: { #wrapper }
+                original: { #original }
+            }
+        });
     }
 }
 
@@ -192,26 +838,92 @@ impl ImplWrapper {
                  `#[mock(using = \"Mock\", rename = \"mock_{}\")]";
             return Err(SynError::new(attrs.using.span(), message));
         }
+        if let Some(ty) = &attrs.pinned_type {
+            let message = "`for` attr is not supported for impl blocks; \
+                 place it on the mocked method instead";
+            return Err(SynError::new(ty.span(), message));
+        }
+
+        let mut selected_methods = attrs
+            .methods
+            .iter()
+            .map(|path| {
+                path.get_ident().cloned().ok_or_else(|| {
+                    let message = "mocked method must be an identifier";
+                    SynError::new_spanned(path, message)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let path = &attrs.using;
-        let path_string = quote!(#path).to_string();
-        let rename = attrs.rename.as_deref();
         for item in &mut block.items {
             if let syn::ImplItem::Method(method) = item {
-                if FunctionWrapper::can_process(&method.sig).is_ok()
+                let is_selected = if attrs.methods.is_empty() {
+                    true
+                } else {
+                    let len = selected_methods.len();
+                    selected_methods.retain(|ident| *ident != method.sig.ident);
+                    selected_methods.len() < len
+                };
+                if is_selected
+                    && FunctionWrapper::can_process(&method.sig).is_ok()
                     && find_meta_attrs("mock", Some("mimicry"), &method.attrs).is_none()
                 {
-                    Self::add_attr(method, &path_string, rename);
+                    // Conditional overrides (e.g., `#[cfg_attr(test, mock(..))]`) must not be
+                    // combined with the block-level attr; otherwise, the method is wrapped twice.
+                    let predicates = find_cfg_predicates("mock", Some("mimicry"), &method.attrs);
+                    Self::add_attr(method, &attrs, &predicates);
                 }
             }
         }
+
+        if let Some(ident) = selected_methods.first() {
+            let message = "method is not defined in the impl block";
+            return Err(SynError::new(ident.span(), message));
+        }
         Ok(Self { block })
     }
 
-    fn add_attr(method: &mut syn::ImplItemMethod, path_str: &str, rename: Option<&str>) {
-        let rename = rename.map(|spec| quote!(, rename = #spec));
-        method.attrs.push(syn::parse_quote! {
-            #[mimicry::mock(using = #path_str #rename)]
+    fn add_attr(
+        method: &mut syn::ImplItemMethod,
+        attrs: &FunctionAttrs,
+        override_predicates: &[NestedMeta],
+    ) {
+        let cr = attrs.crate_path();
+        let path = &attrs.using;
+        let path_string = quote!(#path).to_string();
+        let rename = attrs.rename.as_ref().map(|spec| {
+            let spec = spec.as_str();
+            quote!(, rename = #spec)
+        });
+        let scoped = if attrs.scoped {
+            Some(quote!(, scoped))
+        } else {
+            None
+        };
+        let krate = attrs.krate.as_ref().map(|krate| {
+            let krate_string = quote!(#krate).to_string();
+            quote!(, crate = #krate_string)
+        });
+        let instance = attrs
+            .instance
+            .as_ref()
+            .map(|name| quote!(, instance = #name));
+        let strict = if attrs.strict {
+            Some(quote!(, strict))
+        } else {
+            None
+        };
+        let variants = if attrs.variants.is_empty() {
+            None
+        } else {
+            let variants = attrs.variants.iter();
+            Some(quote!(, variants(#(#variants),*)))
+        };
+        let mock_attr = quote!(#cr::mock(using = #path_string #rename #krate #instance #strict #variants #scoped));
+        method.attrs.push(if override_predicates.is_empty() {
+            syn::parse_quote!(#[#mock_attr])
+        } else {
+            syn::parse_quote!(#[cfg_attr(not(any(#(#override_predicates),*)), #mock_attr)])
         });
     }
 }
@@ -253,6 +965,20 @@ pub(crate) fn wrap(attr: TokenStream, item: TokenStream) -> TokenStream {
 mod tests {
     use super::*;
 
+    fn create_attrs(using: Path) -> FunctionAttrs {
+        FunctionAttrs {
+            using,
+            rename: None,
+            krate: None,
+            instance: None,
+            strict: false,
+            variants: PathList::default(),
+            methods: PathList::default(),
+            pinned_type: None,
+            scoped: false,
+        }
+    }
+
     #[test]
     fn splitting_off_function() {
         let mut path: Path = syn::parse_quote!(TestMock);
@@ -301,10 +1027,7 @@ mod tests {
 
     #[test]
     fn simple_wrapper() {
-        let attrs = FunctionAttrs {
-            using: syn::parse_quote!(TestMock),
-            rename: None,
-        };
+        let attrs = create_attrs(syn::parse_quote!(TestMock));
         let function: ItemFn = syn::parse_quote! {
             fn test(
                 mut this: Vec<u8>,
@@ -330,11 +1053,109 @@ mod tests {
     }
 
     #[test]
-    fn error_on_const_fn() {
+    fn wrapper_preserving_outer_attrs() {
+        let attrs = create_attrs(syn::parse_quote!(TestMock));
+        let function: ItemFn = syn::parse_quote! {
+            /// Documentation.
+            #[must_use = "answer is important"]
+            #[inline]
+            #[deprecated]
+            /// More documentation.
+            pub fn test(x: u8) -> u8 {
+                x
+            }
+        };
+        let wrapper = FunctionWrapper::new(attrs, function).unwrap();
+        let wrapper = wrapper.wrap(quote!());
+        let wrapper: ItemFn = syn::parse_quote!(#wrapper);
+
+        let expected: ItemFn = syn::parse_quote! {
+            /// Documentation.
+            #[must_use = "answer is important"]
+            #[inline]
+            #[deprecated]
+            /// More documentation.
+            pub fn test(__arg0: u8) -> u8 {
+                let (x,) = (__arg0,);
+                x
+            }
+        };
+        assert_eq!(wrapper, expected, "{}", quote!(#wrapper));
+    }
+
+    #[test]
+    fn detecting_explicit_generics() {
+        let inferred_signatures: [Signature; 6] = [
+            syn::parse_quote!(fn test<T: AsRef<str>>(value: T) -> usize),
+            syn::parse_quote!(fn fill<'a, const N: usize>(buf: &'a mut [u8; N])),
+            syn::parse_quote!(fn zeros<const N: usize>() -> [u8; N]),
+            syn::parse_quote!(fn test<T>(value: impl AsRef<str>) -> Option<Vec<T>>),
+            syn::parse_quote!(fn test<T, U>(map: &dyn Fn(T) -> U) -> Box<dyn Iterator<Item = U>>),
+            syn::parse_quote!(fn test<const N: usize>(value: Array<{ N }>) -> (fn(), *const u8)),
+        ];
+        for signature in &inferred_signatures {
+            let generics = FunctionWrapper::explicit_generics(signature);
+            assert!(generics.is_none(), "{generics:?}");
+        }
+
+        let signature: Signature =
+            syn::parse_quote!(fn test<'a, T, const N: usize>(value: &'a T) -> usize);
+        let generics = FunctionWrapper::explicit_generics(&signature).unwrap();
+        let generics: Vec<_> = generics.iter().map(ToString::to_string).collect();
+        assert_eq!(generics, ["T", "N"]);
+
+        let explicit_signatures: [Signature; 5] = [
+            syn::parse_quote!(fn test<T: Add>(x: u32) -> T::Output),
+            syn::parse_quote!(fn test<T: Add>(x: u32) -> Vec<<T as Add>::Output>),
+            syn::parse_quote!(fn test<T>(x: Wrapper<T>::Assoc) -> usize),
+            syn::parse_quote!(fn test<T>(value: impl AsRef<T>) -> usize),
+            syn::parse_quote!(fn test<const N: usize>(value: impl AsRef<str>) -> usize),
+        ];
+        for signature in &explicit_signatures {
+            let generics = FunctionWrapper::explicit_generics(signature);
+            assert!(generics.is_some(), "{}", quote!(#signature));
+        }
+    }
+
+    #[test]
+    fn detecting_checked_output() {
+        let signature: Signature = syn::parse_quote!(fn test(&self) -> Option<&str>);
+        let ty = FunctionWrapper::checked_output(&signature).unwrap();
+        assert_eq!(ty, syn::parse_quote!(Option<&str>));
+        let signature: Signature = syn::parse_quote!(fn test(value: u8));
+        let ty = FunctionWrapper::checked_output(&signature).unwrap();
+        assert_eq!(ty, syn::parse_quote!(()));
+
+        let unchecked_signatures: [Signature; 4] = [
+            syn::parse_quote!(async fn test() -> usize),
+            syn::parse_quote!(fn test(code: i32) -> !),
+            syn::parse_quote!(fn test() -> impl Iterator<Item = u8>),
+            syn::parse_quote!(fn test() -> Vec<impl Display>),
+        ];
+        for signature in &unchecked_signatures {
+            let ty = FunctionWrapper::checked_output(signature);
+            assert!(ty.is_none(), "{ty:?}");
+        }
+    }
+
+    #[test]
+    fn error_on_function_spec_with_rename() {
         let attrs = FunctionAttrs {
-            using: syn::parse_quote!(TestMock),
-            rename: None,
+            rename: Some(SpannedValue::new("mock_{}".to_owned(), Span::call_site())),
+            ..create_attrs(syn::parse_quote!(TestMock::test))
+        };
+        let function: ItemFn = syn::parse_quote! {
+            fn test(x: u8) -> u8 { x }
         };
+        let err = FunctionWrapper::new(attrs, function)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("`rename` cannot be used"), "{err}");
+    }
+
+    #[test]
+    fn error_on_const_fn() {
+        let attrs = create_attrs(syn::parse_quote!(TestMock));
         let function: ItemFn = syn::parse_quote! {
             const fn test(x: u8, y: u8) -> u8 { x + y }
         };
@@ -345,14 +1166,111 @@ mod tests {
         assert!(err.contains("const functions"), "{err}");
     }
 
+    #[test]
+    fn casting_args_for_pinned_type() {
+        let sig: Signature = syn::parse_quote! {
+            fn test<'a, T: 'static>(x: &'a mut Vec<T>, y: T, z: u8) -> Option<T>
+        };
+        let ty = SpannedValue::new(syn::parse_quote!(u32), Span::call_site());
+        let pinned = PinnedType::new(&ty, &sig).unwrap();
+        let cr: Path = syn::parse_quote!(mimicry);
+
+        let arg_types: Vec<Type> = vec![
+            syn::parse_quote!(&'a mut Vec<T>),
+            syn::parse_quote!(T),
+            syn::parse_quote!(u8),
+        ];
+        let casts: Vec<syn::Expr> = arg_types
+            .iter()
+            .map(|ty| {
+                let cast = pinned.cast(&cr, quote!(arg), ty, false);
+                syn::parse_quote!(#cast)
+            })
+            .collect();
+        let expected: [syn::Expr; 3] = [
+            syn::parse_quote!(mimicry::cast_pinned_mut::<Vec<T>, Vec<u32>>(arg)),
+            syn::parse_quote!(mimicry::cast_pinned::<T, u32>(arg)),
+            syn::parse_quote!(arg),
+        ];
+        assert_eq!(casts, expected);
+
+        let output: Type = syn::parse_quote!(Option<T>);
+        let output_cast = pinned.cast(&cr, quote!(output), &output, true);
+        let output_cast: syn::Expr = syn::parse_quote!(#output_cast);
+        let expected: syn::Expr =
+            syn::parse_quote!(mimicry::cast_pinned::<Option<u32>, Option<T>>(output));
+        assert_eq!(output_cast, expected);
+    }
+
+    #[test]
+    fn errors_on_pinned_type() {
+        let ty = SpannedValue::new(syn::parse_quote!(u32), Span::call_site());
+        let signatures: [(Signature, &str); 5] = [
+            (syn::parse_quote!(fn test(x: u8) -> u8), "single type param"),
+            (
+                syn::parse_quote!(fn test<T, U>(x: T, y: U)),
+                "single type param",
+            ),
+            (
+                syn::parse_quote!(fn test<T, const N: usize>(x: [T; N])),
+                "no const params",
+            ),
+            (syn::parse_quote!(async fn test<T>(x: T)), "async functions"),
+            (
+                syn::parse_quote!(fn test<'a, T>(x: Option<&'a T>)),
+                "must not contain references",
+            ),
+        ];
+
+        for (sig, expected_err) in &signatures {
+            let err = PinnedType::new(&ty, sig).unwrap_err().to_string();
+            assert!(err.contains(expected_err), "{err}");
+        }
+    }
+
     #[test]
     fn defining_routing_logic() {
+        let attrs = create_attrs(syn::parse_quote!(TestMock));
+        let function: ItemFn = syn::parse_quote! {
+            fn test(x: u8, y: u8) -> u16 { x + y }
+        };
+        let wrapper = FunctionWrapper::new(attrs, function).unwrap();
+        let routing_logic = wrapper.routing_logic();
+        let routing_logic: syn::Block = syn::parse_quote!({ #routing_logic });
+
+        #[rustfmt::skip] // formatting removes the necessary trailing comma
+        let expected: syn::Block = syn::parse_quote!({
+            {
+                if mimicry::has_active_mocks() {
+                    let instance = <TestMock as mimicry::Mock>::instance();
+                    if let Some(mock_ref) = mimicry::get_mock::<TestMock>(instance) {
+                        if !mimicry::CheckRealCall::should_call_real(&*mock_ref) {
+                            mimicry::CountCalls::record_call(instance);
+                            mimicry::record_dispatch::<TestMock, _>(instance, { fn fn_id() {} fn_id }, true);
+                            mimicry::trace_call(concat!(module_path!(), "::", stringify!(test)), core::any::type_name::<TestMock>(), true);
+                            let _called_fn = mimicry::CalledFunctionGuard::new(concat!(module_path!(), "::", stringify!(test)));
+                            return {
+                                let output: u16 = <TestMock>::test(&*mock_ref, __arg0, __arg1,);
+                                output
+                            };
+                        }
+                        mimicry::record_dispatch::<TestMock, _>(instance, { fn fn_id() {} fn_id }, false);
+                    }
+                }
+                mimicry::trace_call(concat!(module_path!(), "::", stringify!(test)), core::any::type_name::<TestMock>(), false);
+            }
+        });
+        assert_eq!(routing_logic, expected, "{}", quote!(#routing_logic));
+    }
+
+    #[test]
+    fn routing_logic_with_variants() {
         let attrs = FunctionAttrs {
-            using: syn::parse_quote!(TestMock),
-            rename: None,
+            variants: PathList::new::<Path>(vec![syn::parse_quote!(slow)]),
+            ..create_attrs(syn::parse_quote!(TestMock))
         };
         let function: ItemFn = syn::parse_quote! {
-            fn test(x: u8, y: u8) -> u16 { x + y }
+            fn test(x: u8) -> u8 { x }
         };
         let wrapper = FunctionWrapper::new(attrs, function).unwrap();
         let routing_logic = wrapper.routing_logic();
@@ -361,23 +1279,231 @@ mod tests {
         #[rustfmt::skip] // formatting removes the necessary trailing comma
         let expected: syn::Block = syn::parse_quote!({
             {
-                let instance = <TestMock as mimicry::Mock>::instance();
-                if let Some(mock_ref) = mimicry::GetMock::get(instance) {
-                    if !mimicry::CheckRealCall::should_call_real(&*mock_ref) {
-                        return TestMock::test(&*mock_ref, __arg0, __arg1,);
+                if mimicry::has_active_mocks() {
+                    let instance = <TestMock as mimicry::Mock>::instance();
+                    if let Some(mock_ref) = mimicry::get_mock::<TestMock>(instance) {
+                        if !mimicry::CheckRealCall::should_call_real(&*mock_ref) {
+                            mimicry::CountCalls::record_call(instance);
+                            mimicry::record_dispatch::<TestMock, _>(instance, { fn fn_id() {} fn_id }, true);
+                            mimicry::trace_call(concat!(module_path!(), "::", stringify!(test)), core::any::type_name::<TestMock>(), true);
+                            let _called_fn = mimicry::CalledFunctionGuard::new(concat!(module_path!(), "::", stringify!(test)));
+                            let variant = mimicry::MockVariant::mock_variant(&*mock_ref);
+                            if variant == Some("slow") {
+                                return {
+                                    let output: u8 = <TestMock>::test_slow(&*mock_ref, __arg0,);
+                                    output
+                                };
+                            }
+                            return {
+                                let output: u8 = <TestMock>::test(&*mock_ref, __arg0,);
+                                output
+                            };
+                        }
+                        mimicry::record_dispatch::<TestMock, _>(instance, { fn fn_id() {} fn_id }, false);
                     }
                 }
+                mimicry::trace_call(concat!(module_path!(), "::", stringify!(test)), core::any::type_name::<TestMock>(), false);
             }
         });
         assert_eq!(routing_logic, expected, "{}", quote!(#routing_logic));
     }
 
     #[test]
-    fn wrapping_impl_block() {
+    fn routing_logic_with_custom_crate() {
         let attrs = FunctionAttrs {
-            using: syn::parse_quote!(TestMock),
-            rename: None,
+            krate: Some(syn::parse_quote!(test_utils::mimicry)),
+            ..create_attrs(syn::parse_quote!(TestMock))
+        };
+        let function: ItemFn = syn::parse_quote! {
+            fn test(x: u8) -> u8 { x }
+        };
+        let wrapper = FunctionWrapper::new(attrs, function).unwrap();
+        let routing_logic = wrapper.routing_logic();
+        let routing_logic: syn::Block = syn::parse_quote!({ #routing_logic });
+
+        #[rustfmt::skip] // formatting removes the necessary trailing comma
+        let expected: syn::Block = syn::parse_quote!({
+            {
+                if test_utils::mimicry::has_active_mocks() {
+                    let instance = <TestMock as test_utils::mimicry::Mock>::instance();
+                    if let Some(mock_ref) = test_utils::mimicry::get_mock::<TestMock>(instance) {
+                        if !test_utils::mimicry::CheckRealCall::should_call_real(&*mock_ref) {
+                            test_utils::mimicry::CountCalls::record_call(instance);
+                            test_utils::mimicry::record_dispatch::<TestMock, _>(instance, { fn fn_id() {} fn_id }, true);
+                            test_utils::mimicry::trace_call(concat!(module_path!(), "::", stringify!(test)), core::any::type_name::<TestMock>(), true);
+                            let _called_fn = test_utils::mimicry::CalledFunctionGuard::new(concat!(module_path!(), "::", stringify!(test)));
+                            return {
+                                let output: u8 = <TestMock>::test(&*mock_ref, __arg0,);
+                                output
+                            };
+                        }
+                        test_utils::mimicry::record_dispatch::<TestMock, _>(instance, { fn fn_id() {} fn_id }, false);
+                    }
+                }
+                test_utils::mimicry::trace_call(concat!(module_path!(), "::", stringify!(test)), core::any::type_name::<TestMock>(), false);
+            }
+        });
+        assert_eq!(routing_logic, expected, "{}", quote!(#routing_logic));
+    }
+
+    #[test]
+    fn routing_logic_with_named_instance() {
+        let attrs = FunctionAttrs {
+            instance: Some("test".to_owned()),
+            ..create_attrs(syn::parse_quote!(TestMock))
+        };
+        let function: ItemFn = syn::parse_quote! {
+            fn test(x: u8) -> u8 { x }
+        };
+        let wrapper = FunctionWrapper::new(attrs, function).unwrap();
+        let routing_logic = wrapper.routing_logic();
+        let routing_logic: syn::Block = syn::parse_quote!({ #routing_logic });
+
+        #[rustfmt::skip] // formatting removes the necessary trailing comma
+        let expected: syn::Block = syn::parse_quote!({
+            {
+                if mimicry::has_active_mocks() {
+                    let instance = <TestMock as mimicry::Mock>::instance_named("test");
+                    if let Some(mock_ref) = mimicry::get_mock::<TestMock>(instance) {
+                        if !mimicry::CheckRealCall::should_call_real(&*mock_ref) {
+                            mimicry::CountCalls::record_call(instance);
+                            mimicry::record_dispatch::<TestMock, _>(instance, { fn fn_id() {} fn_id }, true);
+                            mimicry::trace_call(concat!(module_path!(), "::", stringify!(test)), core::any::type_name::<TestMock>(), true);
+                            let _called_fn = mimicry::CalledFunctionGuard::new(concat!(module_path!(), "::", stringify!(test)));
+                            return {
+                                let output: u8 = <TestMock>::test(&*mock_ref, __arg0,);
+                                output
+                            };
+                        }
+                        mimicry::record_dispatch::<TestMock, _>(instance, { fn fn_id() {} fn_id }, false);
+                    }
+                }
+                mimicry::trace_call(concat!(module_path!(), "::", stringify!(test)), core::any::type_name::<TestMock>(), false);
+            }
+        });
+        assert_eq!(routing_logic, expected, "{}", quote!(#routing_logic));
+    }
+
+    #[test]
+    fn routing_logic_with_strict_check() {
+        let attrs = FunctionAttrs {
+            strict: true,
+            ..create_attrs(syn::parse_quote!(TestMock))
+        };
+        let function: ItemFn = syn::parse_quote! {
+            fn test(x: u8) -> u8 { x }
+        };
+        let wrapper = FunctionWrapper::new(attrs, function).unwrap();
+        let routing_logic = wrapper.routing_logic();
+        let routing_logic: syn::Block = syn::parse_quote!({ #routing_logic });
+
+        #[rustfmt::skip] // formatting removes the necessary trailing comma
+        let expected: syn::Block = syn::parse_quote!({
+            {
+                if mimicry::has_active_mocks() {
+                    let instance = <TestMock as mimicry::Mock>::instance();
+                    if let Some(mock_ref) = mimicry::get_mock::<TestMock>(instance) {
+                        if !mimicry::CheckRealCall::should_call_real(&*mock_ref) {
+                            mimicry::CountCalls::record_call(instance);
+                            mimicry::record_dispatch::<TestMock, _>(instance, { fn fn_id() {} fn_id }, true);
+                            mimicry::trace_call(concat!(module_path!(), "::", stringify!(test)), core::any::type_name::<TestMock>(), true);
+                            let _called_fn = mimicry::CalledFunctionGuard::new(concat!(module_path!(), "::", stringify!(test)));
+                            return {
+                                let output: u8 = <TestMock>::test(&*mock_ref, __arg0,);
+                                output
+                            };
+                        }
+                        mimicry::record_dispatch::<TestMock, _>(instance, { fn fn_id() {} fn_id }, false);
+                    }
+                }
+                mimicry::trace_call(concat!(module_path!(), "::", stringify!(test)), core::any::type_name::<TestMock>(), false);
+                mimicry::check_strict_mode(concat!(module_path!(), "::", stringify!(test)));
+            }
+        });
+        assert_eq!(routing_logic, expected, "{}", quote!(#routing_logic));
+    }
+
+    #[test]
+    fn routing_logic_with_scoped_state() {
+        let attrs = FunctionAttrs {
+            scoped: true,
+            ..create_attrs(syn::parse_quote!(TestMock))
+        };
+        let function: ItemFn = syn::parse_quote! {
+            fn test(x: u8) -> u8 { x }
+        };
+        let wrapper = FunctionWrapper::new(attrs, function).unwrap();
+        let routing_logic = wrapper.routing_logic();
+        let routing_logic: syn::Block = syn::parse_quote!({ #routing_logic });
+
+        #[rustfmt::skip] // formatting removes the necessary trailing comma
+        let expected: syn::Block = syn::parse_quote!({
+            {
+                if mimicry::has_active_mocks() {
+                    let should_mock = mimicry::with_scoped_state::<TestMock<'static>, _>(|mock_ref| {
+                        !mimicry::CheckRealCall::should_call_real(mock_ref)
+                    });
+                    if should_mock == Some(true) {
+                        mimicry::trace_call(concat!(module_path!(), "::", stringify!(test)), core::any::type_name::<TestMock<'_> >(), true);
+                        let _called_fn = mimicry::CalledFunctionGuard::new(concat!(module_path!(), "::", stringify!(test)));
+                        return mimicry::call_scoped_mock::<TestMock<'static>, _>(move |mock_ref| {
+                            let output: u8 = <TestMock<'_> >::test(&*mock_ref, __arg0,);
+                            output
+                        });
+                    }
+                }
+                mimicry::trace_call(concat!(module_path!(), "::", stringify!(test)), core::any::type_name::<TestMock<'_> >(), false);
+            }
+        });
+        assert_eq!(routing_logic, expected, "{}", quote!(#routing_logic));
+    }
+
+    #[test]
+    fn errors_on_scoped_state() {
+        let attrs = FunctionAttrs {
+            scoped: true,
+            ..create_attrs(syn::parse_quote!(TestMock<'a>))
+        };
+        let function: ItemFn = syn::parse_quote!(
+            fn test(x: u8) -> u8 {
+                x
+            }
+        );
+        let err = FunctionWrapper::new(attrs, function).unwrap_err();
+        let err = err.to_string();
+        assert!(err.contains("without generic args"), "{err}");
+
+        let attrs = FunctionAttrs {
+            scoped: true,
+            instance: Some("test".to_owned()),
+            ..create_attrs(syn::parse_quote!(TestMock))
+        };
+        let function: ItemFn = syn::parse_quote!(
+            fn test(x: u8) -> u8 {
+                x
+            }
+        );
+        let err = FunctionWrapper::new(attrs, function).unwrap_err();
+        let err = err.to_string();
+        assert!(err.contains("`instance` attr"), "{err}");
+
+        let attrs = FunctionAttrs {
+            scoped: true,
+            ..create_attrs(syn::parse_quote!(TestMock))
         };
+        let function: ItemFn = syn::parse_quote!(
+            fn test(code: i32) -> ! {
+                loop {}
+            }
+        );
+        let err = FunctionWrapper::new(attrs, function).unwrap_err();
+        let err = err.to_string();
+        assert!(err.contains("diverging functions"), "{err}");
+    }
+
+    #[test]
+    fn wrapping_impl_block() {
+        let attrs = create_attrs(syn::parse_quote!(TestMock));
         let block: ItemImpl = syn::parse_quote! {
             impl Test {
                 const CONST: usize = 0;
@@ -405,11 +1531,58 @@ mod tests {
     }
 
     #[test]
-    fn wrapping_impl_block_errors() {
+    fn wrapping_impl_block_with_cfg_gated_methods() {
+        let attrs = create_attrs(syn::parse_quote!(TestMock));
+        let block: ItemImpl = syn::parse_quote! {
+            impl Test {
+                #[cfg(feature = "test")]
+                fn test(&self) -> usize { 0 }
+
+                #[cfg_attr(feature = "test", mock(using = "OtherMock"))]
+                fn other() -> String { String::new() }
+            }
+        };
+
+        let wrapper = ImplWrapper::new(attrs, block).unwrap();
+        let expected: ItemImpl = syn::parse_quote! {
+            impl Test {
+                #[cfg(feature = "test")]
+                #[mimicry::mock(using = "TestMock")]
+                fn test(&self) -> usize { 0 }
+
+                #[cfg_attr(feature = "test", mock(using = "OtherMock"))]
+                #[cfg_attr(not(any(feature = "test")), mimicry::mock(using = "TestMock"))]
+                fn other() -> String { String::new() }
+            }
+        };
+        assert_eq!(wrapper.block, expected, "{}", quote!(#wrapper));
+    }
+
+    #[test]
+    fn wrapping_impl_block_with_custom_crate() {
         let attrs = FunctionAttrs {
-            using: syn::parse_quote!(TestMock::test),
-            rename: None,
+            krate: Some(syn::parse_quote!(test_utils::mimicry)),
+            ..create_attrs(syn::parse_quote!(TestMock))
         };
+        let block: ItemImpl = syn::parse_quote! {
+            impl Test {
+                fn test(&self) -> usize { 0 }
+            }
+        };
+
+        let wrapper = ImplWrapper::new(attrs, block).unwrap();
+        let expected: ItemImpl = syn::parse_quote! {
+            impl Test {
+                #[test_utils::mimicry::mock(using = "TestMock", crate = "test_utils :: mimicry")]
+                fn test(&self) -> usize { 0 }
+            }
+        };
+        assert_eq!(wrapper.block, expected, "{}", quote!(#wrapper));
+    }
+
+    #[test]
+    fn wrapping_impl_block_errors() {
+        let attrs = create_attrs(syn::parse_quote!(TestMock::test));
         let block: ItemImpl = syn::parse_quote! {
             impl Test {
                 fn test(&self) -> usize { Self::CONST }
@@ -421,5 +1594,41 @@ mod tests {
             err.contains("function specification is not supported"),
             "{err}"
         );
+
+        let attrs = FunctionAttrs {
+            methods: PathList::new::<Path>(vec![syn::parse_quote!(other)]),
+            ..create_attrs(syn::parse_quote!(TestMock))
+        };
+        let block: ItemImpl = syn::parse_quote! {
+            impl Test {
+                fn test(&self) -> usize { Self::CONST }
+            }
+        };
+        let err = ImplWrapper::new(attrs, block).unwrap_err().to_string();
+        assert!(err.contains("method is not defined"), "{err}");
+    }
+
+    #[test]
+    fn wrapping_selected_methods_in_impl_block() {
+        let attrs = FunctionAttrs {
+            methods: PathList::new::<Path>(vec![syn::parse_quote!(handle)]),
+            ..create_attrs(syn::parse_quote!(TestMock))
+        };
+        let block: ItemImpl = syn::parse_quote! {
+            impl Handler for Test {
+                fn handle(&self, value: u8) -> u8 { value }
+                fn name(&self) -> &str { "test" }
+            }
+        };
+
+        let wrapper = ImplWrapper::new(attrs, block).unwrap();
+        let expected: ItemImpl = syn::parse_quote! {
+            impl Handler for Test {
+                #[mimicry::mock(using = "TestMock")]
+                fn handle(&self, value: u8) -> u8 { value }
+                fn name(&self) -> &str { "test" }
+            }
+        };
+        assert_eq!(wrapper.block, expected, "{}", quote!(#wrapper));
     }
 }
@@ -11,6 +11,8 @@
 #![warn(missing_debug_implementations, missing_docs, bare_trait_objects)]
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(clippy::must_use_candidate, clippy::module_name_repetitions)]
+// False positive in `darling`-generated code.
+#![allow(clippy::manual_unwrap_or_default)]
 
 extern crate proc_macro;
 
@@ -23,6 +25,10 @@ use proc_macro::TokenStream;
 
 /// Derives the `Mock` trait for a type, allowing to use it as a state for mocking.
 ///
+/// The trait can be derived both for structs and enums. Since [`CallReal`](macro@CallReal)
+/// can only be derived for structs, enum states that need partial mocking / spying
+/// should use the `mut` attribute described below.
+///
 /// # Container attributes
 ///
 /// Container attributes are placed in a `#[mock(...)]` attribute on a struct / enum.
@@ -33,18 +39,154 @@ use proc_macro::TokenStream;
 /// the [`ThreadLocal`] wrapper is used. Can be specified as `#[mock(shared)]` or
 /// `#[mock(shared = true)]`.
 ///
+/// Alternatively, `#[mock(shared = "rw")]` signals to use the [`SharedRw`] wrapper,
+/// which allows concurrent access to the state. This option cannot be used together
+/// with `mut`.
+///
+/// ## `global`
+///
+/// Signals to use the [`Global`] wrapper for the mock state. Like [`Shared`], this wrapper
+/// shares the state across threads, but it does not support recursive mock calls (they will
+/// deadlock), including delegation to real impls. Can be specified as `#[mock(global)]`
+/// or `#[mock(global = true)]`. Cannot be used together with `shared`.
+///
 /// ## `mut`
 ///
 /// Signals to use the [`Mut`] wrapper for the mock state. With this flag set, mock methods
 /// will receive `&Mut<Self>` as the first arg instead of `&self`.
 ///
+/// [`Mut`] contains its own [`RealCallSwitch`] and implements `CallReal`, so partial mocking /
+/// spying is performed by calling `CallReal` methods on the wrapper (e.g., `this.call_real()`).
+/// Thus, the state does not need a [`RealCallSwitch`] field, and [`CallReal`](macro@CallReal)
+/// cannot be derived for it.
+///
+/// ## `auto_check`
+///
+/// Implements the [`CheckRealCall`] trait for the state, so that the mock impls are always
+/// used. This is useful for states that do not use partial mocking / spying and thus
+/// do not have a [`RealCallSwitch`] field. Can be specified as `#[mock(auto_check)]`
+/// or `#[mock(auto_check = true)]`.
+///
+/// This attribute cannot be used together with `mut` (the [`Mut`] wrapper
+/// already implements [`CheckRealCall`]), or if the state has a [`RealCallSwitch`] field
+/// (in this case, derive `CallReal` instead).
+///
+/// ## `scoped`
+///
+/// Flag specifying that the mock state has a lifetime param and is set via
+/// `mimicry::with_scoped_mock()` (see the `Mock` derive macro docs). The mock impl receives
+/// the state via a reference with a call-local lifetime, so it must be generic over
+/// the state lifetime, e.g., be defined in `impl SpyMock<'_>`. `async` and diverging functions
+/// are not supported; `instance`, `variants` and `for` attrs cannot be used together with `scoped`.
+///
+/// ```
+/// # use mimicry::{mock, Mock};
+/// # use std::sync::Mutex;
+/// #[mock(using = "SpyMock", scoped)]
+/// fn answer(value: u32) -> u32 {
+///     value * 2
+/// }
+///
+/// #[derive(Mock)]
+/// #[mock(auto_check)]
+/// struct SpyMock<'a> {
+///     calls: &'a Mutex<Vec<u32>>,
+/// }
+///
+/// impl SpyMock<'_> {
+///     fn answer(&self, value: u32) -> u32 {
+///         self.calls.lock().unwrap().push(value);
+///         value
+///     }
+/// }
+///
+/// let calls = Mutex::default();
+/// let (output, _) = mimicry::with_scoped_mock(SpyMock { calls: &calls }, || answer(5));
+/// assert_eq!(output, 5);
+/// assert_eq!(*calls.lock().unwrap(), [5]);
+/// ```
+///
+/// ## `crate`
+///
+/// Specifies a [path] to the `mimicry` crate used in the generated code, such as
+/// `#[mock(crate = "test_utils::mimicry")]`. This is useful if `mimicry` is re-exported
+/// from another crate. By default, the path is `mimicry`.
+///
+/// ## `reset`
+///
+/// Generates an inherent `reset(&mut self)` method for the state, which resets
+/// all fields to their default values. This is useful for multi-phase tests, e.g.,
+/// `guard.with(|state| state.reset())`. Can be specified as `#[mock(reset)]`
+/// or `#[mock(reset = true)]`. Only supported for structs; all struct fields must implement
+/// the `Default` trait.
+///
+/// ## `count`
+///
+/// Generates an associated `call_count() -> usize` function for the state, which returns
+/// the number of calls dispatched to the mock impls since the current state was set
+/// (or 0 if the state is not set). Calls delegated to real implementations are not counted.
+/// This is mostly useful in mock impls, which do not have access to the guard.
+/// Can be specified as `#[mock(count)]` or `#[mock(count = true)]`.
+///
+/// The function only considers the default instance of the state (i.e., not named instances),
+/// and only the innermost of stacked thread-local states; use `MockGuard::call_count()`
+/// for other cases. For thread-local mocks, only calls on the current thread are counted.
+///
+/// ## `stats`
+///
+/// Enables collecting [`DispatchStats`], i.e., per-function numbers of calls dispatched
+/// to the mock impls or delegated to the real impls while the state is set. Generates
+/// an associated `dispatch_stats() -> DispatchStats` function for the state, which returns
+/// a snapshot of the statistics since the current state was set. Can be specified
+/// as `#[mock(stats)]` or `#[mock(stats = true)]`. Similar to `count`, the function
+/// only considers the current state of the default instance; use `MockGuard::dispatch_stats()`
+/// for other cases.
+///
+/// ## `debug`
+///
+/// Implements the `Debug` trait for the state, formatting the fields that implement `Debug`
+/// and eliding the rest (e.g., boxed closures). This is useful if `#[derive(Debug)]`
+/// cannot be used because of such fields. Elided named fields are indicated by `..`
+/// in the output, and elided unnamed fields are replaced with `_`. Can be specified
+/// as `#[mock(debug)]` or `#[mock(debug = true)]`. Only supported for structs.
+///
+/// Whether a field implements `Debug` is determined for the field type in the struct
+/// definition, so fields with a type param as their type are always elided.
+///
+/// # Generics
+///
+/// Mock states may have type params. The state is placed in a `static` while the mock is set,
+/// so it cannot borrow test fixtures; consider sharing them via [`Arc`] instead.
+///
+/// Alternatively, a state may have a single lifetime param (and no other generic params).
+/// In this case, the macro implements `ScopedMock` instead of `Mock` for the state.
+/// Such a state is set for the current thread for the duration of a closure using
+/// `mimicry::with_scoped_mock()`, and functions using it must specify the `scoped` attr
+/// of the `mock` macro. The state must be covariant in its lifetime param; e.g.,
+/// `&'a Mutex<Vec<u32>>` fields are fine, but `Cell<&'a u32>` ones are not. Scoped states
+/// cannot be used with `shared`, `global`, `mut`, `count` or `stats` attrs.
+/// Since `ScopedMock` is an unsafe trait, the generated code contains an `unsafe impl`,
+/// and thus cannot be used in modules with `#![forbid(unsafe_code)]`.
+///
+/// The state must be [`Send`] (and, for shared states, [`Sync`]), so the generated `Mock` impl
+/// is bounded accordingly; type params are not required to declare these bounds themselves.
+/// If a type param is only used as a marker, consider using `PhantomData<fn() -> T>`
+/// rather than `PhantomData<T>`; the former is `Send + Sync` regardless of `T`.
+///
 /// # Examples
 ///
 /// See [`ThreadLocal`] and [`Shared`] docs for examples of usage.
 ///
 /// [`Shared`]: https://docs.rs/mimicry/latest/mimicry/struct.Shared.html
+/// [`Global`]: https://docs.rs/mimicry/latest/mimicry/struct.Global.html
+/// [`SharedRw`]: https://docs.rs/mimicry/latest/mimicry/struct.SharedRw.html
 /// [`ThreadLocal`]: https://docs.rs/mimicry/latest/mimicry/struct.ThreadLocal.html
 /// [`Mut`]: https://docs.rs/mimicry/latest/mimicry/struct.Mut.html
+/// [`DispatchStats`]: https://docs.rs/mimicry/latest/mimicry/struct.DispatchStats.html
+/// [`Arc`]: https://doc.rust-lang.org/std/sync/struct.Arc.html
+/// [path]: https://docs.rs/syn/latest/syn/struct.Path.html
+/// [`CheckRealCall`]: https://docs.rs/mimicry/latest/mimicry/trait.CheckRealCall.html
+/// [`RealCallSwitch`]: https://docs.rs/mimicry/latest/mimicry/struct.RealCallSwitch.html
 #[proc_macro_derive(Mock, attributes(mock))]
 pub fn mock_derive(input: TokenStream) -> TokenStream {
     mock_impl::impl_mock(input)
@@ -53,9 +195,22 @@ pub fn mock_derive(input: TokenStream) -> TokenStream {
 /// Derives the `CallReal` trait for a struct allowing to switch to real implementations
 /// for partial mocking or spying.
 ///
+/// The trait cannot be derived for states with the `mut` attribute: the [`Mut`] wrapper
+/// already implements `CallReal` for such states.
+///
+/// # Container attributes
+///
+/// Container attributes are placed in a `#[mock(...)]` attribute on a struct. Attributes
+/// not listed here (e.g., ones for the `Mock` derive macro) are ignored.
+///
+/// ## `crate`
+///
+/// Specifies a path to the `mimicry` crate, similar to the same attribute
+/// for the `Mock` derive macro.
+///
 /// # Field attributes
 ///
-/// Field attributes are placed in a `#[mock(...)]` attribute on a struct / enum.
+/// Field attributes are placed in a `#[mock(...)]` attribute on a struct field.
 ///
 /// ## `switch`
 ///
@@ -63,6 +218,7 @@ pub fn mock_derive(input: TokenStream) -> TokenStream {
 /// by the field type, so an explicit declaration is reserved for extraordinary cases.
 /// Specified as `#[mock(switch)]`.
 ///
+/// [`Mut`]: https://docs.rs/mimicry/latest/mimicry/struct.Mut.html
 /// [`RealCallSwitch`]: https://docs.rs/mimicry/latest/mimicry/struct.RealCallSwitch.html
 #[proc_macro_derive(CallReal, attributes(mock))]
 pub fn call_real_derive(input: TokenStream) -> TokenStream {
@@ -89,6 +245,41 @@ pub fn call_real_derive(input: TokenStream) -> TokenStream {
 /// in the path: if it starts with an uppercase letter, it is considered a mock state type;
 /// otherwise, it is considered a type + function.
 ///
+/// Several functions can use the same mock impl if their signatures are compatible.
+/// Args are passed to the mock impl as in a regular function call, so the usual coercions
+/// apply; e.g., a mock impl taking `s: &str` can mock both `len(s: &str)` and `len(s: &String)`.
+/// If args need a non-trivial conversion (e.g., `String` into `&str`), define a thin adapter
+/// mock impl for the function that converts args and delegates to the shared mock impl:
+///
+/// ```
+/// # use mimicry::{mock, Mock};
+/// #[mock(using = "LenMock::len")]
+/// fn len(s: &str) -> usize { s.len() }
+/// #[mock(using = "LenMock::len")]
+/// fn string_len(s: &String) -> usize { s.len() }
+/// #[mock(using = "LenMock::len_owned")]
+/// fn owned_len(s: String) -> usize { s.len() }
+///
+/// #[derive(Mock)]
+/// #[mock(auto_check)]
+/// struct LenMock;
+///
+/// impl LenMock {
+///     fn len(&self, s: &str) -> usize {
+///         s.chars().count()
+///     }
+///
+///     fn len_owned(&self, s: String) -> usize {
+///         self.len(&s)
+///     }
+/// }
+///
+/// let _guard = LenMock.set_as_mock();
+/// assert_eq!(len("ü"), 1);
+/// assert_eq!(string_len(&"ü".to_owned()), 1);
+/// assert_eq!(owned_len("ü".to_owned()), 1);
+/// ```
+///
 /// ## `rename`
 ///
 /// Specifies a pattern to use when accessing mock impl methods. A pattern is a string with `{}`
@@ -97,15 +288,205 @@ pub fn call_real_derive(input: TokenStream) -> TokenStream {
 ///
 /// This attribute is mostly useful for impl blocks.
 ///
+/// ## `instance`
+///
+/// Specifies the name of the mock state instance to use. Named instances are set
+/// using `Mock::set_as_named_mock()`; they are independent from each other and from
+/// the default instance set via `Mock::set_as_mock()`. This allows using several
+/// instances of the same mock state type simultaneously.
+///
+/// ## `strict`
+///
+/// Flag that makes the function panic if it's called in strict mode (enabled via
+/// `mimicry::enable_strict_mode()`) and the call is not routed to the mock impl,
+/// i.e., if the mock state is not set or the call is delegated to the real implementation.
+/// The real implementation is executed as usual if strict mode is not enabled.
+///
+/// ## `variants`
+///
+/// Specifies alternative variants of the mock impl, e.g., `#[mock(using = "Mock", variants(slow))]`.
+/// On each call, the variant is selected using the `MockVariant` trait, which must be implemented
+/// for the mock state. The mock impl for a variant has the name of the primary mock impl
+/// suffixed with `_{variant}` (e.g., `fetch_slow` for the `fetch` mock impl
+/// and the `slow` variant). If no variant or an undeclared variant is selected,
+/// the call is dispatched to the primary mock impl.
+///
+/// ## `for`
+///
+/// Pins the type param of a generic function to a concrete type, e.g.,
+/// `#[mock(using = "LenMock", for = "u32")]`. Only calls with the pinned type are routed
+/// to the mock impl, which is not generic and has the type param replaced with the pinned type
+/// in its signature; calls with other types are always forwarded to the real impl.
+/// This is useful if the function is only called with a single type in tests.
+///
+/// The type is checked at runtime via `TypeId`, so the type param must be bounded by `'static`.
+/// The function must have a single type param and no const params. Arg and return types
+/// mentioning the type param may be references (e.g., `&T` or `&mut Vec<T>`), but otherwise
+/// must not contain references or lifetimes. `async` functions and functions returning
+/// `impl Trait` are not supported.
+///
+/// ```
+/// # use mimicry::{mock, Mock};
+/// #[mock(using = "LenMock", for = "u32")]
+/// fn len<T: ToString + 'static>(value: &T) -> usize {
+///     value.to_string().len()
+/// }
+///
+/// #[derive(Mock)]
+/// #[mock(auto_check)]
+/// struct LenMock;
+///
+/// impl LenMock {
+///     fn len(&self, value: &u32) -> usize {
+///         usize::try_from(*value).unwrap()
+///     }
+/// }
+///
+/// let _guard = LenMock.set_as_mock();
+/// assert_eq!(len(&5_u32), 5);
+/// assert_eq!(len(&"test".to_owned()), 4); // not mocked
+/// ```
+///
+/// ## `scoped`
+///
+/// Flag specifying that the mock state has a lifetime param and is set via
+/// `mimicry::with_scoped_mock()` (see the `Mock` derive macro docs). The mock impl receives
+/// the state via a reference with a call-local lifetime, so it must be generic over
+/// the state lifetime, e.g., be defined in `impl SpyMock<'_>`. `async` and diverging functions
+/// are not supported; `instance`, `variants` and `for` attrs cannot be used together with `scoped`.
+///
+/// ```
+/// # use mimicry::{mock, Mock};
+/// # use std::sync::Mutex;
+/// #[mock(using = "SpyMock", scoped)]
+/// fn answer(value: u32) -> u32 {
+///     value * 2
+/// }
+///
+/// #[derive(Mock)]
+/// #[mock(auto_check)]
+/// struct SpyMock<'a> {
+///     calls: &'a Mutex<Vec<u32>>,
+/// }
+///
+/// impl SpyMock<'_> {
+///     fn answer(&self, value: u32) -> u32 {
+///         self.calls.lock().unwrap().push(value);
+///         value
+///     }
+/// }
+///
+/// let calls = Mutex::default();
+/// let (output, _) = mimicry::with_scoped_mock(SpyMock { calls: &calls }, || answer(5));
+/// assert_eq!(output, 5);
+/// assert_eq!(*calls.lock().unwrap(), [5]);
+/// ```
+///
+/// ## `crate`
+///
+/// Specifies a [path] to the `mimicry` crate used in the generated code, such as
+/// `#[mock(using = "Mock", crate = "test_utils::mimicry")]`. This is useful if `mimicry`
+/// is re-exported from another crate. By default, the path is `mimicry`.
+///
 /// # Supported items
 ///
 /// The `mock` attribute can be used on functions / methods. Pretty much all signatures
 /// are supported, e.g., generic functions, non-`'static` args, return types
 /// with dependent / elided lifetime, etc. `const` functions are not supported.
+/// Functions with an explicit ABI (e.g., `extern "C" fn callback(x: i32) -> i32`) are supported
+/// as well, so they can be passed as FFI callbacks. Keep in mind that panics must not unwind
+/// out of `extern "C"` functions, so mock impls of such functions should not panic.
+///
+/// Functions returning `impl Trait` (e.g., `impl Iterator<Item = &str> + '_`) are supported
+/// as well. The real impl is kept as is, so the mock impl must return the same concrete type
+/// as the real impl; the compiler infers the hidden type of `impl Trait` from both impls.
+/// For example, both impls can return `std::vec::IntoIter<&str>`, or box their outputs
+/// into `Pin<Box<dyn Stream<Item = &str> + '_>>` if the concrete types differ.
+/// The same applies to `impl Trait` nested in other types
+/// (e.g., `Result<impl Iterator<Item = &str>, Error>`).
+///
+/// Diverging functions (i.e., ones returning `!`, such as `fn abort(code: i32) -> !`)
+/// are supported as well. The mock impl must diverge too, e.g., by panicking; this allows
+/// capturing the call args and unwinding instead of, say, exiting the process in tests.
+///
+/// For methods, the receiver is passed to the mock impl as the first arg after the mock state,
+/// with the same type as in the mocked method (e.g., `&Self` for `&self`). Consuming methods
+/// (e.g., `fn build(self) -> Widget` for a builder) receive the receiver by value;
+/// the mock impl can then modify it and / or forward it to the real impl,
+/// e.g., `self.call_real().scope(|| builder.build())`.
+/// Associated functions without a receiver (e.g., constructors like `fn new(cap: usize) -> Self`)
+/// are mocked in the same way as free functions; since `Self` in the mock impl refers
+/// to the mock state, the mock impl must name the constructed type explicitly. It may make sense
+/// to name the mock impl differently, e.g. `#[mock(using = "BufferMock::new_buffer")]`.
+///
+/// Generic params of the mocked function are usually inferred for the mock impl from the args
+/// and the return type. If some type / const params cannot be inferred this way
+/// (e.g., in `fn size<T>() -> usize`), all type / const params are passed to the mock impl
+/// explicitly. In this case, the mock impl must declare the same type / const params
+/// in the same order. Params mentioned only in projections (e.g., `T` in `-> T::Output`)
+/// or in `impl Trait` bounds (e.g., `T` in `value: impl AsRef<T>`) are not considered inferable.
+/// If the mocked function has `impl Trait` args as well, passing explicit params requires
+/// Rust 1.63 or newer.
+///
+/// Args in `impl Trait` position (e.g., `value: impl AsRef<str>`) are forwarded to the mock impl
+/// as is, i.e., as values of an anonymous generic type. Thus, the corresponding arg in the mock impl
+/// must be generic as well, with bounds implied by the bounds in the mocked function
+/// (e.g., `value: impl AsRef<str>` or `value: T` where `T: AsRef<str>`). The mock impl
+/// can narrow the arg to a concrete type in its body, e.g., by calling `value.as_ref()`.
+/// If the arg in the mock impl has a concrete type (e.g., `value: &str`), compilation fails
+/// with a type mismatch error pointing to the arg of the mocked function.
+/// This includes closure args (e.g., `op: impl FnMut() -> Result<T, E>`): the mock impl
+/// receives the closure and may call it as many times as necessary, or not at all.
 ///
 /// The `mock` attribute can also be placed on an impl block (including a trait implementation).
 /// In this case, it will apply to all methods in the block. If necessary, mocking options can
-/// be overridden for separate methods in the block by adding a `mock` attribute on them.
+/// be overridden for separate methods in the block by adding a `mock` attribute on them
+/// (possibly a conditional one, e.g., `#[cfg_attr(test, mock(..))]`).
+/// To only mock some methods in the block, list them in the `methods` attr, e.g.,
+/// `#[mock(using = "HandlerMock", methods(handle))]`; other methods are left intact.
+///
+/// Mocking a trait implementation for a concrete type also covers calls via trait objects
+/// (e.g., `&dyn Handler`) since dynamic dispatch calls the methods of the concrete type.
+/// Default trait methods that are not overridden in the implementation cannot be mocked;
+/// to mock such a method, override it in the implementation with the same body.
+///
+/// Function pointers and closures stored in fields (e.g., `handler: fn(&Event)`) cannot be mocked
+/// directly. Instead, call the stored function via a dedicated method and mock this method;
+/// the mock impl can delegate to the stored function via [`CallReal`](macro@CallReal).
+///
+/// ```
+/// # use mimicry::{mock, CallReal, Mock, RealCallSwitch};
+/// # use std::sync::Mutex;
+/// struct Dispatcher {
+///     handler: fn(&str) -> usize,
+/// }
+///
+/// impl Dispatcher {
+///     #[mock(using = "HandlerMock")]
+///     fn handle(&self, event: &str) -> usize {
+///         (self.handler)(event)
+///     }
+/// }
+///
+/// #[derive(Default, Mock, CallReal)]
+/// struct HandlerMock {
+///     events: Mutex<Vec<String>>,
+///     switch: RealCallSwitch,
+/// }
+///
+/// impl HandlerMock {
+///     fn handle(&self, dispatcher: &Dispatcher, event: &str) -> usize {
+///         self.events.lock().unwrap().push(event.to_owned());
+///         self.call_real_with(|| dispatcher.handle(event))
+///     }
+/// }
+///
+/// let dispatcher = Dispatcher { handler: str::len };
+/// let guard = HandlerMock::default().set_as_mock();
+/// assert_eq!(dispatcher.handle("test"), 4);
+/// let events = guard.into_inner().events.into_inner().unwrap();
+/// assert_eq!(events, ["test"]);
+/// ```
 ///
 /// # Examples
 ///
@@ -4,26 +4,96 @@ use darling::FromMeta;
 use proc_macro::TokenStream;
 use quote::{quote, ToTokens};
 use syn::{
-    parse::Error as SynError, parse_quote, spanned::Spanned, DeriveInput, GenericParam, Generics,
-    Ident,
+    parse::Error as SynError, parse_quote, spanned::Spanned, Data, DataStruct, DeriveInput, Fields,
+    GenericParam, Generics, Ident, Lifetime, Path, Visibility,
 };
 
-use crate::utils::find_meta_attrs;
+use crate::{
+    call_real_impl::{CallReal, FieldIdent},
+    utils::find_meta_attrs,
+};
+
+/// Value of the `shared` attribute.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sharing {
+    /// `ThreadLocal` wrapper.
+    ThreadLocal,
+    /// `Shared` wrapper.
+    Reentrant,
+    /// `SharedRw` wrapper.
+    ReadWrite,
+    /// `Global` wrapper.
+    Global,
+}
+
+impl Default for Sharing {
+    fn default() -> Self {
+        Self::ThreadLocal
+    }
+}
+
+impl FromMeta for Sharing {
+    fn from_word() -> darling::Result<Self> {
+        Ok(Self::Reentrant)
+    }
+
+    fn from_bool(value: bool) -> darling::Result<Self> {
+        Ok(if value {
+            Self::Reentrant
+        } else {
+            Self::ThreadLocal
+        })
+    }
+
+    fn from_string(value: &str) -> darling::Result<Self> {
+        match value {
+            "rw" => Ok(Self::ReadWrite),
+            _ => Err(darling::Error::unknown_value(value)),
+        }
+    }
+}
 
 #[derive(Debug, Default, FromMeta)]
+#[allow(clippy::struct_excessive_bools)] // flags are independent
 struct MockAttrs {
     #[darling(default)]
-    shared: bool,
+    shared: Sharing,
+    #[darling(default)]
+    global: bool,
     #[darling(rename = "mut", default)]
     mutable: bool,
+    #[darling(default)]
+    auto_check: bool,
+    #[darling(default)]
+    reset: bool,
+    #[darling(default)]
+    count: bool,
+    #[darling(default)]
+    stats: bool,
+    #[darling(default)]
+    debug: bool,
+    #[darling(rename = "crate")]
+    krate: Option<Path>,
 }
 
 #[derive(Debug)]
+#[allow(clippy::struct_excessive_bools)] // flags are independent
 struct Mock {
+    cr: Path,
+    vis: Visibility,
     generics: Generics,
     ident: Ident,
-    shared: bool,
+    shared: Sharing,
     mutable: bool,
+    /// Whether the state has a lifetime param and thus implements `ScopedMock`.
+    scoped: bool,
+    auto_check: bool,
+    count: bool,
+    stats: bool,
+    /// Fields to reset in the generated `reset()` method.
+    reset_fields: Option<Vec<FieldIdent>>,
+    /// Fields to format in the generated `Debug` impl.
+    debug_fields: Option<Fields>,
 }
 
 impl Mock {
@@ -33,71 +103,365 @@ impl Mock {
             |meta| MockAttrs::from_nested_meta(&meta),
         )?;
 
-        let mut params = input.generics.params.iter();
-        let lifetime_span = params.find_map(|param| {
-            if matches!(param, GenericParam::Lifetime(_)) {
-                Some(param.span())
-            } else {
-                None
+        let scoped_lifetime = Self::scoped_lifetime(&input.generics)?;
+        if scoped_lifetime.is_some() {
+            Self::check_scoped(input, &attrs)?;
+        }
+
+        let shared = if attrs.global {
+            if attrs.shared != Sharing::ThreadLocal {
+                let message = "`global` cannot be used together with `shared`";
+                return Err(SynError::new(input.ident.span(), message));
             }
-        });
-        if let Some(span) = lifetime_span {
-            let message = "Mock states with lifetimes are not supported";
-            return Err(SynError::new(span, message));
+            Sharing::Global
+        } else {
+            attrs.shared
+        };
+        if shared == Sharing::ReadWrite && attrs.mutable {
+            let message = "`shared = \"rw\"` cannot be used together with `mut`; \
+                `Mut` wrapper cannot be accessed concurrently";
+            return Err(SynError::new(input.ident.span(), message));
+        }
+        if attrs.auto_check {
+            Self::check_auto_check(input, &attrs)?;
         }
+        let reset_fields = if attrs.reset {
+            Some(Self::reset_fields(input)?)
+        } else {
+            None
+        };
+        let debug_fields = if attrs.debug {
+            if let Data::Struct(DataStruct { fields, .. }) = &input.data {
+                Some(fields.clone())
+            } else {
+                let message = "`debug` is only supported for structs";
+                return Err(SynError::new(input.ident.span(), message));
+            }
+        } else {
+            None
+        };
 
         Ok(Self {
+            cr: attrs.krate.unwrap_or_else(|| parse_quote!(mimicry)),
+            vis: input.vis.clone(),
             generics: input.generics.clone(),
             ident: input.ident.clone(),
-            shared: attrs.shared,
+            shared,
             mutable: attrs.mutable,
+            scoped: scoped_lifetime.is_some(),
+            auto_check: attrs.auto_check,
+            count: attrs.count,
+            stats: attrs.stats,
+            reset_fields,
+            debug_fields,
         })
     }
 
+    /// Returns the lifetime param of a scoped state. Scoped states must have a single lifetime
+    /// param and no other generic params.
+    fn scoped_lifetime(generics: &Generics) -> Result<Option<&Lifetime>, SynError> {
+        let mut lifetimes = generics.lifetimes().map(|param| &param.lifetime);
+        let lifetime = match lifetimes.next() {
+            Some(lifetime) => lifetime,
+            None => return Ok(None),
+        };
+        if let Some(other) = lifetimes.next() {
+            let message = "Mock states with multiple lifetime params are not supported";
+            return Err(SynError::new(other.span(), message));
+        }
+        let generic_param = generics
+            .params
+            .iter()
+            .find(|param| !matches!(param, GenericParam::Lifetime(_)));
+        if let Some(param) = generic_param {
+            let message = "Mock states with a lifetime param cannot have type / const params";
+            return Err(SynError::new(param.span(), message));
+        }
+        Ok(Some(lifetime))
+    }
+
+    fn check_scoped(input: &DeriveInput, attrs: &MockAttrs) -> Result<(), SynError> {
+        let unsupported_attr = if attrs.shared != Sharing::ThreadLocal {
+            Some("shared")
+        } else if attrs.global {
+            Some("global")
+        } else if attrs.mutable {
+            Some("mut")
+        } else if attrs.count {
+            Some("count")
+        } else if attrs.stats {
+            Some("stats")
+        } else {
+            None
+        };
+        if let Some(attr) = unsupported_attr {
+            let message = format!(
+                "`{attr}` is not supported for mock states with a lifetime param; \
+                 such states are always set for the current thread via `with_scoped_mock()`"
+            );
+            return Err(SynError::new(input.ident.span(), message));
+        }
+        Ok(())
+    }
+
+    fn reset_fields(input: &DeriveInput) -> Result<Vec<FieldIdent>, SynError> {
+        if let Data::Struct(DataStruct { fields, .. }) = &input.data {
+            let fields = fields.iter().enumerate();
+            Ok(fields.map(|(i, field)| FieldIdent::new(i, field)).collect())
+        } else {
+            let message = "`reset` is only supported for structs";
+            Err(SynError::new(input.ident.span(), message))
+        }
+    }
+
+    fn check_auto_check(input: &DeriveInput, attrs: &MockAttrs) -> Result<(), SynError> {
+        if attrs.mutable {
+            let message = "`auto_check` is redundant for `mut` mock states; \
+                `Mut` wrapper already implements `CheckRealCall`";
+            return Err(SynError::new(input.ident.span(), message));
+        }
+        if let Data::Struct(DataStruct { fields, .. }) = &input.data {
+            let switch_field = fields.iter().find(|field| CallReal::is_switch(&field.ty));
+            if let Some(field) = switch_field {
+                let message = "`auto_check` cannot be used together with a `RealCallSwitch` \
+                    field; derive `CallReal` instead";
+                return Err(SynError::new_spanned(field, message));
+            }
+        }
+        Ok(())
+    }
+
+    fn is_generic(&self) -> bool {
+        self.generics
+            .params
+            .iter()
+            .any(|param| !matches!(param, GenericParam::Lifetime(_)))
+    }
+
     fn impl_mock(&self) -> impl ToTokens {
+        let cr = &self.cr;
         let ident = &self.ident;
         let base = if self.mutable {
-            quote!(mimicry::Mut<Self>)
+            quote!(#cr::Mut<Self>)
         } else {
             quote!(Self)
         };
-        let wrapper = if self.shared {
-            quote!(mimicry::Shared)
-        } else {
-            quote!(mimicry::ThreadLocal)
+        let wrapper = match self.shared {
+            Sharing::ThreadLocal => quote!(#cr::ThreadLocal),
+            Sharing::Reentrant => quote!(#cr::Shared),
+            Sharing::ReadWrite => quote!(#cr::SharedRw),
+            Sharing::Global => quote!(#cr::Global),
         };
 
         let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
         let mut where_clause = where_clause.cloned().unwrap_or_else(|| parse_quote!(where));
         where_clause
             .predicates
-            .push(parse_quote!(#wrapper<#base>: Send + Sync));
+            .push(parse_quote!(#wrapper<#base>: Send + Sync + 'static));
+        // Required by the `ThreadLocal` wrapper and implied by `Sync` for other wrappers.
+        // Without this bound, generic states would need to declare `Send` bounds
+        // on their type params explicitly.
+        where_clause.predicates.push(parse_quote!(#base: Send));
+
+        let instance = if self.is_generic() {
+            // `static`s cannot depend on generic params, so we use a type-indexed map.
+            quote! {
+                static SHARED: #cr::StaticMap = #cr::StaticMap::new();
+                SHARED.get::<Self::Shared>()
+            }
+        } else {
+            // `static` requires an exact type.
+            let shared_ty = if self.mutable {
+                quote!(#wrapper<#cr::Mut<#ident #ty_generics>>)
+            } else {
+                quote!(#wrapper<#ident #ty_generics>)
+            };
+            quote! {
+                static SHARED: #cr::Static<#shared_ty> = #cr::Static::new();
+                &SHARED
+            }
+        };
 
-        // `static` requires an exact type.
-        let shared_ty = if self.mutable {
-            quote!(#wrapper<mimicry::Mut<#ident #ty_generics>>)
+        let stats = if self.stats {
+            Some(quote!(
+                const DISPATCH_STATS: bool = true;
+            ))
         } else {
-            quote!(#wrapper<#ident #ty_generics>)
+            None
         };
 
         quote! {
-            impl #impl_generics mimicry::Mock for #ident #ty_generics #where_clause {
+            impl #impl_generics #cr::Mock for #ident #ty_generics #where_clause {
                 type Base = #base;
                 type Shared = #wrapper<Self::Base>;
+                #stats
+
+                fn instance() -> &'static #cr::Static<Self::Shared> {
+                    #instance
+                }
+            }
+        }
+    }
+
+    fn impl_scoped_mock(&self) -> impl ToTokens {
+        let cr = &self.cr;
+        let ident = &self.ident;
+        // The implemented traits are unsafe; the types in their impls are consistent by design,
+        // and the covariance of the state is checked by the compiler.
+        quote! {
+            unsafe impl<'a> #cr::ScopedMock<'a> for #ident<'a> {
+                type Static = #ident<'static>;
+            }
+
+            unsafe impl<'s> #cr::ScopedStatic<'s> for #ident<'static> {
+                type Scoped = #ident<'s>;
+            }
+
+            const _: () = {
+                #[allow(dead_code)]
+                fn check_lifetime_covariance<'a: 's, 's>(state: &'s #ident<'a>) -> &'s #ident<'s> {
+                    state
+                }
+            };
+        }
+    }
+
+    fn impl_reset(&self, fields: &[FieldIdent]) -> impl ToTokens {
+        let vis = &self.vis;
+        let ident = &self.ident;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+        quote! {
+            impl #impl_generics #ident #ty_generics #where_clause {
+                /// Resets all fields in this state to their default values.
+                #vis fn reset(&mut self) {
+                    #(self.#fields = core::default::Default::default();)*
+                }
+            }
+        }
+    }
+
+    fn impl_call_count(&self) -> impl ToTokens {
+        let cr = &self.cr;
+        let vis = &self.vis;
+        let ident = &self.ident;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+        quote! {
+            impl #impl_generics #ident #ty_generics #where_clause {
+                /// Returns the number of calls dispatched to the mock impls since the current state
+                /// of the default instance was set, or 0 if the state is not set.
+                #vis fn call_count() -> usize {
+                    #cr::CountCalls::call_count(<Self as #cr::Mock>::instance())
+                }
+            }
+        }
+    }
+
+    fn impl_dispatch_stats(&self) -> impl ToTokens {
+        let cr = &self.cr;
+        let vis = &self.vis;
+        let ident = &self.ident;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+        quote! {
+            impl #impl_generics #ident #ty_generics #where_clause {
+                /// Returns a snapshot of statistics of calls dispatched to the mock impls
+                /// or delegated to the real impls since the current state of the default instance
+                /// was set. The statistics are empty if the state is not set.
+                #vis fn dispatch_stats() -> #cr::DispatchStats {
+                    #cr::CountCalls::dispatch_stats(<Self as #cr::Mock>::instance())
+                }
+            }
+        }
+    }
+
+    /// Implements `Debug` formatting fields which implement `Debug` and eliding the rest.
+    fn impl_debug(&self, fields: &Fields) -> impl ToTokens {
+        let cr = &self.cr;
+        let ident = &self.ident;
+        let name = ident.to_string();
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+        let field_idents = fields.iter().enumerate();
+        let field_idents: Vec<_> = field_idents
+            .map(|(i, field)| FieldIdent::new(i, field))
+            .collect();
 
-                fn instance() -> &'static mimicry::Static<Self::Shared> {
-                    static SHARED: mimicry::Static<#shared_ty> = mimicry::Static::new();
-                    &SHARED
+        let body = match fields {
+            Fields::Named(_) => {
+                let field_names = field_idents
+                    .iter()
+                    .map(|ident| ident.to_token_stream().to_string());
+                quote! {
+                    let mut debug_struct = formatter.debug_struct(#name);
+                    let mut is_exhaustive = true;
+                    #(
+                    if let Some(value) = (&#cr::MaybeDebug(&self.#field_idents)).as_debug() {
+                        debug_struct.field(#field_names, value);
+                    } else {
+                        is_exhaustive = false;
+                    }
+                    )*
+                    if is_exhaustive {
+                        debug_struct.finish()
+                    } else {
+                        debug_struct.finish_non_exhaustive()
+                    }
+                }
+            }
+            Fields::Unnamed(_) => quote! {
+                let mut debug_tuple = formatter.debug_tuple(#name);
+                #(
+                if let Some(value) = (&#cr::MaybeDebug(&self.#field_idents)).as_debug() {
+                    debug_tuple.field(value);
+                } else {
+                    debug_tuple.field(&format_args!("_"));
+                }
+                )*
+                debug_tuple.finish()
+            },
+            Fields::Unit => quote!(formatter.write_str(#name)),
+        };
+
+        quote! {
+            impl #impl_generics core::fmt::Debug for #ident #ty_generics #where_clause {
+                fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                    #[allow(unused_imports)]
+                    use #cr::{AsDebug as _, AsDebugFallback as _};
+                    #body
                 }
             }
         }
     }
+
+    fn impl_check_real_call(&self) -> impl ToTokens {
+        let cr = &self.cr;
+        let ident = &self.ident;
+        let (impl_generics, ty_generics, where_clause) = self.generics.split_for_impl();
+        quote! {
+            impl #impl_generics #cr::CheckRealCall for #ident #ty_generics #where_clause {}
+        }
+    }
 }
 
 impl ToTokens for Mock {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
-        let mock_impl = self.impl_mock();
-        tokens.extend(quote!(#mock_impl));
+        let mock_impl = if self.scoped {
+            self.impl_scoped_mock().into_token_stream()
+        } else {
+            self.impl_mock().into_token_stream()
+        };
+        let check_impl = self.auto_check.then(|| self.impl_check_real_call());
+        let reset_impl = self
+            .reset_fields
+            .as_ref()
+            .map(|fields| self.impl_reset(fields));
+        let count_impl = self.count.then(|| self.impl_call_count());
+        let stats_impl = self.stats.then(|| self.impl_dispatch_stats());
+        let debug_impl = self
+            .debug_fields
+            .as_ref()
+            .map(|fields| self.impl_debug(fields));
+        tokens.extend(quote! {
+            #mock_impl #check_impl #reset_impl #count_impl #stats_impl #debug_impl
+        });
     }
 }
 
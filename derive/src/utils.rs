@@ -1,7 +1,7 @@
 //! Misc utils.
 
 use proc_macro2::Span;
-use syn::{spanned::Spanned, Attribute, FnArg, NestedMeta, Pat, PatType};
+use syn::{spanned::Spanned, Attribute, FnArg, Meta, NestedMeta, Pat, PatType};
 
 pub(crate) fn find_meta_attrs(
     name: &str,
@@ -14,6 +14,30 @@ pub(crate) fn find_meta_attrs(
         .map(NestedMeta::from)
 }
 
+/// Finds predicates of `cfg_attr` attributes that conditionally add the specified attribute,
+/// such as `feature = "test"` in `#[cfg_attr(feature = "test", mock(using = "Mock"))]`.
+pub(crate) fn find_cfg_predicates(
+    name: &str,
+    cr: Option<&str>,
+    args: &[Attribute],
+) -> Vec<NestedMeta> {
+    let cfg_attrs = args.iter().filter_map(|attr| match attr.parse_meta() {
+        Ok(Meta::List(list)) if list.path.is_ident("cfg_attr") => Some(list.nested),
+        _ => None,
+    });
+    cfg_attrs
+        .filter_map(|nested| {
+            let mut nested = nested.into_iter();
+            let predicate = nested.next()?;
+            let has_attr = nested.any(|meta| match meta {
+                NestedMeta::Meta(meta) => match_path(meta.path(), name, cr),
+                NestedMeta::Lit(_) => false,
+            });
+            Some(predicate).filter(|_| has_attr)
+        })
+        .collect()
+}
+
 fn match_path(path: &syn::Path, name: &str, cr: Option<&str>) -> bool {
     if path.is_ident(name) {
         return true;
@@ -60,4 +84,42 @@ mod tests {
         assert!(!match_path(&path, "other", Some("crate")));
         assert!(!match_path(&path, "test", Some("crater")));
     }
+
+    #[test]
+    fn finding_cfg_predicates() {
+        let attrs: [Attribute; 4] = [
+            syn::parse_quote!(#[cfg_attr(feature = "test", mock(using = "Mock"))]),
+            syn::parse_quote!(#[cfg_attr(test, inline, crate::mock(using = "Mock"))]),
+            syn::parse_quote!(#[cfg_attr(unix, inline)]),
+            syn::parse_quote!(#[mock(using = "Mock")]),
+        ];
+        let predicates = find_cfg_predicates("mock", Some("crate"), &attrs);
+        let expected: [NestedMeta; 2] =
+            [syn::parse_quote!(feature = "test"), syn::parse_quote!(test)];
+        assert_eq!(predicates, expected);
+    }
+
+    #[test]
+    fn detecting_receivers() {
+        let receivers: [FnArg; 4] = [
+            syn::parse_quote!(&self),
+            syn::parse_quote!(mut self),
+            syn::parse_quote!(self: Box<Self>),
+            syn::parse_quote!(self: &Self),
+        ];
+        for arg in &receivers {
+            assert!(receiver_span(arg).is_some(), "{}", quote::quote!(#arg));
+        }
+
+        let args: [FnArg; 5] = [
+            syn::parse_quote!(this: &Self),
+            syn::parse_quote!(self_: &Point),
+            syn::parse_quote!(selfish: u32),
+            syn::parse_quote!((this, other): (u32, u32)),
+            syn::parse_quote!(Point { x, .. }: Point),
+        ];
+        for arg in &args {
+            assert!(receiver_span(arg).is_none(), "{}", quote::quote!(#arg));
+        }
+    }
 }
@@ -0,0 +1,18 @@
+use mimicry::{mock, Mock};
+
+#[mock(using = "MyMock")]
+fn answer() -> usize {
+    42
+}
+
+#[derive(Mock)]
+#[mock(auto_check)]
+struct MyMock;
+
+impl MyMock {
+    async fn answer(&self) -> usize {
+        23
+    }
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use mimicry_derive::Mock;
+
+/// Dummy struct to trick `Mock` derive logic.
+struct RealCallSwitch;
+
+#[derive(Mock)]
+#[mock(auto_check)]
+struct MyMock {
+    value: u32,
+    switch: RealCallSwitch,
+}
+
+fn main() {}
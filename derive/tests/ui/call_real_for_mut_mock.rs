@@ -0,0 +1,13 @@
+use mimicry_derive::CallReal;
+
+/// Dummy struct to trick `CallReal` derive logic.
+struct RealCallSwitch;
+
+#[derive(CallReal)]
+#[mock(mut)]
+struct MyMock {
+    value: u32,
+    switch: RealCallSwitch,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use mimicry_derive::Mock;
+
+#[derive(Mock)]
+#[mock(shared = "what")]
+struct MyMock {
+    value: u32,
+}
+
+fn main() {}
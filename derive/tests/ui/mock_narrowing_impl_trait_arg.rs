@@ -0,0 +1,18 @@
+use mimicry::{mock, Mock};
+
+#[mock(using = "MyMock")]
+fn len(value: impl AsRef<str>) -> usize {
+    value.as_ref().len()
+}
+
+#[derive(Mock)]
+#[mock(auto_check)]
+struct MyMock;
+
+impl MyMock {
+    fn len(&self, value: &str) -> usize {
+        value.len()
+    }
+}
+
+fn main() {}
@@ -0,0 +1,18 @@
+use mimicry::{mock, Mock};
+
+#[mock(using = "MyMock::mock_answer", rename = "mock_{}")]
+fn answer() -> usize {
+    42
+}
+
+#[derive(Mock)]
+#[mock(auto_check)]
+struct MyMock;
+
+impl MyMock {
+    fn mock_answer(&self) -> usize {
+        23
+    }
+}
+
+fn main() {}
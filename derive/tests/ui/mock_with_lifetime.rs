@@ -1,8 +1,20 @@
 use mimicry_derive::Mock;
 
 #[derive(Mock)]
+#[mock(shared)]
 struct WithLifetime<'a> {
     field: &'a (),
 }
 
+#[derive(Mock)]
+struct WithMultipleLifetimes<'a, 'b> {
+    field: &'a (),
+    other_field: &'b (),
+}
+
+#[derive(Mock)]
+struct WithLifetimeAndType<'a, T> {
+    field: &'a T,
+}
+
 fn main() {}
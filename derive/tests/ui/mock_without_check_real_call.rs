@@ -0,0 +1,14 @@
+use mimicry::{Mock, RealCallSwitch};
+
+#[derive(Mock)]
+struct MyMock {
+    value: u32,
+}
+
+#[derive(Mock)]
+struct MockWithSwitch {
+    value: u32,
+    switch: RealCallSwitch,
+}
+
+fn main() {}
@@ -0,0 +1,19 @@
+use mimicry::{mock, Mock};
+
+#[mock(using = "SpyMock", scoped)]
+async fn answer() -> u32 {
+    42
+}
+
+#[mock(using = "SpyMock", scoped, variants(other))]
+fn answer_with_variants() -> u32 {
+    42
+}
+
+#[derive(Mock)]
+#[mock(auto_check)]
+struct SpyMock<'a> {
+    value: &'a u32,
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use mimicry::{CheckRealCall, Mock};
+
+use std::cell::Cell;
+
+#[derive(Mock)]
+struct InvariantMock<'a> {
+    value: Cell<&'a u32>,
+}
+
+impl CheckRealCall for InvariantMock<'_> {}
+
+fn main() {}
@@ -0,0 +1,30 @@
+#![deny(unused_must_use)]
+
+use mimicry::{mock, Mock};
+
+#[mock(using = "ValueMock")]
+#[must_use = "answer is important"]
+fn answer() -> u32 {
+    42
+}
+
+#[must_use]
+#[mock(using = "ValueMock", rename = "answer")]
+fn other_answer() -> u32 {
+    23
+}
+
+#[derive(Mock)]
+#[mock(auto_check)]
+struct ValueMock;
+
+impl ValueMock {
+    fn answer(&self) -> u32 {
+        0
+    }
+}
+
+fn main() {
+    answer();
+    other_answer();
+}
@@ -2,7 +2,10 @@
 
 use parking_lot::Mutex;
 
-use core::{fmt, future::Future, iter, mem};
+use crate::expect::CallCount;
+use core::{fmt, future::Future, mem};
+#[cfg(feature = "shared")]
+use std::thread::ThreadId;
 use std::{sync::Arc, thread};
 
 /// Answers for a function call.
@@ -13,6 +16,9 @@ use std::{sync::Arc, thread};
 ///   [`Self::next_for()`].
 /// - The provided contexts are recorded for each call and then can be retrieved using
 ///   [`Self::take_calls()`]. This can be used to verify calls.
+/// - If the `shared` feature is enabled, the calling thread is recorded for each call as well;
+///   see [`Self::take_calls_with_thread()`]. This can be useful to debug
+///   [shared](crate::Shared) mocks used from multiple threads.
 ///
 /// The intended usage of `Answers` is as an element of [`Mock`](crate::Mock) state
 /// used in one or more mock methods.
@@ -148,8 +154,77 @@ use std::{sync::Arc, thread};
 /// assert_eq!(calls[1].1, 3);
 /// ```
 pub struct Answers<V, Ctx = ()> {
-    inner: Box<dyn FnMut(&Ctx) -> V + Send>,
+    inner: AnswersInner<V, Ctx>,
     calls: Vec<Ctx>,
+    /// Calling threads for `calls`.
+    #[cfg(feature = "shared")]
+    threads: Vec<ThreadId>,
+}
+
+/// Internal representation of [`Answers`]. Single-value answers (i.e., ones created
+/// with [`Answers::from_value()`] / [`Answers::from_value_once()`]) are represented without
+/// heap allocation and dynamic dispatch. Iterators passed to [`Answers::from_values()`]
+/// are boxed since they may be infinite (e.g., [`iter::repeat()`](core::iter::repeat())) or lazily evaluated,
+/// and thus cannot be collected on construction.
+enum AnswersInner<V, Ctx> {
+    /// Value cloned on each call.
+    Const { value: V, clone: fn(&V) -> V },
+    /// Value returned on the first call.
+    Once(Option<V>),
+    /// Values returned by an iterator.
+    Values(Box<dyn Iterator<Item = V> + Send>),
+    /// Values returned by an iterator together with the expected call contexts.
+    ValuesWithCtx {
+        iter: Box<dyn Iterator<Item = (Ctx, V)> + Send>,
+        check_context: fn(&Ctx, &Ctx),
+    },
+    /// Generic function.
+    Fn(Box<dyn FnMut(&Ctx) -> V + Send>),
+    /// Generic function that can mutate the context.
+    FnMut(Box<dyn FnMut(&mut Ctx) -> V + Send>),
+}
+
+impl<V, Ctx> AnswersInner<V, Ctx> {
+    fn next_for(&mut self, context: &mut Ctx) -> V {
+        match self {
+            Self::Const { value, clone } => clone(value),
+            Self::Once(value) => value.take().expect("run out of mock responses"),
+            Self::Values(iter) => iter.next().expect("run out of mock responses"),
+            Self::ValuesWithCtx {
+                iter,
+                check_context,
+            } => {
+                let (expected, value) = iter.next().expect("run out of mock responses");
+                check_context(context, &expected);
+                value
+            }
+            Self::Fn(function) => function(context),
+            Self::FnMut(function) => function(context),
+        }
+    }
+
+    fn remaining(&self) -> Option<usize> {
+        match self {
+            Self::Once(value) => Some(usize::from(value.is_some())),
+            Self::Values(iter) => Self::exact_len(iter.size_hint()),
+            Self::ValuesWithCtx { iter, .. } => Self::exact_len(iter.size_hint()),
+            Self::Const { .. } | Self::Fn(_) | Self::FnMut(_) => None,
+        }
+    }
+
+    fn exact_len(size_hint: (usize, Option<usize>)) -> Option<usize> {
+        match size_hint {
+            (lower, Some(upper)) if lower == upper => Some(lower),
+            _ => None,
+        }
+    }
+}
+
+fn check_context<Ctx: PartialEq + fmt::Debug>(actual: &Ctx, expected: &Ctx) {
+    assert!(
+        *actual == *expected,
+        "unexpected call context\n  expected: {expected:?}\n    actual: {actual:?}"
+    );
 }
 
 impl<V, Ctx: fmt::Debug> fmt::Debug for Answers<V, Ctx> {
@@ -157,7 +232,7 @@ impl<V, Ctx: fmt::Debug> fmt::Debug for Answers<V, Ctx> {
         formatter
             .debug_struct("Answers")
             .field("calls", &self.calls)
-            .finish()
+            .finish_non_exhaustive()
     }
 }
 
@@ -173,42 +248,391 @@ impl<V, Ctx> Answers<V, Ctx> {
     where
         F: FnMut(&Ctx) -> V + Send + 'static,
     {
+        Self::new(AnswersInner::Fn(Box::new(function)))
+    }
+
+    /// Answers based on the provided fallible function. If the function returns `None`,
+    /// the answers are considered exhausted, and the call panics in the same way as
+    /// for [`Self::from_values()`] once the values run out. This allows stateful functions
+    /// to signal exhaustion uniformly with other answers.
+    ///
+    /// # Panics
+    ///
+    /// The returned answers panic if the function returns `None`.
+    ///
+    /// # Examples
+    ///
+    /// ```should_panic
+    /// # use mimicry::Answers;
+    /// let mut budget = 10_u32;
+    /// let mut answers = Answers::from_fallible_fn(move |&cost: &u32| {
+    ///     budget = budget.checked_sub(cost)?;
+    ///     Some(budget)
+    /// });
+    /// assert_eq!(answers.next_for(3), 7);
+    /// assert_eq!(answers.next_for(5), 2);
+    /// answers.next_for(5); // panics: run out of mock responses
+    /// ```
+    pub fn from_fallible_fn<F>(mut function: F) -> Self
+    where
+        F: FnMut(&Ctx) -> Option<V> + Send + 'static,
+    {
+        Self::from_fn(move |context| function(context).expect("run out of mock responses"))
+    }
+
+    /// Answers based on the provided function, which can mutate the call context
+    /// (e.g., to canonicalize it or redact irrelevant data). The mutated context is recorded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::Answers;
+    /// let mut answers = Answers::from_fn_mut(|(name, timestamp): &mut (String, u64)| {
+    ///     *timestamp = 0; // redact the timestamp so that it's not recorded
+    ///     name.len()
+    /// });
+    /// assert_eq!(answers.next_for(("test".to_owned(), 1_234_567)), 4);
+    /// assert_eq!(answers.take_calls(), [("test".to_owned(), 0)]);
+    /// ```
+    pub fn from_fn_mut<F>(function: F) -> Self
+    where
+        F: FnMut(&mut Ctx) -> V + Send + 'static,
+    {
+        Self::new(AnswersInner::FnMut(Box::new(function)))
+    }
+
+    fn new(inner: AnswersInner<V, Ctx>) -> Self {
         Self {
-            inner: Box::new(function),
+            inner,
             calls: Vec::new(),
+            #[cfg(feature = "shared")]
+            threads: Vec::new(),
         }
     }
 
     /// Answers with values from the provided iterator. Once the iterator runs out of items,
     /// panics.
+    ///
+    /// # Panics
+    ///
+    /// The returned answers panic when the iterator runs out of items.
     pub fn from_values<I>(iter: I) -> Self
     where
         I: IntoIterator<Item = V>,
         I::IntoIter: Send + 'static,
     {
-        let mut iter = iter.into_iter();
-        Self::from_fn(move |_| iter.next().expect("run out of mock responses"))
+        Self::new(AnswersInner::Values(Box::new(iter.into_iter())))
+    }
+
+    /// Answers with values from the provided iterator, checking that each call has the context
+    /// paired with the value. This allows scripting both expected calls and responses to them.
+    ///
+    /// # Panics
+    ///
+    /// The returned answers panic if the call context differs from the expected one
+    /// (the panic message contains both contexts), or when the iterator runs out of items.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::Answers;
+    /// let mut answers = Answers::from_values_with_ctx([
+    ///     ("GET /".to_owned(), 200),
+    ///     ("GET /admin".to_owned(), 403),
+    /// ]);
+    /// assert_eq!(answers.next_for("GET /".to_owned()), 200);
+    /// assert_eq!(answers.next_for("GET /admin".to_owned()), 403);
+    /// ```
+    ///
+    /// ```should_panic
+    /// # use mimicry::Answers;
+    /// let mut answers = Answers::from_values_with_ctx([(1, "one"), (2, "two")]);
+    /// answers.next_for(2); // panics: the first call is expected to have context 1
+    /// ```
+    pub fn from_values_with_ctx<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (Ctx, V)>,
+        I::IntoIter: Send + 'static,
+        Ctx: PartialEq + fmt::Debug,
+    {
+        Self::new(AnswersInner::ValuesWithCtx {
+            iter: Box::new(iter.into_iter()),
+            check_context: check_context::<Ctx>,
+        })
+    }
+
+    /// Answers with the provided values in a shuffled order. The order is fully determined
+    /// by `seed`, so failures caused by a specific order are reproducible. This is useful
+    /// to check that the tested code is insensitive to the order of responses.
+    ///
+    /// Shuffling uses a simple non-cryptographic PRNG (xorshift); the produced order is stable
+    /// for the same `values` and `seed`. Recorded call contexts are not affected.
+    ///
+    /// # Panics
+    ///
+    /// The returned answers panic when values run out, similarly to [`Self::from_values()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::Answers;
+    /// let mut answers: Answers<u32> = Answers::from_values_shuffled(1..=5, 42);
+    /// let mut values: Vec<_> = (0..5).map(|_| answers.next_for(())).collect();
+    /// let mut other_answers: Answers<u32> = Answers::from_values_shuffled(1..=5, 42);
+    /// let other_values: Vec<_> = (0..5).map(|_| other_answers.next_for(())).collect();
+    /// assert_eq!(values, other_values);
+    /// values.sort_unstable();
+    /// assert_eq!(values, [1, 2, 3, 4, 5]);
+    /// ```
+    pub fn from_values_shuffled<I>(values: I, seed: u64) -> Self
+    where
+        I: IntoIterator<Item = V>,
+        V: Send + 'static,
+    {
+        let mut values: Vec<_> = values.into_iter().collect();
+        // xorshift64 has an all-zero fixed point, so the zero seed is replaced.
+        let mut state = if seed == 0 {
+            0x9e37_79b9_7f4a_7c15
+        } else {
+            seed
+        };
+        // Fisher-Yates shuffle
+        for i in (1..values.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            #[allow(clippy::cast_possible_truncation)] // the result is <= `i`
+            let j = (state % (i as u64 + 1)) as usize;
+            values.swap(i, j);
+        }
+        Self::from_values(values)
+    }
+
+    /// Answers with values generated from the provided [`proptest`] `strategy` using
+    /// the specified `runner`. A new value is generated on each call; the sequence of values
+    /// is deterministic if the runner is deterministic (e.g., created
+    /// with [`TestRunner::deterministic()`]).
+    ///
+    /// [`proptest`]: https://docs.rs/proptest/
+    /// [`TestRunner::deterministic()`]: proptest::test_runner::TestRunner::deterministic()
+    ///
+    /// # Panics
+    ///
+    /// The returned answers panic if the strategy fails to generate a value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::Answers;
+    /// use proptest::test_runner::TestRunner;
+    ///
+    /// let runner = TestRunner::deterministic();
+    /// let mut answers = Answers::from_strategy(0_u32..100, runner);
+    /// for i in 0..10 {
+    ///     assert!(answers.next_for(i) < 100);
+    /// }
+    /// assert_eq!(answers.take_calls().len(), 10);
+    /// ```
+    #[cfg(feature = "proptest")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "proptest")))]
+    pub fn from_strategy<S>(strategy: S, mut runner: proptest::test_runner::TestRunner) -> Self
+    where
+        S: proptest::strategy::Strategy<Value = V> + Send + 'static,
+    {
+        use proptest::strategy::ValueTree;
+
+        Self::from_fn(move |_| {
+            let tree = strategy
+                .new_tree(&mut runner)
+                .unwrap_or_else(|err| panic!("failed generating answer from strategy: {err}"));
+            tree.current()
+        })
     }
 
     /// Selects an answer based on the specified `context`. The context is recorded and can
     /// then be retrieved via [`Self::take_calls()`].
-    pub fn next_for(&mut self, context: Ctx) -> V {
-        let response = (self.inner)(&context);
+    pub fn next_for(&mut self, mut context: Ctx) -> V {
+        let response = self.inner.next_for(&mut context);
         self.calls.push(context);
+        #[cfg(feature = "shared")]
+        self.threads.push(thread::current().id());
         response
     }
 
+    /// Selects an answer based on the context projected from a `borrowed` value, e.g., a mocked
+    /// function arg. This allows recording only relevant parts of large args instead of cloning
+    /// them. Otherwise, works the same as [`Self::next_for()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::Answers;
+    /// struct Request {
+    ///     id: u64,
+    ///     payload: Vec<u8>,
+    /// }
+    ///
+    /// let mut answers = Answers::from_fn(|&id: &u64| id % 2 == 0);
+    /// let request = Request { id: 42, payload: vec![0; 1_024] };
+    /// assert!(answers.next_for_with(&request, |req| req.id));
+    /// assert_eq!(answers.take_calls(), [42]);
+    /// ```
+    pub fn next_for_with<C: ?Sized>(&mut self, borrowed: &C, project: impl FnOnce(&C) -> Ctx) -> V {
+        self.next_for(project(borrowed))
+    }
+
+    /// Returns the number of remaining answers if it is known. This is the case for answers
+    /// created with [`Self::from_value_once()`], and [`Self::from_values()`] /
+    /// [`Self::from_values_with_ctx()`] if the iterator reports its exact size
+    /// (e.g., for arrays and `Vec`s). For other answers
+    /// (e.g., ones created with [`Self::from_fn()`]), returns `None`.
+    ///
+    /// This can be used to check that all scripted answers were consumed
+    /// without using a [channel](Self::channel()).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::Answers;
+    /// let mut answers: Answers<usize> = Answers::from_values([1, 3, 5]);
+    /// assert_eq!(answers.remaining(), Some(3));
+    /// answers.next_for(());
+    /// answers.next_for(());
+    /// assert_eq!(answers.remaining(), Some(1));
+    /// answers.next_for(());
+    /// assert_eq!(answers.remaining(), Some(0));
+    ///
+    /// let answers: Answers<usize> = Answers::from_fn(|_| 42);
+    /// assert_eq!(answers.remaining(), None);
+    /// ```
+    pub fn remaining(&self) -> Option<usize> {
+        self.inner.remaining()
+    }
+
+    /// Asserts that all scripted answers were consumed, i.e., that the number
+    /// of [remaining answers](Self::remaining()) is known and is zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are remaining scripted answers, i.e., if the code under test made
+    /// fewer calls than expected. Also panics if the number of remaining answers is unknown,
+    /// e.g., for answers created with [`Self::from_fn()`], or with [`Self::from_values()`]
+    /// from an iterator that does not report its exact size.
+    ///
+    /// # Examples
+    ///
+    /// ```should_panic
+    /// # use mimicry::Answers;
+    /// let mut answers: Answers<usize> = Answers::from_values([1, 3, 5]);
+    /// answers.next_for(());
+    /// answers.assert_exhausted(); // panics: 2 answers remain
+    /// ```
+    pub fn assert_exhausted(&self) {
+        let remaining = self.remaining().expect(
+            "number of remaining answers is unknown; only answers with a known number \
+             of scripted values can be checked for exhaustion",
+        );
+        assert!(
+            remaining == 0,
+            "{remaining} scripted answer(s) were not consumed"
+        );
+    }
+
     /// Takes contexts for recorded calls since the last call to [`Self::take_calls()`],
     /// or after creation if called for the first time.
     pub fn take_calls(&mut self) -> Vec<Ctx> {
+        #[cfg(feature = "shared")]
+        self.threads.clear();
         mem::take(&mut self.calls)
     }
+
+    /// Returns contexts for recorded calls since the last call to [`Self::take_calls()`]
+    /// without removing them. This is useful for intermediate checks in a test;
+    /// calls continue to be recorded after them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::Answers;
+    /// let mut answers = Answers::from_fn(|s: &&str| s.len());
+    /// answers.next_for("test");
+    /// assert_eq!(answers.calls(), ["test"]);
+    /// answers.next_for("other");
+    /// assert_eq!(answers.calls(), ["test", "other"]);
+    /// assert_eq!(answers.take_calls(), ["test", "other"]);
+    /// assert!(answers.calls().is_empty());
+    /// ```
+    pub fn calls(&self) -> &[Ctx] {
+        &self.calls
+    }
+
+    /// Returns a serializable view of the contexts for recorded calls since the last call
+    /// to [`Self::take_calls()`]. Calls are serialized as a sequence of contexts;
+    /// this can be used for snapshot testing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::Answers;
+    /// let mut answers = Answers::from_fn(|&(x, y): &(u32, u32)| x + y);
+    /// answers.next_for((1, 2));
+    /// answers.next_for((3, 5));
+    /// let json = serde_json::to_string(&answers.serialized_calls()).unwrap();
+    /// assert_eq!(json, "[[1,2],[3,5]]");
+    /// ```
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub fn serialized_calls(&self) -> impl serde::Serialize + '_
+    where
+        Ctx: serde::Serialize,
+    {
+        self.calls.as_slice()
+    }
+
+    /// Takes contexts for recorded calls together with the IDs of the threads
+    /// that have made the calls. Otherwise, works the same as [`Self::take_calls()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::Answers;
+    /// # use std::thread;
+    /// let mut answers = Answers::from_value(42);
+    /// answers.next_for("main");
+    /// let mut answers = thread::spawn(move || {
+    ///     answers.next_for("other");
+    ///     answers
+    /// })
+    /// .join()
+    /// .unwrap();
+    ///
+    /// let calls = answers.take_calls_with_thread();
+    /// assert_eq!(calls.len(), 2);
+    /// assert_eq!(calls[0], (thread::current().id(), "main"));
+    /// assert_ne!(calls[1].0, thread::current().id());
+    /// ```
+    #[cfg(feature = "shared")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "shared")))]
+    pub fn take_calls_with_thread(&mut self) -> Vec<(ThreadId, Ctx)> {
+        let threads = mem::take(&mut self.threads);
+        threads.into_iter().zip(self.take_calls()).collect()
+    }
 }
 
 impl<V: Send + 'static, Ctx> Answers<V, Ctx> {
     /// Answers with the provided `value` once. Further calls will panic.
     pub fn from_value_once(value: V) -> Self {
-        Self::from_values(iter::once(value))
+        Self::new(AnswersInner::Once(Some(value)))
+    }
+
+    /// Answers with the provided `value` once. Further calls will answer with
+    /// the [default](Default) value.
+    pub fn from_value_once_or_default(value: V) -> Self
+    where
+        V: Default,
+    {
+        let mut value = Some(value);
+        Self::from_fn(move |_| value.take().unwrap_or_default())
     }
 
     /// Creates a new `Answers` instance that can receive answers dynamically via a channel.
@@ -233,12 +657,131 @@ impl<V: Send + 'static, Ctx> Answers<V, Ctx> {
         });
         (this, sender)
     }
+
+    /// Answers with values deserialized from a sequence using the provided `deserializer`.
+    /// Once the values run out, panics (i.e., the returned answers behave like ones
+    /// created with [`Self::from_values()`]).
+    ///
+    /// Together with [`Self::serialized_calls()`], this can be used for replay-style tests:
+    /// responses are recorded in one run and scripted from the recording in subsequent runs.
+    /// The data format is chosen by the caller; e.g., `serde_json::Deserializer::from_reader()`
+    /// allows reading values from a file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if deserialization fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::Answers;
+    /// let mut recorded = Answers::from_fn(|s: &String| s.len());
+    /// recorded.next_for("test".to_owned());
+    /// recorded.next_for("other".to_owned());
+    /// let json = serde_json::to_string(&recorded.serialized_calls()).unwrap();
+    ///
+    /// let mut deserializer = serde_json::Deserializer::from_str(&json);
+    /// let mut answers: Answers<String> = Answers::from_deserializer(&mut deserializer).unwrap();
+    /// assert_eq!(answers.remaining(), Some(2));
+    /// assert_eq!(answers.next_for(()), "test");
+    /// assert_eq!(answers.next_for(()), "other");
+    /// ```
+    #[cfg(feature = "serde")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+    pub fn from_deserializer<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        V: serde::Deserialize<'de>,
+        D: serde::Deserializer<'de>,
+    {
+        let values = <Vec<V> as serde::Deserialize>::deserialize(deserializer)?;
+        Ok(Self::from_values(values))
+    }
 }
 
 impl<V: Clone + Send + 'static, Ctx> Answers<V, Ctx> {
     /// Answers with the provided `value` infinite number of times.
     pub fn from_value(value: V) -> Self {
-        Self::from_values(iter::repeat(value))
+        Self::new(AnswersInner::Const {
+            value,
+            clone: V::clone,
+        })
+    }
+
+    /// Starts building answers routed by the call context. Routes are added with
+    /// [`AnswersRouter::on()`]; on each call, predicates of the routes are evaluated in order,
+    /// and the value of the first matching route is returned. Unlike a map, this does not
+    /// require contexts to be hashable or comparable.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::Answers;
+    /// let mut answers = Answers::route()
+    ///     .on(|path: &String| path.starts_with("/api/"), 200)
+    ///     .on(|path| path == "/", 302)
+    ///     .default(404);
+    /// assert_eq!(answers.next_for("/api/users".to_owned()), 200);
+    /// assert_eq!(answers.next_for("/".to_owned()), 302);
+    /// assert_eq!(answers.next_for("/admin".to_owned()), 404);
+    /// assert_eq!(answers.take_calls(), ["/api/users", "/", "/admin"]);
+    /// ```
+    pub fn route() -> AnswersRouter<V, Ctx> {
+        AnswersRouter { routes: Vec::new() }
+    }
+}
+
+/// Predicate of a route in [`AnswersRouter`].
+type RoutePredicate<Ctx> = Box<dyn Fn(&Ctx) -> bool + Send>;
+
+/// Builder of [`Answers`] routed by the call context. Created using [`Answers::route()`].
+pub struct AnswersRouter<V, Ctx> {
+    routes: Vec<(RoutePredicate<Ctx>, V)>,
+}
+
+impl<V, Ctx> fmt::Debug for AnswersRouter<V, Ctx> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("AnswersRouter")
+            .field("routes_len", &self.routes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<V: Clone + Send + 'static, Ctx: 'static> AnswersRouter<V, Ctx> {
+    /// Adds a route answering with `value` if the call context matches `predicate`.
+    #[must_use]
+    pub fn on<F>(mut self, predicate: F, value: V) -> Self
+    where
+        F: Fn(&Ctx) -> bool + Send + 'static,
+    {
+        self.routes.push((Box::new(predicate), value));
+        self
+    }
+
+    /// Finishes building answers, answering with `value` if no route matches the call context.
+    pub fn default(self, value: V) -> Answers<V, Ctx> {
+        self.finish(Some(value))
+    }
+
+    /// Finishes building answers without a default value.
+    ///
+    /// # Panics
+    ///
+    /// The returned answers panic if no route matches the call context.
+    pub fn build(self) -> Answers<V, Ctx> {
+        self.finish(None)
+    }
+
+    fn finish(self, default: Option<V>) -> Answers<V, Ctx> {
+        let routes = self.routes;
+        Answers::from_fn(move |context| {
+            let route = routes.iter().find(|(predicate, _)| predicate(context));
+            route
+                .map(|(_, value)| value)
+                .or(default.as_ref())
+                .expect("no route matches the call context")
+                .clone()
+        })
     }
 }
 
@@ -278,9 +821,12 @@ impl<V> AnswersSender<V> {
     pub fn send_all(&mut self, values: impl IntoIterator<Item = V>) -> AnswersGuard<'_, V> {
         let mut values: Vec<_> = values.into_iter().collect();
         values.reverse();
+        let sent = values.len();
         *self.inner.lock() = AnswersChannel { answers: values };
         AnswersGuard {
             inner: &mut self.inner,
+            sent,
+            expected: None,
         }
     }
 }
@@ -288,14 +834,45 @@ impl<V> AnswersSender<V> {
 /// Guard ensuring that answers sent from an [`AnswersSender`] are timely consumed.
 ///
 /// The consumption check is performed on guard drop: either implicit, or explicit
-/// via [`Self::scope()`].
+/// via [`Self::scope()`]. By default, the check requires all sent answers to be consumed;
+/// this can be relaxed using [`Self::expect_at_least()`] / [`Self::expect_at_most()`].
+///
+/// # Examples
+///
+/// ```
+/// # use mimicry::Answers;
+/// let (mut answers, mut sx) = Answers::channel();
+/// // Script up to 3 retries; fewer retries are fine as well.
+/// let guard = sx.send_all([Err(()), Err(()), Ok(5)]).expect_at_least(1);
+/// guard.scope(|| {
+///     assert_eq!(answers.next_for(()), Err(()));
+///     assert_eq!(answers.next_for(()), Err(()));
+/// });
+/// ```
 #[derive(Debug)]
 #[must_use = "If not used, the answer value(s) will be immediately discarded"]
 pub struct AnswersGuard<'a, V> {
     inner: &'a mut Arc<Mutex<AnswersChannel<V>>>,
+    sent: usize,
+    /// Expected number of consumed answers. If not set, all answers must be consumed.
+    expected: Option<CallCount>,
 }
 
 impl<V> AnswersGuard<'_, V> {
+    /// Requires at least `count` of the sent answers to be consumed before the guard is dropped.
+    /// Can be combined with [`Self::expect_at_most()`].
+    pub fn expect_at_least(mut self, count: usize) -> Self {
+        self.expected.get_or_insert(CallCount::ANY).min = count;
+        self
+    }
+
+    /// Requires at most `count` of the sent answers to be consumed before the guard is dropped.
+    /// Can be combined with [`Self::expect_at_least()`].
+    pub fn expect_at_most(mut self, count: usize) -> Self {
+        self.expected.get_or_insert(CallCount::ANY).max = Some(count);
+        self
+    }
+
     /// Executes the provided closure and checks that all the answers were consumed by it.
     pub fn scope<R>(self, action: impl FnOnce() -> R) -> R {
         let result = action();
@@ -315,20 +892,47 @@ impl<V> AnswersGuard<'_, V> {
     }
 
     /// Drops this guard discarding any remaining answers, so that the guard does not panic.
-    pub fn discard(self) {
-        self.inner.lock().answers.clear();
+    pub fn discard(mut self) {
+        self.expected = Some(CallCount::ANY);
+        // Remaining answers are cleared on drop.
     }
 }
 
 impl<V> Drop for AnswersGuard<'_, V> {
     fn drop(&mut self) {
-        if !thread::panicking() {
-            let guard = self.inner.lock();
+        let mut guard = self.inner.lock();
+        let remaining = mem::take(&mut guard.answers).len();
+        drop(guard);
+        if thread::panicking() {
+            return;
+        }
+
+        if let Some(expected) = self.expected {
+            let consumed = self.sent - remaining;
             assert!(
-                guard.answers.is_empty(),
-                "{} answer(s) not consumed from answers channel",
-                guard.answers.len()
+                expected.contains(consumed),
+                "{consumed} answer(s) consumed from answers channel; expected {expected}",
+                expected = AnswersCount(expected)
             );
+        } else {
+            assert!(
+                remaining == 0,
+                "{remaining} answer(s) not consumed from answers channel"
+            );
+        }
+    }
+}
+
+/// Human-readable expected number of consumed answers.
+struct AnswersCount(CallCount);
+
+impl fmt::Display for AnswersCount {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.0.min, self.0.max) {
+            (min, Some(max)) if min == max => write!(formatter, "exactly {min}"),
+            (0, Some(max)) => write!(formatter, "at most {max}"),
+            (min, Some(max)) => write!(formatter, "{min} to {max}"),
+            (min, None) => write!(formatter, "at least {min}"),
         }
     }
 }
@@ -337,6 +941,9 @@ impl<V> Drop for AnswersGuard<'_, V> {
 mod tests {
     use super::*;
 
+    use core::iter;
+    use std::panic;
+
     #[test]
     fn answers_basics() {
         let mut answers: Answers<i32> = Answers::from_values([1, 2, 3, 5]);
@@ -348,6 +955,172 @@ mod tests {
         assert_eq!(calls.len(), 4);
     }
 
+    #[test]
+    fn answers_from_single_value() {
+        let mut answers: Answers<String, u32> = Answers::from_value("test".to_owned());
+        for i in 0..5 {
+            assert_eq!(answers.next_for(i), "test");
+        }
+        assert_eq!(answers.take_calls(), [0, 1, 2, 3, 4]);
+
+        let mut answers: Answers<String, u32> = Answers::from_value_once("test".to_owned());
+        assert_eq!(answers.next_for(0), "test");
+        let next = panic::AssertUnwindSafe(|| answers.next_for(1));
+        let err = panic::catch_unwind(next).unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert_eq!(err, "run out of mock responses");
+        assert_eq!(answers.take_calls(), [0]);
+
+        let mut answers: Answers<String, u32> =
+            Answers::from_value_once_or_default("test".to_owned());
+        assert_eq!(answers.next_for(0), "test");
+        assert_eq!(answers.next_for(1), "");
+        assert_eq!(answers.next_for(2), "");
+        assert_eq!(answers.take_calls(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn remaining_answers() {
+        let mut answers: Answers<i32> = Answers::from_values(vec![1, 2]);
+        assert_eq!(answers.remaining(), Some(2));
+        answers.next_for(());
+        answers.next_for(());
+        assert_eq!(answers.remaining(), Some(0));
+
+        let answers: Answers<i32> = Answers::from_values((0..).filter(|&x| x % 2 == 0));
+        assert_eq!(answers.remaining(), None);
+        let answers: Answers<i32> = Answers::from_values(iter::repeat(1));
+        assert_eq!(answers.remaining(), None);
+
+        let mut answers: Answers<i32> = Answers::from_value_once(1);
+        assert_eq!(answers.remaining(), Some(1));
+        answers.next_for(());
+        assert_eq!(answers.remaining(), Some(0));
+
+        let mut answers = Answers::from_values_with_ctx([(1, "one"), (2, "two")]);
+        assert_eq!(answers.remaining(), Some(2));
+        answers.next_for(1);
+        assert_eq!(answers.remaining(), Some(1));
+
+        let answers: Answers<i32> = Answers::from_value(1);
+        assert_eq!(answers.remaining(), None);
+        let (answers, _sender) = Answers::<i32>::channel();
+        assert_eq!(answers.remaining(), None);
+    }
+
+    #[test]
+    fn asserting_exhausted_answers() {
+        let mut answers: Answers<i32> = Answers::from_values(vec![1, 2]);
+        answers.next_for(());
+        let err = panic::catch_unwind(panic::AssertUnwindSafe(|| answers.assert_exhausted()))
+            .unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert_eq!(err, "1 scripted answer(s) were not consumed");
+        answers.next_for(());
+        answers.assert_exhausted();
+
+        let mut answers: Answers<i32> = Answers::from_value_once(1);
+        let err = panic::catch_unwind(panic::AssertUnwindSafe(|| answers.assert_exhausted()))
+            .unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert_eq!(err, "1 scripted answer(s) were not consumed");
+        answers.next_for(());
+        answers.assert_exhausted();
+
+        let mut answers = Answers::from_values_with_ctx([(1, "one"), (2, "two")]);
+        answers.next_for(1);
+        let err = panic::catch_unwind(panic::AssertUnwindSafe(|| answers.assert_exhausted()))
+            .unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert_eq!(err, "1 scripted answer(s) were not consumed");
+        answers.next_for(2);
+        answers.assert_exhausted();
+
+        let unknown_answers = [
+            Answers::<i32>::from_fn(|()| 1),
+            Answers::from_value(1),
+            Answers::from_values((0..).filter(|&x| x % 2 == 0)),
+        ];
+        for answers in unknown_answers {
+            let err = panic::catch_unwind(panic::AssertUnwindSafe(|| answers.assert_exhausted()))
+                .unwrap_err();
+            let err = err.downcast_ref::<String>().unwrap();
+            assert!(
+                err.starts_with("number of remaining answers is unknown"),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn shuffled_answers() {
+        let shuffle = |seed| {
+            let mut answers: Answers<u32> = Answers::from_values_shuffled(0..10, seed);
+            assert_eq!(answers.remaining(), Some(10));
+            (0..10).map(|_| answers.next_for(())).collect::<Vec<_>>()
+        };
+
+        for seed in [0, 1, 42, u64::MAX] {
+            let values = shuffle(seed);
+            assert_eq!(values, shuffle(seed));
+            let mut sorted_values = values.clone();
+            sorted_values.sort_unstable();
+            assert_eq!(sorted_values, (0..10).collect::<Vec<_>>());
+        }
+        assert_ne!(shuffle(1), shuffle(2));
+        assert_ne!(shuffle(1), (0..10).collect::<Vec<_>>());
+
+        let answers: Answers<u32> = Answers::from_values_shuffled(vec![], 1);
+        answers.assert_exhausted();
+    }
+
+    #[test]
+    fn fallible_fn_answers() {
+        let mut values = vec![1, 2];
+        let mut answers = Answers::from_fallible_fn(move |&skip: &bool| {
+            if skip {
+                values.pop();
+            }
+            values.pop()
+        });
+        assert_eq!(answers.next_for(false), 2);
+        let next = panic::AssertUnwindSafe(|| answers.next_for(true));
+        let err = panic::catch_unwind(next).unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert_eq!(err, "run out of mock responses");
+    }
+
+    #[test]
+    fn routed_answers() {
+        let mut answers: Answers<&str, u32> = Answers::route()
+            .on(|&x| x % 15 == 0, "fizzbuzz")
+            .on(|&x| x % 3 == 0, "fizz")
+            .on(|&x| x % 5 == 0, "buzz")
+            .build();
+        assert_eq!(answers.next_for(30), "fizzbuzz");
+        assert_eq!(answers.next_for(9), "fizz");
+        assert_eq!(answers.next_for(10), "buzz");
+
+        let next = panic::AssertUnwindSafe(|| answers.next_for(7));
+        let err = panic::catch_unwind(next).unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert_eq!(err, "no route matches the call context");
+        assert_eq!(answers.take_calls(), [30, 9, 10]);
+    }
+
+    #[test]
+    fn answers_with_expected_contexts() {
+        let mut answers = Answers::from_values_with_ctx([(1, "one"), (2, "two"), (3, "three")]);
+        assert_eq!(answers.next_for(1), "one");
+        assert_eq!(answers.next_for(2), "two");
+
+        let next = panic::AssertUnwindSafe(|| answers.next_for(5));
+        let err = panic::catch_unwind(next).unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert_eq!(err, "unexpected call context\n  expected: 3\n    actual: 5");
+        assert_eq!(answers.take_calls(), [1, 2]);
+    }
+
     #[test]
     fn answers_with_context() {
         let mut answers: Answers<usize, String> = Answers::from_values(5..10);
@@ -393,6 +1166,85 @@ mod tests {
         assert_eq!(answers.next_for(())("test"), 1);
     }
 
+    #[cfg(feature = "shared")]
+    #[test]
+    fn recording_calling_threads() {
+        let mut answers: Answers<usize, &str> = Answers::from_fn(|s: &&str| s.len());
+        assert_eq!(answers.next_for("test"), 4);
+        let main_thread = thread::current().id();
+
+        let handle = thread::spawn(move || {
+            assert_eq!(answers.next_for("other"), 5);
+            (answers, thread::current().id())
+        });
+        let (mut answers, other_thread) = handle.join().unwrap();
+        assert_eq!(answers.next_for("!"), 1);
+
+        let calls = answers.take_calls_with_thread();
+        assert_eq!(
+            calls,
+            [
+                (main_thread, "test"),
+                (other_thread, "other"),
+                (main_thread, "!")
+            ]
+        );
+        assert!(answers.take_calls_with_thread().is_empty());
+
+        answers.next_for("test");
+        assert_eq!(answers.take_calls(), ["test"]);
+        assert!(answers.take_calls_with_thread().is_empty());
+    }
+
+    #[cfg(feature = "proptest")]
+    #[test]
+    fn answers_from_strategy() {
+        use proptest::{strategy::Strategy, test_runner::TestRunner};
+
+        let strategy = (0_u32..10).prop_map(|x| x * 2);
+        let mut answers = Answers::from_strategy(strategy, TestRunner::deterministic());
+        let values: Vec<_> = (0..20).map(|i| answers.next_for(i)).collect();
+        assert!(values.iter().all(|&x| x < 20 && x % 2 == 0), "{values:?}");
+        assert_eq!(answers.take_calls(), (0..20).collect::<Vec<_>>());
+
+        let strategy = (0_u32..10).prop_map(|x| x * 2);
+        let mut other_answers = Answers::from_strategy(strategy, TestRunner::deterministic());
+        let other_values: Vec<_> = (0..20).map(|_| other_answers.next_for(())).collect();
+        assert_eq!(values, other_values);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializing_calls() {
+        let mut answers: Answers<usize, String> = Answers::from_fn(String::len);
+        answers.next_for("test".to_owned());
+        answers.next_for("other".to_owned());
+
+        let json = serde_json::to_value(answers.serialized_calls()).unwrap();
+        assert_eq!(json, serde_json::json!(["test", "other"]));
+        let calls: Vec<String> = serde_json::from_value(json).unwrap();
+        assert_eq!(calls, answers.take_calls());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn replaying_serialized_answers() {
+        let mut recorded: Answers<(), (u32, String)> = Answers::from_fn(|_| ());
+        recorded.next_for((1, "test".to_owned()));
+        recorded.next_for((2, "other".to_owned()));
+        let json = serde_json::to_vec(&recorded.serialized_calls()).unwrap();
+
+        let mut deserializer = serde_json::Deserializer::from_reader(json.as_slice());
+        let mut answers: Answers<(u32, String)> =
+            Answers::from_deserializer(&mut deserializer).unwrap();
+        assert_eq!(answers.next_for(()), (1, "test".to_owned()));
+        assert_eq!(answers.next_for(()), (2, "other".to_owned()));
+        answers.assert_exhausted();
+
+        let mut deserializer = serde_json::Deserializer::from_str("[1, \"test\"]");
+        Answers::<u32>::from_deserializer(&mut deserializer).unwrap_err();
+    }
+
     #[test]
     fn answers_channel_basics() {
         let (mut answers, mut sx) = Answers::channel();
@@ -410,6 +1262,46 @@ mod tests {
         assert_eq!(answers.next_for("bar"), 777);
     }
 
+    #[test]
+    fn answers_channel_with_expected_consumption() {
+        let (mut answers, mut sx) = Answers::channel();
+        sx.send_all([1, 2, 3]).expect_at_least(1).scope(|| {
+            assert_eq!(answers.next_for(()), 1);
+            assert_eq!(answers.next_for(()), 2);
+        });
+        // Remaining answers should be discarded.
+        sx.send(4).scope(|| assert_eq!(answers.next_for(()), 4));
+
+        sx.send_all([1, 2, 3])
+            .expect_at_least(1)
+            .expect_at_most(2)
+            .scope(|| assert_eq!(answers.next_for(()), 1));
+        sx.send_all([1, 2]).expect_at_most(0).discard();
+
+        let err = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            sx.send_all([1, 2, 3]).expect_at_most(1).scope(|| {
+                answers.next_for(());
+                answers.next_for(());
+            });
+        }))
+        .unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert_eq!(
+            err,
+            "2 answer(s) consumed from answers channel; expected at most 1"
+        );
+
+        let err = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _guard = sx.send_all([1, 2, 3]).expect_at_least(2);
+        }))
+        .unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert_eq!(
+            err,
+            "0 answer(s) consumed from answers channel; expected at least 2"
+        );
+    }
+
     #[test]
     #[should_panic(expected = "1 answer(s) not consumed")]
     fn partially_consumed_answers_channel() {
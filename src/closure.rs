@@ -0,0 +1,161 @@
+//! Mock state dispatching calls to ad hoc closures.
+
+use core::{any::Any, fmt};
+
+use crate::{called_function, stats::DispatchStats, CheckRealCall, Mock, Static, ThreadLocal};
+
+type BoxedStub<Args, R> = Box<dyn Fn(Args) -> R + Send>;
+
+/// Closure that can be used as a stub in [`ClosureMock`]. This trait is implemented
+/// for `Fn` closures with up to 3 args; `Args` is the tuple of arg types.
+pub trait Stub<Args, R>: Send + 'static {
+    #[doc(hidden)] // implementation detail
+    fn into_boxed(self) -> BoxedStub<Args, R>;
+}
+
+impl<R, F> Stub<(), R> for F
+where
+    F: Fn() -> R + Send + 'static,
+{
+    fn into_boxed(self) -> BoxedStub<(), R> {
+        Box::new(move |()| self())
+    }
+}
+
+impl<A, R, F> Stub<(A,), R> for F
+where
+    F: Fn(A) -> R + Send + 'static,
+{
+    fn into_boxed(self) -> BoxedStub<(A,), R> {
+        Box::new(move |(a,)| self(a))
+    }
+}
+
+impl<A, B, R, F> Stub<(A, B), R> for F
+where
+    F: Fn(A, B) -> R + Send + 'static,
+{
+    fn into_boxed(self) -> BoxedStub<(A, B), R> {
+        Box::new(move |(a, b)| self(a, b))
+    }
+}
+
+impl<A, B, C, R, F> Stub<(A, B, C), R> for F
+where
+    F: Fn(A, B, C) -> R + Send + 'static,
+{
+    fn into_boxed(self) -> BoxedStub<(A, B, C), R> {
+        Box::new(move |(a, b, c)| self(a, b, c))
+    }
+}
+
+/// Mock state dispatching calls to closures. This is useful for one-off stubs
+/// that do not warrant defining a dedicated mock state.
+///
+/// Mocked functions should reference one of `call*` methods of this state depending
+/// on the number of their args, e.g., `#[mock(using = "ClosureMock::call2")]` for a function
+/// with 2 args. Stubs are added using [`Self::stub()`] and are identified by the function name
+/// in the same way as in [`DispatchStats`], i.e., using a full path or its suffix.
+/// `ClosureMock` is [thread-local](ThreadLocal) and does not support partial mocking.
+///
+/// # Limitations
+///
+/// Arg and return types of mocked functions must be `'static`, since stubs are type-erased.
+/// In particular, this means that methods with a `self` receiver cannot be mocked,
+/// as well as `async` functions.
+///
+/// # Panics
+///
+/// Calls to mocked functions panic if there is no stub for the function, or if the stub
+/// has a different signature.
+///
+/// # Examples
+///
+/// ```
+/// use mimicry::{mock, ClosureMock, Mock};
+///
+/// #[mock(using = "ClosureMock::call1")]
+/// fn answer(value: u32) -> u32 { value }
+/// #[mock(using = "ClosureMock::call2")]
+/// fn concat(first: String, second: String) -> String { first + &second }
+///
+/// let _guard = ClosureMock::default()
+///     .stub("answer", |value: u32| value + 1)
+///     .stub("concat", |_: String, second: String| second)
+///     .set_as_mock();
+/// assert_eq!(answer(1), 2);
+/// assert_eq!(concat("Hello, ".into(), "world".into()), "world");
+/// ```
+#[derive(Default)]
+pub struct ClosureMock {
+    stubs: Vec<(&'static str, Box<dyn Any + Send>)>,
+}
+
+impl fmt::Debug for ClosureMock {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let functions: Vec<_> = self.stubs.iter().map(|(function, _)| *function).collect();
+        formatter
+            .debug_struct("ClosureMock")
+            .field("functions", &functions)
+            .finish()
+    }
+}
+
+impl CheckRealCall for ClosureMock {}
+
+impl Mock for ClosureMock {
+    type Base = Self;
+    type Shared = ThreadLocal<Self>;
+
+    fn instance() -> &'static Static<Self::Shared> {
+        static SHARED: Static<ThreadLocal<ClosureMock>> = Static::new();
+        &SHARED
+    }
+}
+
+impl ClosureMock {
+    /// Adds a stub for the specified `function`. If there are several matching stubs
+    /// for a function, the last added one is used.
+    #[must_use]
+    pub fn stub<Args: 'static, R: 'static>(
+        mut self,
+        function: &'static str,
+        stub: impl Stub<Args, R>,
+    ) -> Self {
+        self.stubs.push((function, Box::new(stub.into_boxed())));
+        self
+    }
+
+    fn get_stub<Args: 'static, R: 'static>(&self) -> &BoxedStub<Args, R> {
+        let function = called_function()
+            .expect("`ClosureMock` methods must only be called by mocked functions");
+        let (_, stub) = self
+            .stubs
+            .iter()
+            .rev()
+            .find(|(name, _)| DispatchStats::matches(function, name))
+            .unwrap_or_else(|| panic!("no stub for function `{function}`"));
+        stub.downcast_ref()
+            .unwrap_or_else(|| panic!("stub for function `{function}` has a different signature"))
+    }
+
+    /// Dispatches a call to a function without args.
+    pub fn call0<R: 'static>(&self) -> R {
+        self.get_stub::<(), R>()(())
+    }
+
+    /// Dispatches a call to a function with 1 arg.
+    pub fn call1<A: 'static, R: 'static>(&self, a: A) -> R {
+        self.get_stub::<(A,), R>()((a,))
+    }
+
+    /// Dispatches a call to a function with 2 args.
+    pub fn call2<A: 'static, B: 'static, R: 'static>(&self, a: A, b: B) -> R {
+        self.get_stub::<(A, B), R>()((a, b))
+    }
+
+    /// Dispatches a call to a function with 3 args.
+    pub fn call3<A: 'static, B: 'static, C: 'static, R: 'static>(&self, a: A, b: B, c: C) -> R {
+        self.get_stub::<(A, B, C), R>()((a, b, c))
+    }
+}
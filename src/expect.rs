@@ -0,0 +1,372 @@
+//! Expectations for mocked calls.
+
+use parking_lot::Mutex;
+
+use core::fmt;
+use std::{mem, thread};
+
+use crate::matchers::Matcher;
+
+type BoxedMatcher<Ctx> = Box<dyn Matcher<Ctx> + Send + Sync>;
+
+/// Expected number of calls.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct CallCount {
+    pub(crate) min: usize,
+    pub(crate) max: Option<usize>,
+}
+
+impl CallCount {
+    /// Default expected count (at least one call).
+    const DEFAULT: Self = Self { min: 1, max: None };
+    /// Any number of calls.
+    pub(crate) const ANY: Self = Self { min: 0, max: None };
+
+    pub(crate) fn contains(self, count: usize) -> bool {
+        count >= self.min && self.max.map_or(true, |max| count <= max)
+    }
+}
+
+impl fmt::Display for CallCount {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.min, self.max) {
+            (0, Some(0)) => formatter.write_str("never"),
+            (min, Some(max)) if min == max => write!(formatter, "exactly {min} time(s)"),
+            (0, Some(max)) => write!(formatter, "at most {max} time(s)"),
+            (min, Some(max)) => write!(formatter, "{min} to {max} times"),
+            (min, None) => write!(formatter, "at least {min} time(s)"),
+        }
+    }
+}
+
+/// Single expectation in [`Expectations`].
+///
+/// By default, an expectation requires at least one matching call; this can be changed using
+/// [`Self::times()`], [`Self::at_least()`], [`Self::at_most()`] and [`Self::never()`].
+pub struct Expectation<Ctx> {
+    name: String,
+    count: Option<CallCount>,
+    matcher: Option<BoxedMatcher<Ctx>>,
+}
+
+impl<Ctx> fmt::Debug for Expectation<Ctx> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("Expectation")
+            .field("name", &self.name)
+            .field("count", &self.count)
+            .field("has_matcher", &self.matcher.is_some())
+            .finish()
+    }
+}
+
+impl<Ctx> Expectation<Ctx> {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            count: None,
+            matcher: None,
+        }
+    }
+
+    /// Expects exactly `count` matching calls.
+    pub fn times(&mut self, count: usize) -> &mut Self {
+        self.count = Some(CallCount {
+            min: count,
+            max: Some(count),
+        });
+        self
+    }
+
+    /// Expects at least `count` matching calls. Can be combined with [`Self::at_most()`].
+    pub fn at_least(&mut self, count: usize) -> &mut Self {
+        self.count.get_or_insert(CallCount::ANY).min = count;
+        self
+    }
+
+    /// Expects at most `count` matching calls. Can be combined with [`Self::at_least()`].
+    pub fn at_most(&mut self, count: usize) -> &mut Self {
+        self.count.get_or_insert(CallCount::ANY).max = Some(count);
+        self
+    }
+
+    /// Expects no matching calls.
+    pub fn never(&mut self) -> &mut Self {
+        self.times(0)
+    }
+
+    /// Restricts the expectation to calls with the context satisfying the provided `matcher`
+    /// (e.g., one created using [`matchers`](crate::matchers)). Other calls are not counted
+    /// towards the expectation.
+    pub fn with<M>(&mut self, matcher: M) -> &mut Self
+    where
+        M: Matcher<Ctx> + Send + Sync + 'static,
+    {
+        self.matcher = Some(Box::new(matcher));
+        self
+    }
+
+    fn matches(&self, name: &str, ctx: &Ctx) -> bool {
+        self.name == name
+            && self
+                .matcher
+                .as_ref()
+                .map_or(true, |matcher| matcher.matches(ctx))
+    }
+
+    fn error(&self, calls: &[(String, Ctx)]) -> Option<String> {
+        let actual_count = calls
+            .iter()
+            .filter(|(name, ctx)| self.matches(name, ctx))
+            .count();
+        let count = self.count.unwrap_or(CallCount::DEFAULT);
+        if count.contains(actual_count) {
+            None
+        } else {
+            let qualifier = if self.matcher.is_some() {
+                " (with matcher)"
+            } else {
+                ""
+            };
+            Some(format!(
+                "`{name}`{qualifier}: expected {expected}, got {actual_count} call(s)",
+                name = self.name,
+                expected = count
+            ))
+        }
+    }
+}
+
+/// Collection of expectations for calls to mocked functions, which are automatically
+/// verified when dropped.
+///
+/// `Expectations` are intended to be placed in a [`Mock`](crate::Mock) state. Mock impls
+/// [record](Self::record()) calls together with a *context* (e.g., call args), and
+/// expectations are verified against the recorded contexts when the state is dropped,
+/// e.g., when the [`MockGuard`] is dropped. Verification can also be performed explicitly
+/// using [`Self::verify()`]. Expectations can be restricted to contexts satisfying
+/// a [`Matcher`]. Contexts recorded by [`Answers`] can be passed to `Expectations`
+/// using [`Self::record_calls()`].
+///
+/// If verification fails, it panics with a message listing all failed expectations.
+/// To not obscure the original panic, automatic verification is skipped if the thread
+/// is already panicking.
+///
+/// [`MockGuard`]: crate::MockGuard
+/// [`Answers`]: crate::Answers
+///
+/// # Examples
+///
+/// ```
+/// use mimicry::{matchers, mock, Expectations, Mock};
+///
+/// #[mock(using = "SearchMock")]
+/// fn search(haystack: &str, needle: char) -> Option<usize> {
+///     haystack.chars().position(|ch| ch == needle)
+/// }
+///
+/// #[derive(Default, Mock)]
+/// #[mock(auto_check)]
+/// struct SearchMock {
+///     expectations: Expectations<char>,
+/// }
+///
+/// impl SearchMock {
+///     fn search(&self, _haystack: &str, needle: char) -> Option<usize> {
+///         self.expectations.record("search", needle);
+///         None
+///     }
+/// }
+///
+/// let mut state = SearchMock::default();
+/// state.expectations.expect("search").times(2);
+/// state.expectations.expect("search").with(matchers::eq('?')).never();
+/// let guard = state.set_as_mock();
+/// assert_eq!(search("test", 't'), None);
+/// assert_eq!(search("test", 'e'), None);
+/// drop(guard); // verifies expectations
+/// ```
+///
+/// Verifying calls recorded by [`Answers`]:
+///
+/// ```
+/// # use mimicry::{matchers, Answers, Expectations};
+/// let mut answers = Answers::<usize, String>::from_values([1, 2, 3]);
+/// for word in ["test", "other", "test"] {
+///     answers.next_for(word.to_owned());
+/// }
+///
+/// let mut expectations = Expectations::<String>::default();
+/// expectations.expect("len").with(matchers::eq("test")).times(2);
+/// let is_long = matchers::pred(|word: &String| word.len() > 4);
+/// expectations.expect("len").with(is_long).at_most(1);
+/// expectations.record_calls("len", answers.take_calls());
+/// expectations.verify();
+/// ```
+///
+/// Failed expectations:
+///
+/// ```should_panic
+/// # use mimicry::Expectations;
+/// let mut expectations = Expectations::<()>::default();
+/// expectations.expect("search").at_least(2);
+/// expectations.record("search", ());
+/// drop(expectations); // panics: `search` was called only once
+/// ```
+pub struct Expectations<Ctx = ()> {
+    expectations: Vec<Expectation<Ctx>>,
+    /// Recorded calls: names of called functions together with call contexts.
+    calls: Mutex<Vec<(String, Ctx)>>,
+}
+
+impl<Ctx> fmt::Debug for Expectations<Ctx> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("Expectations")
+            .field("expectations", &self.expectations)
+            .field("call_count", &self.calls.lock().len())
+            .finish()
+    }
+}
+
+impl<Ctx> Default for Expectations<Ctx> {
+    fn default() -> Self {
+        Self {
+            expectations: Vec::new(),
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<Ctx> Expectations<Ctx> {
+    /// Adds a new expectation for calls with the specified name and returns a reference to it
+    /// allowing to customize the expectation.
+    #[allow(clippy::missing_panics_doc)] // false positive
+    pub fn expect(&mut self, name: impl Into<String>) -> &mut Expectation<Ctx> {
+        self.expectations.push(Expectation::new(name.into()));
+        self.expectations.last_mut().unwrap()
+    }
+
+    /// Records a call with the specified name and context.
+    pub fn record(&self, name: &str, ctx: Ctx) {
+        self.calls.lock().push((name.to_owned(), ctx));
+    }
+
+    /// Records calls with the specified name and contexts, e.g., ones obtained
+    /// from [`Answers::take_calls()`](crate::Answers::take_calls()).
+    pub fn record_calls(&self, name: &str, calls: impl IntoIterator<Item = Ctx>) {
+        let calls = calls.into_iter().map(|ctx| (name.to_owned(), ctx));
+        self.calls.lock().extend(calls);
+    }
+
+    /// Verifies all expectations against the recorded calls and clears both expectations
+    /// and calls.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the expectations is not satisfied.
+    pub fn verify(&mut self) {
+        let calls = mem::take(self.calls.get_mut());
+        let errors: Vec<_> = self
+            .expectations
+            .drain(..)
+            .filter_map(|exp| exp.error(&calls))
+            .collect();
+        assert!(
+            errors.is_empty(),
+            "Unsatisfied expectations:\n{}",
+            errors.join("\n")
+        );
+    }
+}
+
+impl<Ctx> Drop for Expectations<Ctx> {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            self.verify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matchers;
+
+    use static_assertions::assert_impl_all;
+    use std::panic;
+
+    assert_impl_all!(Expectations<String>: Send, Sync);
+
+    #[test]
+    fn call_count_ranges() {
+        let count = CallCount::DEFAULT;
+        assert!(!count.contains(0));
+        assert!(count.contains(1));
+        assert!(count.contains(100));
+        assert_eq!(count.to_string(), "at least 1 time(s)");
+
+        let count = CallCount {
+            min: 2,
+            max: Some(3),
+        };
+        assert!(!count.contains(1));
+        assert!(count.contains(2));
+        assert!(count.contains(3));
+        assert!(!count.contains(4));
+        assert_eq!(count.to_string(), "2 to 3 times");
+    }
+
+    #[test]
+    fn verifying_expectations() {
+        let mut expectations = Expectations::<u32>::default();
+        expectations.expect("test").times(3);
+        expectations
+            .expect("test")
+            .with(matchers::range(11..))
+            .at_most(1);
+        expectations
+            .expect("test")
+            .with(matchers::range(101..))
+            .at_most(1);
+        expectations.expect("test").with(matchers::eq(0)).never();
+        let is_odd = matchers::pred(|&x: &u32| x % 2 == 1);
+        let matcher = is_odd.and(matchers::range(..10));
+        expectations.expect("test").with(matcher).times(2);
+        expectations.expect("other").at_least(1).at_most(2);
+
+        expectations.record("test", 1);
+        expectations.record("test", 20);
+        expectations.record_calls("other", [0]);
+        expectations.record("test", 5);
+        expectations.record("unknown", 0);
+        expectations.verify();
+        assert!(expectations.expectations.is_empty());
+        assert!(expectations.calls.get_mut().is_empty());
+    }
+
+    #[test]
+    fn failed_expectations() {
+        let mut expectations = Expectations::<u32>::default();
+        expectations.expect("test").times(2);
+        expectations.expect("test").with(matchers::eq(0)).never();
+        expectations.expect("other");
+        expectations.record("test", 0);
+
+        let verify = panic::AssertUnwindSafe(move || expectations.verify());
+        let err = panic::catch_unwind(verify).unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert!(
+            err.contains("`test`: expected exactly 2 time(s), got 1 call(s)"),
+            "{err}"
+        );
+        assert!(
+            err.contains("`test` (with matcher): expected never, got 1 call(s)"),
+            "{err}"
+        );
+        assert!(
+            err.contains("`other`: expected at least 1 time(s), got 0 call(s)"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Global mock state wrapper without reentrancy support.
+
+use parking_lot::{MappedMutexGuard, Mutex, MutexGuard, RwLock};
+
+use core::mem;
+use std::sync::Arc;
+
+use crate::{stats::CallCounters, CountCalls, GetMock, Guard, LockMock, SetMock};
+
+/// Wrapper around [`Mock`](crate::Mock) state that shares the state across threads
+/// without support of reentrant calls.
+///
+/// This type rarely needs to be used directly; `#[derive(Mock)]` macro with a `#[mock(global)]`
+/// attribute on the container will set it up automatically.
+///
+/// Like [`Shared`](crate::Shared), this wrapper shares the state across threads, so it can be
+/// used in tests spawning threads. Unlike `Shared`, access to the state is synchronized
+/// via a plain (non-reentrant) mutex, which is a bit simpler and more efficient.
+/// Setting the state is synchronized in the same way as for `Shared`, and the same
+/// pitfalls apply.
+///
+/// # Pitfalls
+///
+/// **Recursive mock calls will deadlock.** The state is locked for the entire duration
+/// of a mock impl call, so a mocked function must not be called (directly or indirectly)
+/// from the mock impl. In particular, this means that the wrapper cannot be used
+/// for partial mocking / spying via [`CallReal`](crate::CallReal). Likewise, accessing the state
+/// via the guard (e.g., [`MockGuard::snapshot()`](crate::MockGuard::snapshot())) from a mock impl
+/// will deadlock.
+///
+/// # Examples
+///
+/// ```
+/// use mimicry::{mock, Mock};
+/// # use std::{sync::atomic::{AtomicU32, Ordering}, thread};
+///
+/// #[derive(Debug, Default, Mock)]
+/// #[mock(global, auto_check)]
+/// // ^ use the `Global` wrapper instead of the default thread-local one
+/// struct MockState {
+///     counter: AtomicU32,
+/// }
+///
+/// impl MockState {
+///     fn answer(&self) -> u32 {
+///         // Calling `answer()` here would deadlock!
+///         self.counter.fetch_add(1, Ordering::Relaxed)
+///     }
+/// }
+///
+/// #[mock(using = "MockState")]
+/// fn answer() -> u32 { 42 }
+///
+/// let mock_guard = MockState::default().set_as_mock();
+/// let threads: Vec<_> = (0..5).map(|_| thread::spawn(answer)).collect();
+/// let mut answers: Vec<_> = threads
+///     .into_iter()
+///     .map(|handle| handle.join().unwrap())
+///     .collect();
+/// answers.sort_unstable();
+/// assert_eq!(answers, [0, 1, 2, 3, 4]);
+/// assert_eq!(mock_guard.into_inner().counter.into_inner(), 5);
+/// ```
+#[derive(Debug)]
+pub struct Global<T> {
+    inner: Mutex<Option<T>>,
+    write_lock: Mutex<()>,
+    counters: RwLock<Option<Arc<CallCounters>>>,
+}
+
+impl<T> Default for Global<T> {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(None),
+            write_lock: Mutex::new(()),
+            counters: RwLock::new(None),
+        }
+    }
+}
+
+impl<T> CountCalls for Global<T> {
+    fn with_counters<R>(&self, action: impl FnOnce(&CallCounters) -> R) -> Option<R> {
+        self.counters.read().as_deref().map(action)
+    }
+}
+
+impl<'a, T: 'static> GetMock<'a, T> for Global<T> {
+    type Ref = MappedMutexGuard<'a, T>;
+
+    fn get(&'a self) -> Option<Self::Ref> {
+        MutexGuard::try_map(self.inner.lock(), Option::as_mut).ok()
+    }
+
+    fn is_set(&'a self) -> bool {
+        // The lock is not reentrant, so blocking on it would deadlock if called from a mock impl.
+        // If the lock is held, the state is being accessed, so we consider it set.
+        self.inner.try_lock().map_or(true, |inner| inner.is_some())
+    }
+}
+
+impl<'a, T: 'static> SetMock<'a, T> for Global<T> {
+    type Guard = GlobalGuard<'a, T>;
+
+    fn set(&self, state: T, call_limit: usize) -> GlobalGuard<'_, T> {
+        let guard = self.write_lock.lock();
+        let counters = Arc::new(CallCounters::new(call_limit));
+        *self.counters.write() = Some(Arc::clone(&counters));
+        *self.inner.lock() = Some(state);
+
+        GlobalGuard {
+            mock: self,
+            counters,
+            write_guard: Some(guard),
+        }
+    }
+}
+
+impl<'a, T: 'static> LockMock<'a, T> for Global<T> {
+    type EmptyGuard = MutexGuard<'a, ()>;
+
+    fn lock(&'a self) -> Self::EmptyGuard {
+        self.write_lock.lock()
+    }
+
+    fn try_lock(&'a self) -> Option<Self::EmptyGuard> {
+        self.write_lock.try_lock()
+    }
+}
+
+/// Exclusive lock on the [`Global`] mock state.
+#[derive(Debug)]
+pub struct GlobalGuard<'a, T> {
+    mock: &'a Global<T>,
+    counters: Arc<CallCounters>,
+    /// Exclusive lock on setting the state; `None` if the guard is leaked.
+    write_guard: Option<MutexGuard<'a, ()>>,
+}
+
+impl<T: 'static> Guard<T> for GlobalGuard<'_, T> {
+    fn counters(&self) -> &CallCounters {
+        &self.counters
+    }
+
+    fn leak(mut self) {
+        // Release the lock, but keep the state set.
+        self.write_guard = None;
+        mem::forget(self);
+    }
+
+    fn with<R>(&mut self, action: impl FnOnce(&mut T) -> R) -> R {
+        let mut locked = self.mock.inner.lock();
+        action(locked.as_mut().unwrap())
+    }
+
+    fn with_ref<R>(&self, action: impl FnOnce(&T) -> R) -> R {
+        // Blocks (and deadlocks if called from a mock impl) in the same way as mock calls.
+        // Using `try_lock()` instead would make this method fail spuriously if the state
+        // is accessed by a mock impl on another thread.
+        let locked = self.mock.inner.lock();
+        action(locked.as_ref().unwrap())
+    }
+
+    fn into_inner(self) -> T {
+        self.mock.inner.lock().take().unwrap()
+    }
+
+    fn try_into_inner(self) -> Result<T, Self> {
+        let state = self
+            .mock
+            .inner
+            .try_lock()
+            .and_then(|mut locked| locked.take());
+        state.ok_or(self)
+    }
+}
+
+impl<T> Drop for GlobalGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mock.inner.lock().take();
+        *self.mock.counters.write() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Static;
+
+    use static_assertions::assert_impl_all;
+    use std::cell::Cell;
+
+    assert_impl_all!(Global<Cell<u8>>: Send, Sync);
+    assert_impl_all!(Static<Global<Cell<u8>>>: Send, Sync);
+}
@@ -21,23 +21,26 @@
 //! # Overview
 //!
 //! 1. Define the state to hold data necessary for mocking / spying and derive
-//!   [`Mock`] for it. Requirements to the state are quite lax; it should be
-//!  `'static` and `Send`.
+//!    [`Mock`] for it. Requirements to the state are quite lax; it should be
+//!    `'static` and `Send`.
 //! 2. Place [`mock`] attrs referencing the state on the relevant functions, methods
-//!   and/or impl blocks.
+//!    and/or impl blocks.
 //! 3. Define mock logic as inherent methods of the mock state type. Such methods will be called
-//!   with the same args as the original functions + additional first arg for the mock state
-//!   reference. In the simplest case,
-//!   each mocked function / method gets its own method with the same name as the original,
-//!   but this can be customized.
+//!    with the same args as the original functions + additional first arg for the mock state
+//!    reference. In the simplest case,
+//!    each mocked function / method gets its own method with the same name as the original,
+//!    but this can be customized.
 //! 4. If the state needs to be mutated in mock logic, add a `#[mock(mut)]` attr on the state.
-//!   In this case, the mock method will receive `&`[`Mut`]`<Self>` wrapper as the first arg
-//!   instead of `&self`. If the mocked function / method is `async`, the mock implementation
-//!   will receive [`MockRef`]`<Self>` as the first arg.
+//!    In this case, the mock method will receive `&`[`Mut`]`<Self>` wrapper as the first arg
+//!    instead of `&self`. If the mocked function / method is `async`, the mock implementation
+//!    will receive [`MockRef`]`<Self>` as the first arg.
 //! 5. If the mock logic needs to be shared across threads, add a `#[mock(shared)]` attr
-//!   on the state. (By default, mocks are thread-local.)
+//!    on the state. (By default, mocks are thread-local.) If the state is accessed
+//!    from many threads concurrently, consider using `#[mock(shared = "rw")]` instead.
+//!    If mock impls never call mocked functions recursively, `#[mock(global)]`
+//!    is a simpler alternative that does not require the `shared` feature.
 //! 6. Set the mock state in tests using [`Mock::set_as_mock()`]. Inspect the state during tests
-//!   using [`MockGuard::with()`] and after tests using [`MockGuard::into_inner()`].
+//!    using [`MockGuard::with()`] and after tests using [`MockGuard::into_inner()`].
 //!
 //! # Features and limitations
 //!
@@ -60,10 +63,14 @@
 //! - You still cannot mock types from other crates.
 //! - Even if mocking logic does not use certain args, they need to be properly constructed,
 //!   which, depending on the case, may defy the reasons behind using mocks.
-//! - Very limited built-in matching / verifying (see [`Answers`]). With the chosen approach,
-//!   it is frequently easier and more transparent to just use `match` statements.
+//! - Very limited built-in matching / verifying (see [`Answers`], [`Expectations`],
+//!   [`CallSequence`] and [`matchers`]).
+//!   With the chosen approach, it is frequently easier and more transparent to just use
+//!   `match` statements.
 //!   As a downside, if matching logic needs to be customized across tests, it's (mostly)
 //!   up to the test writer.
+//! - The crate requires the standard library. Mocks can still be used in `no_std` crates
+//!   by only applying the [`mock`] attribute in tests; see [below](#usage-in-no_std-crates).
 //!
 //! # Crate features
 //!
@@ -71,7 +78,116 @@
 //!
 //! *(Off by default)*
 //!
-//! Enables mocks that [can be used](Shared) across multiple threads.
+//! Enables mocks that [can be used](Shared) across multiple threads, including ones
+//! [allowing concurrent access](SharedRw) to the state.
+//!
+//! ## `fold_states`
+//!
+//! *(Off by default)*
+//!
+//! Allows aggregating [`ThreadLocal`] mock states set on all threads via
+//! `ThreadLocal::fold_states()` and `Mock::fold_states()`, e.g., to collect metrics
+//! recorded by worker threads. To make reading states from other threads sound,
+//! per-thread states are wrapped in a reentrant mutex. The mutex is uncontended unless
+//! states are being folded, but locking it adds overhead to each call of a function mocked
+//! with a thread-local state.
+//!
+//! ## `mockall_compat`
+//!
+//! *(Off by default)*
+//!
+//! Provides the [`mockall_compat`] module with `mockall`-style expectation builders,
+//! which ease migrating tests from [`mockall`].
+//!
+//! [`mockall`]: https://docs.rs/mockall/
+//!
+//! ## `proptest`
+//!
+//! *(Off by default)*
+//!
+//! Allows creating [`Answers`] from [`proptest`] strategies, e.g., to test code against
+//! a wide range of mock responses.
+//!
+//! [`proptest`]: https://docs.rs/proptest/
+//!
+//! ## `serde`
+//!
+//! *(Off by default)*
+//!
+//! Allows serializing calls recorded by [`Answers`] using [`serde`], e.g.,
+//! for snapshot testing, and deserializing scripted answers, e.g., for replay-style tests.
+//!
+//! [`serde`]: https://crates.io/crates/serde
+//!
+//! ## `tracing`
+//!
+//! *(Off by default)*
+//!
+//! Logs whether each call to a mocked function is dispatched to the mock impl
+//! or to the real impl using [`tracing`] events on the `TRACE` level. Events include
+//! the function name and the mock state type, and are emitted with the `mimicry` target.
+//!
+//! [`tracing`]: https://docs.rs/tracing/
+//!
+//! # Disabling mocking
+//!
+//! The recommended way to use the [`mock`] attribute is conditionally, e.g.,
+//! `#[cfg_attr(test, mock(using = "..."))]`. In this case, non-test builds contain
+//! the original functions without any changes, so mocking has no runtime cost.
+//!
+//! If the attribute is applied unconditionally (e.g., because mocks are used in
+//! integration tests or benchmarks of another crate), mocking can be disabled
+//! by compiling with the `mimicry_disabled` [cfg flag], e.g.,
+//! `RUSTFLAGS="--cfg mimicry_disabled" cargo bench`. With this flag, the [`mock`] attribute
+//! emits the original function / method verbatim, so mocking has no runtime cost.
+//! Mock state types still compile, but setting a mock state has no effect on mocked functions
+//! (and mock impls are never called, so they may need to be marked with `#[allow(dead_code)]`).
+//! A cfg flag is used instead of a crate feature because features are additive:
+//! a feature disabling mocking would break all other crates using mocks in the same build.
+//!
+//! [cfg flag]: https://doc.rust-lang.org/rustc/command-line-arguments.html#--cfg-configure-the-compilation-environment
+//!
+//! # Usage in `no_std` crates
+//!
+//! The crate requires the standard library, and there is no `no_std` build of it.
+//! All mock state wrappers rely on `std` primitives: [`ThreadLocal`] is built on thread-local
+//! storage, [`Global`] and `Shared` on `std`-only locks, and call recording (e.g., in [`Answers`]
+//! or [`DispatchStats`]) on `std` threads and collections. Providing `no_std` alternatives
+//! (e.g., based on critical sections) would require a separate implementation of each wrapper
+//! and of the code generated by the macros, while mocks are only used in tests,
+//! which are built with the standard library anyway.
+//!
+//! Thus, to use mocks in a `no_std` crate, apply the [`mock`] attribute only in tests:
+//!
+//! ```
+//! // In the crate root: `#![cfg_attr(not(test), no_std)]`
+//! #[cfg_attr(test, mimicry::mock(using = "tests::ValueMock"))]
+//! pub fn answer() -> u32 {
+//!     42
+//! }
+//!
+//! #[cfg(test)]
+//! mod tests {
+//!     use mimicry::Mock;
+//!
+//!     #[derive(Default, Mock)]
+//!     #[mock(auto_check)]
+//!     pub(super) struct ValueMock;
+//!
+//!     impl ValueMock {
+//!         pub(super) fn answer(&self) -> u32 {
+//!             23
+//!         }
+//!     }
+//!
+//!     #[test]
+//!     fn mocking_in_no_std_crate() {
+//!         let _guard = ValueMock.set_as_mock();
+//!         assert_eq!(super::answer(), 23);
+//!     }
+//! }
+//! # assert_eq!(answer(), 42);
+//! ```
 //!
 //! # Examples
 //!
@@ -142,7 +258,7 @@
 //! to apply a mock to all methods in the block:
 //!
 //! ```
-//! # use mimicry::{mock, CheckRealCall, Mock};
+//! # use mimicry::{mock, Mock};
 //! struct Tested(String);
 //!
 //! #[mock(using = "TestMock")]
@@ -163,10 +279,10 @@
 //! }
 //!
 //! #[derive(Mock)]
+//! #[mock(auto_check)]
+//! // ^ Since we don't use partial mocking / spying, we indicate
+//! // this with the `auto_check` attr.
 //! struct TestMock { /* ... */ }
-//! // Since we don't use partial mocking / spying, we indicate
-//! // this with an empty `CheckRealCall` impl.
-//! impl CheckRealCall for TestMock {}
 //!
 //! impl TestMock {
 //!     fn len(&self, recv: &Tested) -> usize {
@@ -259,27 +375,66 @@
 #![warn(missing_debug_implementations, missing_docs, bare_trait_objects)]
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(clippy::must_use_candidate, clippy::module_name_repetitions)]
+// `#[test]` attrs in doctests are illustrative.
+#![allow(clippy::test_attr_in_doctest)]
 
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
+use parking_lot::RwLock;
+use pin_project_lite::pin_project;
 
-use core::{cell::RefCell, fmt, ops};
+use core::{
+    any::{self, Any, TypeId},
+    cell::{Cell, RefCell},
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    mem, ops,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use std::collections::HashMap;
 
 mod answers;
+mod closure;
+mod expect;
+mod global;
+pub mod matchers;
+#[cfg(feature = "mockall_compat")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mockall_compat")))]
+pub mod mockall_compat;
+mod scoped;
+mod sequence;
 #[cfg(feature = "shared")]
 mod shared;
+mod stats;
 mod tls;
 mod traits;
 
 #[cfg(feature = "shared")]
-pub use crate::shared::Shared;
+pub use crate::shared::{Shared, SharedRw};
 pub use crate::{
-    answers::{Answers, AnswersGuard, AnswersSender},
+    answers::{Answers, AnswersGuard, AnswersRouter, AnswersSender},
+    closure::{ClosureMock, Stub},
+    expect::{Expectation, Expectations},
+    global::Global,
+    scoped::{with_scoped_mock, ScopedMock},
+    sequence::CallSequence,
+    stats::DispatchStats,
     tls::ThreadLocal,
-    traits::{CallReal, CheckRealCall, GetMock, RealCallGuard, RealCallSwitch},
+    traits::{
+        CallReal, CheckRealCall, CountCalls, GetMock, MockVariant, RealCallGuard, RealCallSwitch,
+    },
 };
 pub use mimicry_derive::{mock, CallReal, Mock};
 
-use crate::traits::{Guard, LockMock, SetMock, Wrap};
+#[doc(hidden)] // used by the `mock` and `Mock` macros
+pub use crate::scoped::{call_scoped_mock, with_scoped_state, ScopedStatic};
+
+use crate::{
+    stats::CallCounters,
+    traits::{Guard, LockMock, SetMock, Wrap},
+};
 
 /// Wrapper that allows creating `static`s with mock state.
 #[derive(Debug)]
@@ -296,6 +451,69 @@ impl<T> Static<T> {
     }
 }
 
+impl<T> Default for Static<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Type-indexed collection of [`Static`]s. Used for generic mock states and named instances,
+/// since a `static` cannot depend on generic params.
+///
+/// Entries are only added once per key, so lookups of existing entries take a read lock
+/// and do not contend with each other. Instances are intentionally not cached in the code
+/// generated for mocked functions: for non-generic states, [`Mock::instance()`] already
+/// returns a plain `static`, and a `static` / `thread_local!` cache for generic states
+/// cannot depend on generic params either.
+#[doc(hidden)] // used by the `Mock` derive macro
+pub struct StaticMap {
+    // Entries are only added once per key, so most accesses only need a read lock.
+    inner: Lazy<RwLock<HashMap<StaticKey, &'static (dyn Any + Send + Sync)>>>,
+}
+
+/// Key in a [`StaticMap`]: the type of the stored value + the instance name.
+type StaticKey = (TypeId, &'static str);
+
+impl fmt::Debug for StaticMap {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.debug_struct("StaticMap").finish_non_exhaustive()
+    }
+}
+
+impl Default for StaticMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StaticMap {
+    /// Creates a new instance.
+    pub const fn new() -> Self {
+        Self {
+            inner: Lazy::new(RwLock::default),
+        }
+    }
+
+    /// Returns a [`Static`] for the specified type, creating it if necessary.
+    /// Created instances are leaked, which is fine since there is a bounded number of them.
+    pub fn get<T: Send + Sync + 'static>(&self) -> &'static Static<T> {
+        self.get_named("")
+    }
+
+    /// Returns a named [`Static`] for the specified type, creating it if necessary.
+    #[allow(clippy::missing_panics_doc)] // false positive
+    pub fn get_named<T: Send + Sync + 'static>(&self, name: &'static str) -> &'static Static<T> {
+        let key = (TypeId::of::<T>(), name);
+        let instance = self.inner.read().get(&key).copied();
+        let instance = instance.unwrap_or_else(|| {
+            let mut map = self.inner.write();
+            *map.entry(key)
+                .or_insert_with(|| Box::leak(Box::new(Static::<T>::new())))
+        });
+        instance.downcast_ref().unwrap()
+    }
+}
+
 impl<'a, T, S> GetMock<'a, T> for Static<S>
 where
     S: GetMock<'a, T> + Default,
@@ -306,6 +524,16 @@ where
         let cell = self.cell.get_or_init(S::default);
         cell.get()
     }
+
+    fn is_set(&'a self) -> bool {
+        self.cell.get_or_init(S::default).is_set()
+    }
+}
+
+impl<S: CountCalls + Default> CountCalls for Static<S> {
+    fn with_counters<R>(&self, action: impl FnOnce(&CallCounters) -> R) -> Option<R> {
+        self.cell.get_or_init(S::default).with_counters(action)
+    }
 }
 
 /// State of a mock.
@@ -320,22 +548,129 @@ pub trait Mock: Sized {
     #[doc(hidden)]
     type Shared: GetMock<'static, Self::Base>
         + SetMock<'static, Self::Base>
+        + CountCalls
         + 'static
         + Default
         + Send
         + Sync;
 
+    /// Whether to collect [`DispatchStats`] for this state.
+    #[doc(hidden)]
+    const DISPATCH_STATS: bool = false;
+
     /// Returns the shared wrapper around this state.
     #[doc(hidden)]
     fn instance() -> &'static Static<Self::Shared>;
 
+    /// Returns the shared wrapper around a named instance of this state. Named instances
+    /// are independent from each other and from the [default one](Self::instance()).
+    #[doc(hidden)]
+    fn instance_named(name: &'static str) -> &'static Static<Self::Shared> {
+        static NAMED: StaticMap = StaticMap::new();
+        NAMED.get_named::<Self::Shared>(name)
+    }
+
     /// Sets the mock state and returns an exclusive guard to the shared state.
     #[must_use = "mock is only set until the returned `MockGuard` is dropped"]
     fn set_as_mock(self) -> MockGuard<Self> {
-        let cell = Self::instance().cell.get_or_init(<Self::Shared>::default);
-        MockGuard {
-            inner: cell.set(self.into()),
-        }
+        MockGuard::new(Self::instance(), self, usize::MAX)
+    }
+
+    /// Sets the mock state for a single call. After the first call is dispatched to the mock impl,
+    /// the state behaves as if it were unset: further calls are routed to the real impls,
+    /// and [`Self::is_mock_set()`] returns `false`. Unlike [`Self::set_as_mock()`], the state
+    /// is retained by the returned guard, so it can be inspected after the call.
+    ///
+    /// Calls delegated to the real impl (e.g., via [`CallReal`]) do not count towards the limit.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, Mock};
+    /// #[mock(using = "ValueMock")]
+    /// fn answer() -> usize { 42 }
+    ///
+    /// #[derive(Default, Mock)]
+    /// #[mock(auto_check)]
+    /// struct ValueMock;
+    ///
+    /// impl ValueMock {
+    ///     fn answer(&self) -> usize { 23 }
+    /// }
+    ///
+    /// let guard = ValueMock.set_for_next_call();
+    /// assert_eq!(answer(), 23);
+    /// assert_eq!(answer(), 42);
+    /// assert!(!ValueMock::is_mock_set());
+    /// assert_eq!(guard.call_count(), 1);
+    /// ```
+    #[must_use = "mock is only set until the returned `MockGuard` is dropped"]
+    fn set_for_next_call(self) -> MockGuard<Self> {
+        MockGuard::new(Self::instance(), self, 1)
+    }
+
+    /// Sets the [default](Default) state as the mock state and returns an exclusive guard to it.
+    /// Equivalent to `Self::default().set_as_mock()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, Mock};
+    /// #[mock(using = "ValueMock")]
+    /// fn answer() -> usize { 42 }
+    ///
+    /// #[derive(Default, Mock)]
+    /// #[mock(auto_check)]
+    /// struct ValueMock(usize);
+    ///
+    /// impl ValueMock {
+    ///     fn answer(&self) -> usize { self.0 }
+    /// }
+    ///
+    /// let _guard = ValueMock::set_default();
+    /// assert_eq!(answer(), 0);
+    /// ```
+    #[must_use = "mock is only set until the returned `MockGuard` is dropped"]
+    fn set_default() -> MockGuard<Self>
+    where
+        Self: Default,
+    {
+        Self::default().set_as_mock()
+    }
+
+    /// Sets the mock state as a named instance and returns an exclusive guard to it.
+    ///
+    /// Named instances allow using several independent instances of the same mock state type.
+    /// A named instance is only used by functions / methods that specify the same name
+    /// in the `instance` attr of the [`mock`] macro; the other mocked functions
+    /// are not affected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, Mock};
+    /// #[mock(using = "ValueMock", instance = "first")]
+    /// fn first() -> usize { 0 }
+    /// #[mock(using = "ValueMock", instance = "second")]
+    /// fn second() -> usize { 0 }
+    ///
+    /// #[derive(Mock)]
+    /// #[mock(auto_check)]
+    /// struct ValueMock(usize);
+    ///
+    /// impl ValueMock {
+    ///     fn first(&self) -> usize { self.0 }
+    ///     fn second(&self) -> usize { self.0 }
+    /// }
+    ///
+    /// let _first_guard = ValueMock(1).set_as_named_mock("first");
+    /// let _second_guard = ValueMock(2).set_as_named_mock("second");
+    /// assert_eq!(first(), 1);
+    /// assert_eq!(second(), 2);
+    /// ```
+    #[must_use = "mock is only set until the returned `MockGuard` is dropped"]
+    fn set_as_named_mock(self, name: &'static str) -> MockGuard<Self> {
+        MockGuard::new(Self::instance_named(name), self, usize::MAX)
     }
 
     /// Locks write access to the mock state without setting the state. This is useful
@@ -350,6 +685,629 @@ pub trait Mock: Sized {
             _inner: cell.lock(),
         }
     }
+
+    /// Attempts to lock write access to the mock state without blocking. Returns `None`
+    /// if the state is currently set or locked elsewhere. This allows tests to fail
+    /// with a clear message instead of hanging, e.g., if they deadlock against each other.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, Mock};
+    /// #[mock(using = "ValueMock")]
+    /// fn answer() -> usize { 42 }
+    ///
+    /// #[derive(Mock)]
+    /// #[mock(global, auto_check)]
+    /// struct ValueMock(usize);
+    ///
+    /// impl ValueMock {
+    ///     fn answer(&self) -> usize { self.0 }
+    /// }
+    ///
+    /// let guard = ValueMock(23).set_as_mock();
+    /// assert!(ValueMock::try_lock().is_none());
+    /// drop(guard);
+    /// let _guard = ValueMock::try_lock().expect("`ValueMock` is locked");
+    /// assert_eq!(answer(), 42);
+    /// ```
+    fn try_lock() -> Option<EmptyGuard<Self>>
+    where
+        Self::Shared: LockMock<'static, Self::Base>,
+    {
+        let cell = Self::instance().cell.get_or_init(<Self::Shared>::default);
+        let inner = cell.try_lock()?;
+        Some(EmptyGuard { _inner: inner })
+    }
+
+    /// Checks whether the mock state of this type is currently set, without dispatching
+    /// any calls to it. This is useful in helper code shared among tests that do and do not
+    /// set mocks.
+    ///
+    /// For [`ThreadLocal`] mocks, only the state set on the current thread is considered.
+    /// Shared mocks may be set or unset concurrently by other threads, so the returned value
+    /// may be outdated by the time it is used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, Mock};
+    /// #[mock(using = "ValueMock")]
+    /// fn answer() -> usize { 42 }
+    ///
+    /// #[derive(Default, Mock)]
+    /// #[mock(auto_check)]
+    /// struct ValueMock;
+    ///
+    /// impl ValueMock {
+    ///     fn answer(&self) -> usize { 23 }
+    /// }
+    ///
+    /// assert!(!ValueMock::is_mock_set());
+    /// let guard = ValueMock.set_as_mock();
+    /// assert!(ValueMock::is_mock_set());
+    /// assert_eq!(guard.call_count(), 0);
+    /// drop(guard);
+    /// assert!(!ValueMock::is_mock_set());
+    /// ```
+    fn is_mock_set() -> bool {
+        let instance = Self::instance();
+        GetMock::is_set(instance) && !CountCalls::is_exhausted(instance)
+    }
+
+    /// Folds the [thread-local](ThreadLocal) states of this type currently set on all threads.
+    /// Requires the `fold_states` crate feature.
+    /// See [`ThreadLocal::fold_states()`] for details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, Mock, Mut};
+    /// # use std::{sync::{Arc, Barrier}, thread};
+    /// #[mock(using = "CallCounter")]
+    /// fn work() {}
+    ///
+    /// #[derive(Default, Mock)]
+    /// #[mock(mut)]
+    /// struct CallCounter(usize);
+    ///
+    /// impl CallCounter {
+    ///     fn work(this: &Mut<Self>) {
+    ///         this.borrow().0 += 1;
+    ///     }
+    /// }
+    ///
+    /// let barrier = Arc::new(Barrier::new(5));
+    /// let handles: Vec<_> = (0..4)
+    ///     .map(|i| {
+    ///         let barrier = Arc::clone(&barrier);
+    ///         thread::spawn(move || {
+    ///             let _guard = CallCounter::default().set_as_mock();
+    ///             (0..=i).for_each(|_| work());
+    ///             barrier.wait(); // wait until the states are set...
+    ///             barrier.wait(); // ...and then folded
+    ///         })
+    ///     })
+    ///     .collect();
+    ///
+    /// barrier.wait();
+    /// let total_calls = CallCounter::fold_states(0, |acc, state| acc + state.0);
+    /// assert_eq!(total_calls, 1 + 2 + 3 + 4);
+    /// barrier.wait();
+    /// handles.into_iter().for_each(|handle| handle.join().unwrap());
+    /// ```
+    #[cfg(feature = "fold_states")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "fold_states")))]
+    fn fold_states<R>(init: R, mut fold: impl FnMut(R, &Self) -> R) -> R
+    where
+        Self: Mock<Shared = ThreadLocal<<Self as Mock>::Base>>,
+        Self::Base: Send + 'static,
+    {
+        let instance = Self::instance().cell.get_or_init(ThreadLocal::default);
+        instance.fold_states(init, |acc, state| {
+            state.with_inner(|state| fold(acc, state))
+        })
+    }
+
+    /// Async version of [`with_mock()`]. The returned future sets this state
+    /// as the mock state when first polled, drives `future` to completion and resolves
+    /// to its output together with the mock state.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use async_std::task::block_on;
+    /// # use mimicry::{mock, Mock, MockRef};
+    /// #[mock(using = "ValueMock")]
+    /// async fn answer() -> usize { 42 }
+    ///
+    /// #[derive(Mock)]
+    /// #[mock(auto_check)]
+    /// struct ValueMock(usize);
+    ///
+    /// impl ValueMock {
+    ///     async fn answer(this: MockRef<Self>) -> usize {
+    ///         this.with(|this| this.0)
+    ///     }
+    /// }
+    ///
+    /// # block_on(async {
+    /// let (value, state) = ValueMock(23).async_mock_scope(answer()).await;
+    /// assert_eq!(value, 23);
+    /// assert_eq!(state.0, 23);
+    /// assert_eq!(answer().await, 42);
+    /// # });
+    /// ```
+    fn async_mock_scope<Fut: Future>(self, future: Fut) -> MockScope<Self, Fut> {
+        MockScope {
+            state: Some(self),
+            guard: None,
+            future: Box::pin(future),
+        }
+    }
+}
+
+/// Sets `state` as the mock state, runs the provided closure and returns its output
+/// together with the mock state. This is a shortcut for [`Mock::set_as_mock()`]
+/// followed by [`MockGuard::into_inner()`]; the mock is unset even if the closure panics.
+///
+/// # Examples
+///
+/// ```
+/// # use mimicry::{mock, Mock, Mut};
+/// #[mock(using = "CounterMock")]
+/// fn answer() -> usize { 42 }
+///
+/// #[derive(Default, Mock)]
+/// #[mock(mut)]
+/// struct CounterMock(usize);
+///
+/// impl CounterMock {
+///     fn answer(this: &Mut<Self>) -> usize {
+///         this.borrow().0 += 1;
+///         this.borrow().0
+///     }
+/// }
+///
+/// let (sum, state) = mimicry::with_mock(CounterMock::default(), || answer() + answer());
+/// assert_eq!(sum, 3);
+/// assert_eq!(state.0, 2);
+/// assert_eq!(answer(), 42); // the mock is no longer set
+/// ```
+pub fn with_mock<T: Mock, R>(state: T, action: impl FnOnce() -> R) -> (R, T) {
+    let guard = state.set_as_mock();
+    let output = action();
+    (output, guard.into_inner())
+}
+
+/// Future returned by [`Mock::async_mock_scope()`].
+#[must_use = "futures do nothing unless polled"]
+pub struct MockScope<T: Mock, Fut> {
+    state: Option<T>,
+    guard: Option<MockGuard<T>>,
+    future: Pin<Box<Fut>>,
+}
+
+impl<T: Mock, Fut> fmt::Debug for MockScope<T, Fut> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("MockScope")
+            .field("is_set", &self.guard.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+// The wrapped future is boxed, and the state is never pinned.
+impl<T: Mock, Fut> Unpin for MockScope<T, Fut> {}
+
+impl<T: Mock, Fut: Future> Future for MockScope<T, Fut> {
+    type Output = (Fut::Output, T);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if let Some(state) = this.state.take() {
+            this.guard = Some(state.set_as_mock());
+        }
+        let output = match this.future.as_mut().poll(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+        let guard = this
+            .guard
+            .take()
+            .expect("`MockScope` polled after completion");
+        Poll::Ready((output, guard.into_inner()))
+    }
+}
+
+thread_local! {
+    static STRICT_MODE: Cell<usize> = const { Cell::new(0) };
+    static BYPASSED_MOCKS: Cell<usize> = const { Cell::new(0) };
+    static CALLED_FUNCTION: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+/// Enables strict mode for the current thread until the returned guard is dropped.
+///
+/// In strict mode, calls to functions marked with `#[mock(strict)]` panic instead
+/// of executing the real implementation, i.e., if the mock state is not set or if the mock
+/// has delegated the call to the real implementation (e.g., via [`CallReal::call_real()`]).
+/// This allows ensuring that all such calls in a test are routed to the mock impls.
+///
+/// Strict mode is thread-local: it does not affect calls in other threads, even if
+/// they use [shared mocks](Shared). Guards can be nested; strict mode is disabled
+/// once all guards are dropped.
+///
+/// # Examples
+///
+/// ```should_panic
+/// # use mimicry::{mock, CheckRealCall, Mock};
+/// #[mock(using = "ValueMock", strict)]
+/// fn answer() -> usize { 42 }
+///
+/// #[derive(Default, Mock)]
+/// #[mock(auto_check)]
+/// struct ValueMock;
+///
+/// impl ValueMock {
+///     fn answer(&self) -> usize {
+///         23
+///     }
+/// }
+///
+/// let _strict = mimicry::enable_strict_mode();
+/// let guard = ValueMock.set_as_mock();
+/// assert_eq!(answer(), 23);
+/// drop(guard);
+/// answer(); // panics: the mock is no longer set
+/// ```
+pub fn enable_strict_mode() -> StrictModeGuard {
+    STRICT_MODE.with(|mode| mode.set(mode.get() + 1));
+    StrictModeGuard {
+        _not_send: PhantomData,
+    }
+}
+
+#[doc(hidden)] // used by the `mock` attribute macro
+pub fn check_strict_mode(function: &str) {
+    let is_strict = STRICT_MODE.with(|mode| mode.get() > 0);
+    assert!(
+        !is_strict,
+        "Call to `{function}` was not routed to a mock in strict mode"
+    );
+}
+
+#[doc(hidden)] // used by the `mock` attribute macro
+#[inline]
+pub fn trace_call(function: &str, state: &str, mocked: bool) {
+    #[cfg(feature = "tracing")]
+    if mocked {
+        tracing::trace!(function, state, "dispatching call to mock impl");
+    } else {
+        tracing::trace!(function, state, "calling real impl");
+    }
+    #[cfg(not(feature = "tracing"))]
+    let _ = (function, state, mocked);
+}
+
+/// Guard returned by [`enable_strict_mode()`]. Strict mode is in effect until the guard
+/// is dropped.
+#[derive(Debug)]
+#[must_use = "strict mode is disabled when the guard is dropped"]
+pub struct StrictModeGuard {
+    // Strict mode is thread-local, so the guard must not be sent to other threads.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for StrictModeGuard {
+    fn drop(&mut self) {
+        STRICT_MODE.with(|mode| mode.set(mode.get() - 1));
+    }
+}
+
+/// Executes the provided action with all mocks bypassed for the current thread. That is,
+/// all mocked functions called within the action execute their real implementations,
+/// regardless of the set mock states. Calls in other threads are not affected, even if
+/// they use [shared mocks](Shared).
+///
+/// Bypassed calls are not dispatched to mock states at all, so they are not counted
+/// in [`MockGuard::call_count()`] and do not interact with [`CallReal`] switches.
+/// Calls to functions marked with `#[mock(strict)]` still panic in [strict mode](enable_strict_mode()).
+/// For async functions, routing is performed when the returned future is first polled,
+/// so the future must be polled within the action for the bypass to have effect.
+///
+/// # Examples
+///
+/// ```
+/// # use mimicry::{mock, CheckRealCall, Mock};
+/// #[mock(using = "ValueMock")]
+/// fn answer() -> usize { 42 }
+///
+/// #[derive(Default, Mock)]
+/// #[mock(auto_check)]
+/// struct ValueMock;
+///
+/// impl ValueMock {
+///     fn answer(&self) -> usize {
+///         23
+///     }
+/// }
+///
+/// let guard = ValueMock.set_as_mock();
+/// assert_eq!(answer(), 23);
+/// assert_eq!(mimicry::bypass_mocks(answer), 42);
+/// assert_eq!(answer(), 23);
+/// assert_eq!(guard.call_count(), 2);
+/// ```
+pub fn bypass_mocks<R>(action: impl FnOnce() -> R) -> R {
+    /// Guard restoring routing to mocks on drop, including if the action panics.
+    struct BypassGuard(());
+
+    impl Drop for BypassGuard {
+        fn drop(&mut self) {
+            BYPASSED_MOCKS.with(|bypassed| bypassed.set(bypassed.get() - 1));
+        }
+    }
+
+    BYPASSED_MOCKS.with(|bypassed| bypassed.set(bypassed.get() + 1));
+    let _guard = BypassGuard(());
+    action()
+}
+
+fn are_mocks_bypassed() -> bool {
+    BYPASSED_MOCKS.with(|bypassed| bypassed.get() > 0)
+}
+
+/// Returns the path of the mocked function whose mock impl is being executed in the current
+/// thread, or `None` if no mock impl is being executed. Paths have the same format as
+/// in [`DispatchStats`], e.g. `my_crate::module::function`.
+///
+/// This allows a single mock impl to handle several functions and distinguish among them,
+/// e.g., for logging. If mock impls are nested (e.g., a mock impl calls another mocked function),
+/// the innermost function is returned. For async functions, the function is available
+/// while the future returned by the mock impl is polled.
+///
+/// # Examples
+///
+/// ```
+/// # use mimicry::{mock, CheckRealCall, Mock};
+/// # use std::sync::Mutex;
+/// #[mock(using = "LogMock::log")]
+/// fn answer() -> usize { 42 }
+/// #[mock(using = "LogMock::log")]
+/// fn other_answer() -> usize { 23 }
+///
+/// #[derive(Default, Mock)]
+/// #[mock(auto_check)]
+/// struct LogMock {
+///     called: Mutex<Vec<&'static str>>,
+/// }
+///
+/// impl LogMock {
+///     fn log(&self) -> usize {
+///         let function = mimicry::called_function().unwrap();
+///         self.called.lock().unwrap().push(function);
+///         0
+///     }
+/// }
+///
+/// let guard = LogMock::default().set_as_mock();
+/// assert_eq!(answer() + other_answer(), 0);
+/// let called = guard.into_inner().called.into_inner().unwrap();
+/// assert!(called[0].ends_with("::answer"));
+/// assert!(called[1].ends_with("::other_answer"));
+/// assert_eq!(mimicry::called_function(), None);
+/// ```
+pub fn called_function() -> Option<&'static str> {
+    CALLED_FUNCTION.with(Cell::get)
+}
+
+/// Guard setting the function returned by [`called_function()`] until it is dropped.
+#[doc(hidden)] // used by the `mock` attribute macro
+#[derive(Debug)]
+pub struct CalledFunctionGuard {
+    previous: Option<&'static str>,
+    // The function is set for the current thread, so the guard must not be sent to other threads.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl CalledFunctionGuard {
+    pub fn new(function: &'static str) -> Self {
+        let previous = CALLED_FUNCTION.with(|called| called.replace(Some(function)));
+        Self {
+            previous,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Drop for CalledFunctionGuard {
+    fn drop(&mut self) {
+        CALLED_FUNCTION.with(|called| called.set(self.previous));
+    }
+}
+
+pin_project! {
+    /// Future setting the function returned by [`called_function()`] each time it is polled.
+    #[doc(hidden)] // used by the `mock` attribute macro
+    #[must_use = "futures do nothing unless polled"]
+    pub struct CalledFunctionScope<Fut> {
+        function: &'static str,
+        #[pin]
+        future: Fut,
+    }
+}
+
+impl<Fut> fmt::Debug for CalledFunctionScope<Fut> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("CalledFunctionScope")
+            .field("function", &self.function)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Fut: Future> CalledFunctionScope<Fut> {
+    pub fn new(function: &'static str, future: Fut) -> Self {
+        Self { function, future }
+    }
+}
+
+impl<Fut: Future> Future for CalledFunctionScope<Fut> {
+    type Output = Fut::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _guard = CalledFunctionGuard::new(this.function);
+        this.future.poll(cx)
+    }
+}
+
+/// Number of currently set mock states across all mock types and threads.
+static ACTIVE_MOCKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Checks whether the type param of a mocked function equals the type pinned
+/// via the `for` attr of the `mock` macro.
+#[doc(hidden)] // used by the `mock` attribute macro
+#[inline]
+pub fn is_pinned_type<T: 'static, P: 'static>() -> bool {
+    TypeId::of::<T>() == TypeId::of::<P>()
+}
+
+/// Converts a value between a generic type and the same type with the type param replaced
+/// by the pinned type. The conversion always succeeds since it is only performed after
+/// [`is_pinned_type()`] has returned `true`.
+#[doc(hidden)] // used by the `mock` attribute macro
+pub fn cast_pinned<S: 'static, D: 'static>(value: S) -> D {
+    let mut value = Some(value);
+    let value: &mut dyn Any = &mut value;
+    value
+        .downcast_mut::<Option<D>>()
+        .and_then(Option::take)
+        .expect("mismatch between the type param and the pinned type")
+}
+
+/// Reference version of [`cast_pinned()`].
+#[doc(hidden)] // used by the `mock` attribute macro
+pub fn cast_pinned_ref<S: 'static, D: 'static>(value: &S) -> &D {
+    let value: &dyn Any = value;
+    value
+        .downcast_ref()
+        .expect("mismatch between the type param and the pinned type")
+}
+
+/// Mutable reference version of [`cast_pinned()`].
+#[doc(hidden)] // used by the `mock` attribute macro
+pub fn cast_pinned_mut<S: 'static, D: 'static>(value: &mut S) -> &mut D {
+    let value: &mut dyn Any = value;
+    value
+        .downcast_mut()
+        .expect("mismatch between the type param and the pinned type")
+}
+
+/// Wrapper around a mock state field used to format the field in the `Debug` impl
+/// generated by the `Mock` derive macro if the field type implements `Debug`.
+/// Uses autoref-based specialization with [`AsDebug`] / [`AsDebugFallback`].
+#[doc(hidden)] // used by the `Mock` derive macro
+#[derive(Debug)]
+pub struct MaybeDebug<'a, T>(pub &'a T);
+
+/// Formatting for [`MaybeDebug`] wrapping a `Debug`-able value.
+#[doc(hidden)] // used by the `Mock` derive macro
+pub trait AsDebug {
+    fn as_debug(&self) -> Option<&dyn fmt::Debug>;
+}
+
+impl<T: fmt::Debug> AsDebug for MaybeDebug<'_, T> {
+    fn as_debug(&self) -> Option<&dyn fmt::Debug> {
+        Some(self.0)
+    }
+}
+
+/// Fallback for [`MaybeDebug`] wrapping a value that does not implement `Debug`.
+#[doc(hidden)] // used by the `Mock` derive macro
+pub trait AsDebugFallback {
+    fn as_debug(&self) -> Option<&dyn fmt::Debug> {
+        None
+    }
+}
+
+impl<T> AsDebugFallback for &MaybeDebug<'_, T> {}
+
+/// Returns a reference to the mock state if it is set and has not exhausted its call limit
+/// (e.g., if it is set via [`Mock::set_for_next_call()`]).
+#[doc(hidden)] // used by the `mock` attribute macro
+#[inline]
+pub fn get_mock<T: Mock>(
+    instance: &'static Static<T::Shared>,
+) -> Option<<T::Shared as GetMock<'static, T::Base>>::Ref> {
+    if instance.is_exhausted() {
+        None
+    } else {
+        instance.get()
+    }
+}
+
+/// Records a dispatched call if the state collects [`DispatchStats`]. The called function
+/// is identified by `fn_id`, a function item nested in it.
+#[doc(hidden)] // used by the `mock` attribute macro
+#[inline]
+pub fn record_dispatch<T: Mock, F>(instance: &'static Static<T::Shared>, fn_id: F, mocked: bool) {
+    if T::DISPATCH_STATS {
+        instance.record_dispatch(enclosing_function_path(fn_id), mocked);
+    }
+}
+
+/// Returns the path of the function enclosing the `fn_id` function item.
+fn enclosing_function_path<F>(_fn_id: F) -> &'static str {
+    let path = any::type_name::<F>();
+    path.rsplit_once("::").map_or(path, |(parent, _)| parent)
+}
+
+/// Checks whether any mock state may be set and mocks are not [bypassed](bypass_mocks())
+/// for the current thread. Allows mocked functions to skip mock routing with a single atomic load
+/// if no mocks are set.
+#[doc(hidden)] // used by the `mock` attribute macro
+#[inline]
+pub fn has_active_mocks() -> bool {
+    ACTIVE_MOCKS.load(Ordering::Relaxed) > 0 && !are_mocks_bypassed()
+}
+
+/// Selects the mocked or the original version of a function depending on whether
+/// mocking is [disabled](crate#disabling-mocking). The cfg flag is checked here rather than
+/// in the generated code so that it is defined for a single crate.
+#[cfg(not(mimicry_disabled))]
+#[doc(hidden)] // used by the `mock` attribute macro
+#[macro_export]
+macro_rules! select_mocked_item {
+    (mocked: { $($mocked:tt)* } original: { $($original:tt)* }) => {
+        $($mocked)*
+    };
+}
+
+#[cfg(mimicry_disabled)]
+#[doc(hidden)] // used by the `mock` attribute macro
+#[macro_export]
+macro_rules! select_mocked_item {
+    (mocked: { $($mocked:tt)* } original: { $($original:tt)* }) => {
+        $($original)*
+    };
+}
+
+/// Token tracking a set mock state in [`ACTIVE_MOCKS`].
+#[derive(Debug)]
+struct ActiveMock(());
+
+impl ActiveMock {
+    fn new() -> Self {
+        ACTIVE_MOCKS.fetch_add(1, Ordering::SeqCst);
+        Self(())
+    }
+}
+
+impl Drop for ActiveMock {
+    fn drop(&mut self) {
+        ACTIVE_MOCKS.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 /// Exclusive guard to set the mock state.
@@ -387,8 +1345,85 @@ pub trait Mock: Sized {
 /// // ^ updates mock state without releasing the guard
 /// assert_eq!(answer(), 23);
 /// ```
+///
+/// ## Usage with fixtures
+///
+/// A guard is an owned `'static` value, so it can be returned from a test fixture,
+/// either a plain function or one defined with fixture libraries such as [`rstest`].
+/// The mock state remains set until the test consumes or drops the guard. Note that
+/// the guard does not dereference to the mock state, since the state may be concurrently
+/// accessed by mock impls; use [`Self::with()`] or [`Self::into_inner()`] to access
+/// the state instead.
+///
+/// [`rstest`]: https://docs.rs/rstest/
+///
+/// ```
+/// # use mimicry::{mock, CheckRealCall, Mock, MockGuard};
+/// #[mock(using = "ValueMock")]
+/// fn answer() -> usize { 42 }
+///
+/// #[derive(Default, Mock)]
+/// struct ValueMock(usize);
+/// # impl CheckRealCall for ValueMock {}
+/// # impl ValueMock {
+/// #     fn answer(&self) -> usize { self.0 }
+/// # }
+///
+/// fn value_mock() -> MockGuard<ValueMock> {
+///     ValueMock(23).set_as_mock()
+/// }
+///
+/// #[test]
+/// # fn eat_attr() {}
+/// fn test_with_mock() {
+///     let mut value_mock = value_mock();
+///     assert_eq!(answer(), 23);
+///     value_mock.with(|mock| mock.0 = 5);
+///     assert_eq!(answer(), 5);
+/// }
+/// # test_with_mock();
+/// ```
+///
+/// ## Storing in structs
+///
+/// Similarly, guards can be stored in a test harness struct to keep mocks set for
+/// the harness lifetime. `MockGuard<T>` can be used as a field type directly for any mock state
+/// `T`; the wrapper types used by the state are not a part of the type signature.
+///
+/// ```
+/// # use mimicry::{mock, CheckRealCall, Mock, MockGuard};
+/// #[mock(using = "ValueMock")]
+/// fn answer() -> usize { 42 }
+///
+/// #[derive(Default, Mock)]
+/// struct ValueMock(usize);
+/// # impl CheckRealCall for ValueMock {}
+/// # impl ValueMock {
+/// #     fn answer(&self) -> usize { self.0 }
+/// # }
+///
+/// struct Harness {
+///     value_mock: MockGuard<ValueMock>,
+/// }
+///
+/// impl Harness {
+///     fn new(value: usize) -> Self {
+///         Self { value_mock: ValueMock(value).set_as_mock() }
+///     }
+/// }
+///
+/// let mut harness = Harness::new(5);
+/// assert_eq!(answer(), 5);
+/// harness.value_mock.with(|mock| mock.0 = 23);
+/// assert_eq!(answer(), 23);
+/// drop(harness);
+/// assert_eq!(answer(), 42);
+/// ```
 pub struct MockGuard<T: Mock> {
     inner: <T::Shared as SetMock<'static, T::Base>>::Guard,
+    instance: &'static Static<T::Shared>,
+    // Must be dropped after `inner`, i.e., after the mock state is unset.
+    _active: ActiveMock,
 }
 
 impl<T: Mock> fmt::Debug for MockGuard<T> {
@@ -398,6 +1433,54 @@ impl<T: Mock> fmt::Debug for MockGuard<T> {
 }
 
 impl<T: Mock> MockGuard<T> {
+    fn new(instance: &'static Static<T::Shared>, state: T, call_limit: usize) -> Self {
+        // The counter must be incremented before the state is set so that mocked functions
+        // called concurrently do not miss the state.
+        let active = ActiveMock::new();
+        let cell = instance.cell.get_or_init(<T::Shared>::default);
+        Self {
+            inner: cell.set(state.into(), call_limit),
+            instance,
+            _active: active,
+        }
+    }
+
+    /// Returns the number of calls dispatched to the mock impls since the state was set.
+    /// Calls delegated to the real implementations (e.g., via [`CallReal`]) are not counted.
+    ///
+    /// For [thread-local](ThreadLocal) mocks, only calls on the current thread are counted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, Mock};
+    /// #[mock(using = "ValueMock")]
+    /// fn answer() -> usize { 42 }
+    ///
+    /// #[derive(Default, Mock)]
+    /// #[mock(auto_check)]
+    /// struct ValueMock(usize);
+    ///
+    /// impl ValueMock {
+    ///     fn answer(&self) -> usize { self.0 }
+    /// }
+    ///
+    /// let guard = ValueMock::default().set_as_mock();
+    /// answer();
+    /// answer();
+    /// assert_eq!(guard.call_count(), 2);
+    /// ```
+    pub fn call_count(&self) -> usize {
+        self.inner.counters().call_count()
+    }
+
+    /// Returns statistics of calls dispatched to the mock impls or delegated to the real impls
+    /// since the state was set. Statistics are only collected if the state has the `stats`
+    /// attribute; otherwise, they are empty. See [`DispatchStats`] for more details.
+    pub fn dispatch_stats(&self) -> &DispatchStats {
+        self.inner.counters().dispatch_stats()
+    }
+
     /// Performs an action on the mock state without releasing the guard. This can be used
     /// to adjust the mock state, check or take some parts of it (such as collected args
     /// or responses).
@@ -405,10 +1488,229 @@ impl<T: Mock> MockGuard<T> {
         self.inner.with(|wrapped| action(wrapped.as_mut()))
     }
 
+    /// Clones the mock state without releasing the guard. Unlike [`Self::with()`],
+    /// this method does not require exclusive access to the guard.
+    ///
+    /// This is mostly useful for [`Shared`] mocks: the snapshot is taken under the same lock
+    /// as mock calls, so it is consistent even if the state is concurrently accessed
+    /// from other threads. This allows making periodic assertions during a long concurrent test.
+    ///
+    /// # Panics
+    ///
+    /// For [`mut`](Mut) states, panics if the state is currently borrowed,
+    /// e.g., if called from a mock impl.
+    ///
+    /// # Deadlocks
+    ///
+    /// For [`Global`] mocks, this method blocks until the mock calls in progress are completed,
+    /// so it deadlocks if called from a mock impl (same as recursive mock calls).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, Mock, Mut};
+    /// #[mock(using = "CounterMock")]
+    /// fn answer() -> usize { 42 }
+    ///
+    /// #[derive(Default, Clone, Mock)]
+    /// #[mock(mut)]
+    /// struct CounterMock(usize);
+    ///
+    /// impl CounterMock {
+    ///     fn answer(this: &Mut<Self>) -> usize {
+    ///         this.borrow().0 += 1;
+    ///         this.borrow().0
+    ///     }
+    /// }
+    ///
+    /// let guard = CounterMock::default().set_as_mock();
+    /// answer();
+    /// assert_eq!(guard.snapshot().0, 1);
+    /// answer();
+    /// assert_eq!(guard.snapshot().0, 2);
+    /// ```
+    pub fn snapshot(&self) -> T
+    where
+        T: Clone,
+    {
+        self.inner.with_ref(|wrapped| wrapped.with_inner(T::clone))
+    }
+
+    /// Async version of [`Self::with()`]. The provided closure is executed on the mock state,
+    /// and the future returned by it is then awaited without releasing the guard.
+    ///
+    /// The state is only accessed while the closure is executed; it is not locked / borrowed
+    /// while the future is polled. Thus, the future cannot borrow the state, but it can call
+    /// mocked functions (e.g., ones accessing the state). Parts of the state needed
+    /// by the future should be cloned or moved into it (e.g., `Arc`s or an [`AnswersSender`]).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use async_std::task::block_on;
+    /// # use mimicry::Mock;
+    /// # use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+    /// #[derive(Default, Mock)]
+    /// #[mock(auto_check)]
+    /// struct CounterMock {
+    ///     counter: Arc<AtomicUsize>,
+    /// }
+    ///
+    /// async fn fetch_increment() -> usize {
+    ///     5
+    /// }
+    ///
+    /// # block_on(async {
+    /// let mut guard = CounterMock::default().set_as_mock();
+    /// guard.with_async(|state| {
+    ///     state.counter.store(1, Ordering::SeqCst);
+    ///     let counter = Arc::clone(&state.counter);
+    ///     async move {
+    ///         counter.fetch_add(fetch_increment().await, Ordering::SeqCst);
+    ///     }
+    /// }).await;
+    /// assert_eq!(guard.into_inner().counter.load(Ordering::SeqCst), 6);
+    /// # });
+    /// ```
+    pub async fn with_async<F: Future>(&mut self, action: impl FnOnce(&mut T) -> F) -> F::Output {
+        let future = self.with(action);
+        future.await
+    }
+
+    /// Replaces the mock state with `state` without releasing the guard, and returns
+    /// the previous state. This can be used to switch between test phases, e.g.,
+    /// to provide a new set of [`Answers`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, Mock};
+    /// #[mock(using = "ValueMock")]
+    /// fn answer() -> usize { 42 }
+    ///
+    /// #[derive(Mock)]
+    /// #[mock(auto_check)]
+    /// struct ValueMock(usize);
+    ///
+    /// impl ValueMock {
+    ///     fn answer(&self) -> usize { self.0 }
+    /// }
+    ///
+    /// let mut guard = ValueMock(1).set_as_mock();
+    /// assert_eq!(answer(), 1);
+    /// let prev_state = guard.replace(ValueMock(2));
+    /// assert_eq!(prev_state.0, 1);
+    /// assert_eq!(answer(), 2);
+    /// ```
+    pub fn replace(&mut self, state: T) -> T {
+        Guard::replace(&mut self.inner, state.into()).into_inner()
+    }
+
+    /// Leaks this guard, so that the mock state remains set until the process exits.
+    /// This can be used for mocks shared by all tests in a test binary, e.g., ones set
+    /// in a `once`-initialized fixture.
+    ///
+    /// Leaking releases the exclusive lock on the state (if any), but keeps the state set.
+    /// Use this method with care, since it bypasses the usual cleanup when the guard is dropped:
+    ///
+    /// - The state can never be retrieved via the guard, and its destructor only runs
+    ///   if the state is replaced.
+    /// - For [`ThreadLocal`] mocks, the state is only set for the current thread.
+    ///   If the state was set over another state (i.e., the guards are stacked),
+    ///   dropping the guard for the previous state will panic. Since thread-local slots
+    ///   are reused after a thread exits, the state should only be leaked on a thread living
+    ///   until the process exits (e.g., the main thread).
+    /// - For [`Shared`] and [`Global`] mocks, other tests can [lock](Mock::lock()) the state
+    ///   or set it again. Setting the state replaces the leaked state, so tests sharing
+    ///   the leaked state must not set it.
+    /// - Mocked functions no longer skip mock routing for free, since a leaked state
+    ///   is always considered active.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, Mock};
+    /// #[mock(using = "ValueMock")]
+    /// fn answer() -> usize { 42 }
+    ///
+    /// #[derive(Mock)]
+    /// #[mock(global, auto_check)]
+    /// struct ValueMock(usize);
+    ///
+    /// impl ValueMock {
+    ///     fn answer(&self) -> usize { self.0 }
+    /// }
+    ///
+    /// ValueMock(23).set_as_mock().leak();
+    /// assert_eq!(answer(), 23);
+    /// assert!(ValueMock::is_mock_set());
+    /// // The state is not locked, but remains set.
+    /// let _lock = ValueMock::try_lock().unwrap();
+    /// assert_eq!(answer(), 23);
+    /// ```
+    pub fn leak(self) {
+        let Self {
+            inner,
+            _active: active,
+            ..
+        } = self;
+        inner.leak();
+        mem::forget(active);
+    }
+
     /// Returns the enclosed mock state and releases the exclusive lock.
     pub fn into_inner(self) -> T {
         Guard::into_inner(self.inner).into_inner()
     }
+
+    /// Tries to return the enclosed mock state and release the exclusive lock.
+    /// Unlike [`Self::into_inner()`], this method does not block or panic
+    /// if the state cannot be taken right away, e.g., because it is being accessed
+    /// by a mock impl on this or another thread. Instead, the guard is returned back.
+    ///
+    /// # Errors
+    ///
+    /// Returns this guard if the state cannot be taken.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, CheckRealCall, Mock, MockRef};
+    /// #[mock(using = "ValueMock")]
+    /// fn answer() -> usize { 42 }
+    ///
+    /// #[derive(Debug, Mock)]
+    /// struct ValueMock(usize);
+    /// impl CheckRealCall for ValueMock {}
+    ///
+    /// impl ValueMock {
+    ///     fn answer(&self) -> usize { self.0 }
+    /// }
+    ///
+    /// let guard = ValueMock(1).set_as_mock();
+    /// let state_ref = MockRef::<ValueMock>::new(ValueMock::instance());
+    /// let guard = state_ref.with(|_| {
+    ///     // The state is borrowed, so it cannot be taken.
+    ///     guard.try_into_inner().unwrap_err()
+    /// });
+    /// let state = guard.try_into_inner().unwrap();
+    /// assert_eq!(state.0, 1);
+    /// ```
+    pub fn try_into_inner(self) -> Result<T, Self> {
+        let Self {
+            inner,
+            instance,
+            _active: active,
+        } = self;
+        match Guard::try_into_inner(inner) {
+            Ok(state) => Ok(state.into_inner()),
+            Err(inner) => Err(Self {
+                inner,
+                instance,
+                _active: active,
+            }),
+        }
+    }
 }
 
 /// Exclusive guard to set the mock state without an attached state.
@@ -435,6 +1737,8 @@ where
 /// is produced using interior mutability primitives like [`RefCell`].) Instead, `MockRef`
 /// provides access to the state using [`with()`](Self::with()) or [`with_mut()`](Self::with_mut())
 /// methods that do not overly restrict the lifetime of the state reference.
+/// If a `MockRef` may outlive the mock state (e.g., if it is used in a detached task),
+/// use [`try_with()`](Self::try_with()) or [`try_with_mut()`](Self::try_with_mut()).
 ///
 /// # Examples
 ///
@@ -496,6 +1800,46 @@ where
 /// assert_eq!(captured, Some(42));
 /// # })
 /// ```
+///
+/// ## Delegating sync calls
+///
+/// `MockRef` implements [`CallReal`] if the state does (e.g., for `mut` states), so
+/// [`call_real()`](CallReal::call_real()) and [`call_real_once()`](CallReal::call_real_once())
+/// can be used in sync contexts as well, e.g., to delegate a call to a sync mocked function.
+///
+/// ```
+/// # use async_std::task::block_on;
+/// # use mimicry::{mock, CallReal, Mock, MockRef, Mut};
+/// #[mock(using = "SpyMock")]
+/// fn parse(s: &str) -> u32 { s.parse().unwrap() }
+/// #[mock(using = "SpyMock")]
+/// async fn answer() -> u32 { parse("42") }
+///
+/// #[derive(Default, Mock)]
+/// #[mock(mut)]
+/// struct SpyMock {
+///     parsed: Vec<u32>,
+/// }
+///
+/// impl SpyMock {
+///     fn parse(this: &Mut<Self>, s: &str) -> u32 {
+///         let value = this.call_real_once().scope(|| parse(s));
+///         this.borrow().parsed.push(value);
+///         value + 1
+///     }
+///
+///     async fn answer(r: MockRef<Self>) -> u32 {
+///         r.call_real_once().scope(|| parse("23"))
+///     }
+/// }
+///
+/// # block_on(async {
+/// let guard = SpyMock::default().set_as_mock();
+/// assert_eq!(answer().await, 23);
+/// assert_eq!(parse("5"), 6);
+/// assert_eq!(guard.into_inner().parsed, [5]);
+/// # })
+/// ```
 #[derive(Debug)]
 pub struct MockRef<T: Mock> {
     instance: &'static Static<T::Shared>,
@@ -503,9 +1847,7 @@ pub struct MockRef<T: Mock> {
 
 impl<T: Mock> Clone for MockRef<T> {
     fn clone(&self) -> Self {
-        Self {
-            instance: self.instance,
-        }
+        *self
     }
 }
 
@@ -526,11 +1868,16 @@ impl<T: Mock<Base = T>> MockRef<T> {
     /// Panics if the mock state has gone missing. This is a sign that test code is ill-constructed
     /// (e.g., the mock is removed before all mocked calls are made).
     pub fn with<R>(&self, action: impl FnOnce(&T) -> R) -> R {
-        if let Some(mock_ref) = GetMock::get(self.instance) {
-            action(&*mock_ref)
-        } else {
-            panic!("mock state is gone");
-        }
+        self.try_with(action)
+            .unwrap_or_else(|| mock_state_is_gone::<T>())
+    }
+
+    /// Accesses the underlying mock state, or returns `None` if the mock state has gone missing.
+    /// Unlike [`Self::with()`], this method can be used in detached tasks that may outlive
+    /// the [`MockGuard`].
+    pub fn try_with<R>(&self, action: impl FnOnce(&T) -> R) -> Option<R> {
+        let mock_ref = GetMock::get(self.instance)?;
+        Some(action(&*mock_ref))
     }
 }
 
@@ -542,12 +1889,22 @@ impl<T: Mock<Base = Mut<T>>> MockRef<T> {
     /// Panics if the mock state has gone missing. This is a sign that test code is ill-constructed
     /// (e.g., the mock is removed before all mocked calls are made).
     pub fn with_mut<R>(&self, action: impl FnOnce(&mut T) -> R) -> R {
-        if let Some(mock_ref) = GetMock::get(self.instance) {
-            let base: &Mut<T> = &mock_ref;
-            action(&mut base.borrow())
-        } else {
-            panic!("mock state is gone");
-        }
+        self.try_with_mut(action)
+            .unwrap_or_else(|| mock_state_is_gone::<T>())
+    }
+
+    /// Accesses the underlying [`Mut`]able mock state, or returns `None` if the mock state
+    /// has gone missing. Unlike [`Self::with_mut()`], this method can be used in detached tasks
+    /// that may outlive the [`MockGuard`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the state is already borrowed, similarly to [`Mut::borrow()`].
+    pub fn try_with_mut<R>(&self, action: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let mock_ref = GetMock::get(self.instance)?;
+        let base: &Mut<T> = &mock_ref;
+        let output = action(&mut base.borrow());
+        Some(output)
     }
 }
 
@@ -560,16 +1917,27 @@ where
         if let Some(mock_ref) = GetMock::get(self.instance) {
             mock_ref.access_switch(action)
         } else {
-            panic!("mock state is gone");
+            mock_state_is_gone::<T>();
         }
     }
 }
 
+#[cold]
+#[track_caller]
+fn mock_state_is_gone<T>() -> ! {
+    panic!(
+        "mock state `{}` is gone; this usually means that the `MockGuard` was dropped \
+         before all mocked calls have completed",
+        any::type_name::<T>()
+    );
+}
+
 /// A lightweight wrapper around the state (essentially, a [`RefCell`]) allowing to easily
 /// mutate it in mock code.
 ///
-/// Besides access to the state, `Mut` implements [`CallReal`], thus allowing
-/// partial mocks / spies.
+/// Besides access to the state, `Mut` implements [`CallReal`] using its own [`RealCallSwitch`],
+/// thus allowing partial mocks / spies. The wrapped state does not need a switch field,
+/// and `CallReal` should not be derived for it.
 ///
 /// # Examples
 ///
@@ -609,13 +1977,65 @@ impl<T> Mut<T> {
     /// (including indirect ones, e.g., performed from the tested program code)
     /// will not be able to retrieve the state via this method; this will result
     /// in a panic. To deal with this, you can create short lived state refs a la
-    /// `this.borrow().do_something()`, or enclose the reference into an additional scope.
+    /// `this.borrow().do_something()`, enclose the reference into an additional scope,
+    /// or use [`Self::try_borrow()`].
     ///
     /// # Panics
     ///
     /// Panics if a reference to the same mock state is alive, as described above.
+    #[track_caller]
     pub fn borrow(&self) -> impl ops::DerefMut<Target = T> + '_ {
-        self.inner.borrow_mut()
+        self.inner.try_borrow_mut().unwrap_or_else(|_| {
+            panic!(
+                "mock state `{}` is already borrowed; this usually means that a mocked function \
+                 is called (perhaps indirectly) while a reference returned by `Mut::borrow()` \
+                 is alive",
+                any::type_name::<T>()
+            )
+        })
+    }
+
+    /// Attempts to get an exclusive reference to the underlying mock. Unlike [`Self::borrow()`],
+    /// this method returns `None` instead of panicking if a reference to the same mock state
+    /// is alive. This allows to gracefully handle reentrant calls in mock code, e.g.,
+    /// by skipping state mutation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, CallReal, Mock, Mut};
+    /// #[mock(using = "FactorialMock")]
+    /// fn factorial(n: u64) -> u64 {
+    ///     if n <= 1 { 1 } else { n * factorial(n - 1) }
+    /// }
+    ///
+    /// #[derive(Default, Mock)]
+    /// #[mock(mut)]
+    /// struct FactorialMock {
+    ///     top_level_args: Vec<u64>,
+    /// }
+    ///
+    /// impl FactorialMock {
+    ///     fn factorial(this: &Mut<Self>, n: u64) -> u64 {
+    ///         match this.try_borrow() {
+    ///             Some(mut state) => {
+    ///                 state.top_level_args.push(n);
+    ///                 // Call the real impl while the state is borrowed, so that
+    ///                 // nested calls will not be recorded.
+    ///                 this.call_real().scope(|| factorial(n))
+    ///             }
+    ///             None => this.call_real().scope(|| factorial(n)),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let guard = FactorialMock::default().set_as_mock();
+    /// assert_eq!(factorial(5), 120);
+    /// assert_eq!(factorial(3), 6);
+    /// assert_eq!(guard.into_inner().top_level_args, [5, 3]);
+    /// ```
+    pub fn try_borrow(&self) -> Option<impl ops::DerefMut<Target = T> + '_> {
+        self.inner.try_borrow_mut().ok()
     }
 }
 
@@ -636,6 +2056,10 @@ impl<T> Wrap<T> for Mut<T> {
     fn as_mut(&mut self) -> &mut T {
         self.inner.get_mut()
     }
+
+    fn with_inner<R>(&self, action: impl FnOnce(&T) -> R) -> R {
+        action(&self.borrow())
+    }
 }
 
 impl<T> CallReal for Mut<T> {
@@ -644,5 +2068,39 @@ impl<T> CallReal for Mut<T> {
     }
 }
 
+impl<T: MockVariant> MockVariant for Mut<T> {
+    fn mock_variant(&self) -> Option<&'static str> {
+        self.borrow().mock_variant()
+    }
+}
+
 #[cfg(doctest)]
 doc_comment::doctest!("../README.md");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::ptr;
+    use std::{sync::mpsc, thread, time::Duration};
+
+    #[test]
+    fn static_map_lookups_do_not_block_each_other() {
+        static MAP: StaticMap = StaticMap::new();
+
+        let instance = MAP.get_named::<u32>("test");
+        assert!(ptr::eq(instance, MAP.get_named::<u32>("test")));
+        assert!(!ptr::eq(instance, MAP.get::<u32>()));
+
+        let lookup_guard = MAP.inner.read();
+        let (sx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let instance = MAP.get_named::<u32>("test");
+            sx.send(instance).ok();
+        });
+        let other_instance = rx.recv_timeout(Duration::from_secs(5));
+        drop(lookup_guard);
+        let other_instance = other_instance.expect("lookup is blocked by another lookup");
+        assert!(ptr::eq(other_instance, instance));
+    }
+}
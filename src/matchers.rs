@@ -0,0 +1,268 @@
+//! Reusable matchers for values, e.g., args of mocked calls.
+//!
+//! Matchers are created using the constructor functions in this module ([`eq()`], [`any()`],
+//! [`pred()`] and [`range()`]) and can be combined using [`Matcher::and()`] / [`Matcher::or()`].
+//! Matchers can be used in mock impls, e.g., to choose a response based on the call args,
+//! to restrict [`Expectations`](crate::Expectations) to certain call contexts,
+//! or in the tests with the [`assert_value_matches!`](crate::assert_value_matches) macro.
+//!
+//! # Examples
+//!
+//! ```
+//! use mimicry::{assert_value_matches, mock, Mock};
+//! use mimicry::matchers::{self, Matcher};
+//!
+//! #[mock(using = "SearchMock")]
+//! fn search(haystack: &str, needle: char) -> Option<usize> {
+//!     haystack.chars().position(|ch| ch == needle)
+//! }
+//!
+//! #[derive(Default, Mock)]
+//! #[mock(auto_check)]
+//! struct SearchMock;
+//!
+//! impl SearchMock {
+//!     fn search(&self, haystack: &str, needle: char) -> Option<usize> {
+//!         let is_digit = matchers::pred(|ch: &char| ch.is_ascii_digit());
+//!         if is_digit.and(matchers::range('0'..='4')).matches(&needle) {
+//!             Some(0)
+//!         } else {
+//!             haystack.len().checked_sub(1)
+//!         }
+//!     }
+//! }
+//!
+//! let _guard = SearchMock.set_as_mock();
+//! assert_eq!(search("test", '3'), Some(0));
+//! assert_value_matches!(search("test", '7'), matchers::eq(Some(3)));
+//! assert_value_matches!(search("", 'x'), matchers::eq(None));
+//! ```
+
+use core::ops::RangeBounds;
+
+/// Matcher of values of type `T`.
+///
+/// This trait is implemented for all matchers produced by the constructor functions
+/// in the [module](self).
+pub trait Matcher<T: ?Sized> {
+    /// Checks whether the provided `value` matches.
+    fn matches(&self, value: &T) -> bool;
+
+    /// Combines this matcher with `other` so that a value matches only if it is matched
+    /// by both matchers. `other` is not evaluated if this matcher does not match.
+    fn and<M: Matcher<T>>(self, other: M) -> And<Self, M>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    /// Combines this matcher with `other` so that a value matches if it is matched
+    /// by any of the matchers. `other` is not evaluated if this matcher matches.
+    fn or<M: Matcher<T>>(self, other: M) -> Or<Self, M>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+}
+
+impl<T: ?Sized, M: Matcher<T> + ?Sized> Matcher<T> for &M {
+    fn matches(&self, value: &T) -> bool {
+        (**self).matches(value)
+    }
+}
+
+/// Matcher checking equality to a value. Created using [`eq()`].
+#[derive(Debug, Clone, Copy)]
+pub struct EqualTo<U>(U);
+
+impl<T: PartialEq<U> + ?Sized, U> Matcher<T> for EqualTo<U> {
+    fn matches(&self, value: &T) -> bool {
+        *value == self.0
+    }
+}
+
+/// Creates a matcher checking that a value is equal to `expected`.
+pub fn eq<U>(expected: U) -> EqualTo<U> {
+    EqualTo(expected)
+}
+
+/// Matcher matching any value. Created using [`any()`].
+#[derive(Debug, Clone, Copy)]
+pub struct AnyValue(());
+
+impl<T: ?Sized> Matcher<T> for AnyValue {
+    fn matches(&self, _value: &T) -> bool {
+        true
+    }
+}
+
+/// Creates a matcher matching any value.
+pub fn any() -> AnyValue {
+    AnyValue(())
+}
+
+/// Matcher based on a predicate. Created using [`pred()`].
+#[derive(Debug, Clone, Copy)]
+pub struct Pred<F>(F);
+
+impl<T: ?Sized, F: Fn(&T) -> bool> Matcher<T> for Pred<F> {
+    fn matches(&self, value: &T) -> bool {
+        (self.0)(value)
+    }
+}
+
+/// Creates a matcher based on the provided `predicate`.
+pub fn pred<T: ?Sized, F: Fn(&T) -> bool>(predicate: F) -> Pred<F> {
+    Pred(predicate)
+}
+
+/// Matcher checking that a value is in a range. Created using [`range()`].
+#[derive(Debug, Clone)]
+pub struct InRange<R>(R);
+
+impl<T: PartialOrd, R: RangeBounds<T>> Matcher<T> for InRange<R> {
+    fn matches(&self, value: &T) -> bool {
+        self.0.contains(value)
+    }
+}
+
+/// Creates a matcher checking that a value is in the specified `range`.
+pub fn range<R>(range: R) -> InRange<R> {
+    InRange(range)
+}
+
+/// Conjunction of two matchers. Created using [`Matcher::and()`].
+#[derive(Debug, Clone, Copy)]
+pub struct And<M1, M2>(M1, M2);
+
+impl<T: ?Sized, M1: Matcher<T>, M2: Matcher<T>> Matcher<T> for And<M1, M2> {
+    fn matches(&self, value: &T) -> bool {
+        self.0.matches(value) && self.1.matches(value)
+    }
+}
+
+/// Disjunction of two matchers. Created using [`Matcher::or()`].
+#[derive(Debug, Clone, Copy)]
+pub struct Or<M1, M2>(M1, M2);
+
+impl<T: ?Sized, M1: Matcher<T>, M2: Matcher<T>> Matcher<T> for Or<M1, M2> {
+    fn matches(&self, value: &T) -> bool {
+        self.0.matches(value) || self.1.matches(value)
+    }
+}
+
+/// Asserts that a value is matched by the specified [`Matcher`](crate::matchers::Matcher).
+///
+/// The value must implement [`Debug`](core::fmt::Debug). Similar to [`assert!`],
+/// a custom panic message can be provided after the matcher.
+///
+/// # Examples
+///
+/// ```
+/// use mimicry::{assert_value_matches, matchers::{self, Matcher}};
+///
+/// let value = 42_u32;
+/// assert_value_matches!(value, matchers::range(0..100));
+/// assert_value_matches!(
+///     value,
+///     matchers::pred(|&x: &u32| x % 2 == 0).and(matchers::range(40..)),
+///     "value is odd or too small: {}",
+///     value
+/// );
+/// ```
+///
+/// ```should_panic
+/// # use mimicry::{assert_value_matches, matchers};
+/// assert_value_matches!("test", matchers::eq("other"));
+/// ```
+#[macro_export]
+macro_rules! assert_value_matches {
+    ($value:expr, $matcher:expr $(,)?) => {{
+        let value = &$value;
+        assert!(
+            $crate::matchers::Matcher::matches(&$matcher, value),
+            "value {:?} does not match `{}`",
+            value,
+            stringify!($matcher)
+        );
+    }};
+    ($value:expr, $matcher:expr, $($arg:tt)+) => {{
+        let value = &$value;
+        assert!($crate::matchers::Matcher::matches(&$matcher, value), $($arg)+);
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::panic;
+
+    #[test]
+    fn basic_matchers() {
+        assert!(eq(5).matches(&5));
+        assert!(!eq(5).matches(&6));
+        assert!(eq("test").matches(&String::from("test")));
+
+        assert!(Matcher::<str>::matches(&any(), "test"));
+        assert!(any().matches(&5));
+
+        let is_even = pred(|&x: &u32| x % 2 == 0);
+        assert!(is_even.matches(&4));
+        assert!(!is_even.matches(&3));
+
+        assert!(range(1..3).matches(&1));
+        assert!(!range(1..3).matches(&3));
+        assert!(range(1..=3).matches(&3));
+        assert!(range(..=0.5).matches(&0.25));
+        assert!(range("a".."c").matches(&"b"));
+    }
+
+    #[test]
+    fn combining_matchers() {
+        let matcher = pred(|&x: &u32| x % 2 == 0).and(range(..10));
+        assert!(matcher.matches(&4));
+        assert!(!matcher.matches(&5));
+        assert!(!matcher.matches(&12));
+
+        let matcher = eq(0).or(range(10..20)).and(pred(|&x: &i32| x != 15));
+        assert!(matcher.matches(&0));
+        assert!(matcher.matches(&10));
+        assert!(!matcher.matches(&5));
+        assert!(!matcher.matches(&15));
+
+        let matcher = pred(|s: &str| s.starts_with('t')).or(pred(|s: &str| s == "other"));
+        assert!(matcher.matches("test"));
+        assert!(matcher.matches("other"));
+        assert!(!matcher.matches("?"));
+    }
+
+    #[test]
+    fn short_circuiting_combinators() {
+        let panicking = pred(|_: &u32| panic!("should not be called"));
+        assert!(!eq(1).and(&panicking).matches(&0));
+        assert!(eq(1).or(&panicking).matches(&1));
+    }
+
+    #[test]
+    fn assert_value_matches_macro() {
+        assert_value_matches!(5_u32, range(1..10));
+        assert_value_matches!(
+            "test",
+            pred(|s: &&str| s.len() == 4).and(eq("test")),
+            "custom message"
+        );
+
+        let err = panic::catch_unwind(|| assert_value_matches!(5_u32, range(..5))).unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert_eq!(err, "value 5 does not match `range(..5)`");
+
+        let value = 1;
+        let err = panic::catch_unwind(|| assert_value_matches!(value, eq(2), "custom: {}", value))
+            .unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert_eq!(err, "custom: 1");
+    }
+}
@@ -0,0 +1,487 @@
+//! Adapters easing migration of tests from [`mockall`].
+//!
+//! This module provides [`MockFn`], a per-function collection of `mockall`-style
+//! [expectations](FnExpectation), which can be placed in a [`Mock`](crate::Mock) state.
+//! Combined with a thin `expect_*` method on the state, this allows
+//! `mock.expect_foo().times(1).returning(...)` calls to compile with minimal edits.
+//! Internally, return values are produced by [`Answers`].
+//!
+//! [`mockall`]: https://docs.rs/mockall/
+//!
+//! # Supported functionality
+//!
+//! - Free functions and inherent methods (the `mock` attribute is used as usual).
+//! - Per-method expectation builders: [`times()`](FnExpectation::times()) (with a number or
+//!   a range), [`once()`](FnExpectation::once()), [`never()`](FnExpectation::never()),
+//!   [`withf()`](FnExpectation::withf()), [`with()`](FnExpectation::with()),
+//!   [`returning()`](FnExpectation::returning()) and
+//!   [`return_const()`](FnExpectation::return_const()).
+//! - Call counts are verified when [`MockFn`] is dropped, or explicitly via
+//!   [`MockFn::checkpoint()`].
+//!
+//! As with `mockall`, a call is matched against expectations in the order they were added;
+//! the first expectation that matches the call args and is not saturated (i.e., has not
+//! reached its maximum call count) is used. If there is no such expectation, the call panics.
+//!
+//! # Differences and unsupported functionality
+//!
+//! - Args are passed to expectations as a single tuple, by reference. That is, use
+//!   `withf(|&(x, y)| x == y)` instead of `withf(|x, y| x == y)` and `returning(|&(x,)| x)`
+//!   instead of `returning(|x| x)`.
+//! - `with()` accepts a single [`Matcher`] for the args tuple rather than a predicate per arg.
+//!   Use [`matchers`](crate::matchers) instead of the `predicates` crate.
+//! - Trait-level auto-mocking (`#[automock]`, `mock!`) is not supported; mocks are
+//!   defined via `mimicry` means.
+//! - `Sequence`s, `return_once()`, `return_const_st()` / `returning_st()`,
+//!   and generic methods with per-type expectations are not supported.
+//!   Use [`CallSequence`](crate::CallSequence) to verify call order.
+//! - Delegating to the real implementation must be performed in the mock impl itself,
+//!   e.g., using [`CallReal`](crate::CallReal).
+//!
+//! # Examples
+//!
+//! ```
+//! use mimicry::{mock, Mock};
+//! use mimicry::mockall_compat::{FnExpectation, MockFn};
+//!
+//! #[mock(using = "CalculatorMock")]
+//! fn add(x: u32, y: u32) -> u32 {
+//!     x + y
+//! }
+//!
+//! #[derive(Mock)]
+//! #[mock(auto_check)]
+//! struct CalculatorMock {
+//!     add: MockFn<(u32, u32), u32>,
+//! }
+//!
+//! impl CalculatorMock {
+//!     fn new() -> Self {
+//!         Self { add: MockFn::new("add") }
+//!     }
+//!
+//!     fn expect_add(&mut self) -> &mut FnExpectation<(u32, u32), u32> {
+//!         self.add.expect()
+//!     }
+//!
+//!     fn add(&self, x: u32, y: u32) -> u32 {
+//!         self.add.call((x, y))
+//!     }
+//! }
+//!
+//! let mut mock = CalculatorMock::new();
+//! mock.expect_add()
+//!     .withf(|&(x, _)| x == 0)
+//!     .times(1)
+//!     .returning(|&(_, y)| y * 2);
+//! mock.expect_add().return_const(42_u32);
+//!
+//! let guard = mock.set_as_mock();
+//! assert_eq!(add(0, 5), 10);
+//! assert_eq!(add(0, 5), 42); // the first expectation is saturated
+//! assert_eq!(add(1, 2), 42);
+//! drop(guard); // verifies expectations
+//! ```
+
+use parking_lot::Mutex;
+
+use core::{fmt, ops};
+use std::thread;
+
+use crate::{expect::CallCount, matchers::Matcher, Answers};
+
+type ArgsMatcher<Args> = Box<dyn Fn(&Args) -> bool + Send + Sync>;
+
+/// Expected number of calls for [`FnExpectation::times()`]. Can be created from a `usize`
+/// or a range of `usize`s, similar to `mockall`.
+#[derive(Debug, Clone, Copy)]
+pub struct Times(CallCount);
+
+impl From<usize> for Times {
+    fn from(count: usize) -> Self {
+        Self(CallCount {
+            min: count,
+            max: Some(count),
+        })
+    }
+}
+
+impl From<ops::Range<usize>> for Times {
+    fn from(range: ops::Range<usize>) -> Self {
+        assert!(range.start < range.end, "empty range of call counts");
+        Self(CallCount {
+            min: range.start,
+            max: Some(range.end - 1),
+        })
+    }
+}
+
+impl From<ops::RangeInclusive<usize>> for Times {
+    fn from(range: ops::RangeInclusive<usize>) -> Self {
+        Self(CallCount {
+            min: *range.start(),
+            max: Some(*range.end()),
+        })
+    }
+}
+
+impl From<ops::RangeFrom<usize>> for Times {
+    fn from(range: ops::RangeFrom<usize>) -> Self {
+        Self(CallCount {
+            min: range.start,
+            max: None,
+        })
+    }
+}
+
+impl From<ops::RangeTo<usize>> for Times {
+    fn from(range: ops::RangeTo<usize>) -> Self {
+        assert!(range.end > 0, "empty range of call counts");
+        Self(CallCount {
+            min: 0,
+            max: Some(range.end - 1),
+        })
+    }
+}
+
+impl From<ops::RangeToInclusive<usize>> for Times {
+    fn from(range: ops::RangeToInclusive<usize>) -> Self {
+        Self(CallCount {
+            min: 0,
+            max: Some(range.end),
+        })
+    }
+}
+
+impl From<ops::RangeFull> for Times {
+    fn from(_: ops::RangeFull) -> Self {
+        Self(CallCount::ANY)
+    }
+}
+
+/// `mockall`-style expectation for a single function. Created using [`MockFn::expect()`].
+///
+/// By default, an expectation matches any args and allows any number of calls.
+pub struct FnExpectation<Args, R> {
+    count: CallCount,
+    matcher: Option<ArgsMatcher<Args>>,
+    answers: Option<Answers<R, Args>>,
+    actual_count: usize,
+}
+
+impl<Args, R> fmt::Debug for FnExpectation<Args, R> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("FnExpectation")
+            .field("count", &self.count)
+            .field("has_matcher", &self.matcher.is_some())
+            .field("has_answers", &self.answers.is_some())
+            .field("actual_count", &self.actual_count)
+            .finish()
+    }
+}
+
+impl<Args, R> FnExpectation<Args, R> {
+    fn new() -> Self {
+        Self {
+            count: CallCount::ANY,
+            matcher: None,
+            answers: None,
+            actual_count: 0,
+        }
+    }
+
+    /// Sets the expected number of calls, either exact or a range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the provided range is empty.
+    pub fn times(&mut self, times: impl Into<Times>) -> &mut Self {
+        self.count = times.into().0;
+        self
+    }
+
+    /// Expects exactly one call.
+    pub fn once(&mut self) -> &mut Self {
+        self.times(1)
+    }
+
+    /// Expects no calls.
+    pub fn never(&mut self) -> &mut Self {
+        self.times(0)
+    }
+
+    /// Restricts the expectation to calls with args satisfying the provided `predicate`.
+    pub fn withf<F>(&mut self, predicate: F) -> &mut Self
+    where
+        F: Fn(&Args) -> bool + Send + Sync + 'static,
+    {
+        self.matcher = Some(Box::new(predicate));
+        self
+    }
+
+    /// Restricts the expectation to calls with args matched by the provided `matcher`.
+    pub fn with<M>(&mut self, matcher: M) -> &mut Self
+    where
+        M: Matcher<Args> + Send + Sync + 'static,
+    {
+        self.withf(move |args| matcher.matches(args))
+    }
+
+    /// Sets the function producing return values based on the call args.
+    pub fn returning<F>(&mut self, function: F) -> &mut Self
+    where
+        F: FnMut(&Args) -> R + Send + 'static,
+    {
+        self.answers = Some(Answers::from_fn(function));
+        self
+    }
+
+    /// Makes the expectation always return the provided `value`.
+    pub fn return_const(&mut self, value: R) -> &mut Self
+    where
+        R: Clone + Send + 'static,
+    {
+        self.returning(move |_| value.clone())
+    }
+
+    fn matches(&self, args: &Args) -> bool {
+        let is_saturated = self.count.max.map_or(false, |max| self.actual_count >= max);
+        !is_saturated && self.matcher.as_ref().map_or(true, |matcher| matcher(args))
+    }
+
+    fn error(&self, index: usize) -> Option<String> {
+        if self.count.contains(self.actual_count) {
+            None
+        } else {
+            Some(format!(
+                "expectation #{index}: expected {expected}, got {actual} call(s)",
+                expected = self.count,
+                actual = self.actual_count
+            ))
+        }
+    }
+}
+
+/// Collection of `mockall`-style expectations for a single mocked function.
+///
+/// `MockFn` is intended to be placed in a [`Mock`](crate::Mock) state. See
+/// the [module docs](self) for details and examples.
+///
+/// # Panics
+///
+/// When dropped, verifies that all expectations were satisfied and panics otherwise
+/// (unless the thread is already panicking).
+pub struct MockFn<Args, R> {
+    name: String,
+    expectations: Mutex<Vec<FnExpectation<Args, R>>>,
+}
+
+impl<Args, R> fmt::Debug for MockFn<Args, R> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("MockFn")
+            .field("name", &self.name)
+            .field("expectations", &*self.expectations.lock())
+            .finish()
+    }
+}
+
+impl<Args, R> MockFn<Args, R> {
+    /// Creates a function mock without expectations. The `name` is used in panic messages.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            expectations: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Adds a new expectation and returns a reference to it allowing to customize
+    /// the expectation.
+    #[allow(clippy::missing_panics_doc)] // false positive
+    pub fn expect(&mut self) -> &mut FnExpectation<Args, R> {
+        let expectations = self.expectations.get_mut();
+        expectations.push(FnExpectation::new());
+        expectations.last_mut().unwrap()
+    }
+
+    /// Processes a call with the specified args, returning the value produced by the first
+    /// matching expectation.
+    ///
+    /// The expectations are locked during the call, so the return value producer
+    /// must not call the same mocked function; otherwise, the call will deadlock.
+    ///
+    /// # Panics
+    ///
+    /// - Panics if no expectation matches the call.
+    /// - Panics if the matching expectation has no return value set.
+    pub fn call(&self, args: Args) -> R {
+        let mut expectations = self.expectations.lock();
+        let expectation = expectations
+            .iter_mut()
+            .find(|exp| exp.matches(&args))
+            .unwrap_or_else(|| panic!("no matching expectation found for `{}`", self.name));
+        expectation.actual_count += 1;
+        let answers = expectation.answers.as_mut().unwrap_or_else(|| {
+            panic!(
+                "no return value set for a matching expectation for `{}`; \
+                 use `returning()` or `return_const()`",
+                self.name
+            )
+        });
+        answers.next_for(args)
+    }
+
+    /// Verifies all expectations and clears them, similar to `checkpoint()` in `mockall`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the expectations is not satisfied.
+    pub fn checkpoint(&mut self) {
+        let errors: Vec<_> = self
+            .expectations
+            .get_mut()
+            .drain(..)
+            .enumerate()
+            .filter_map(|(i, exp)| exp.error(i))
+            .collect();
+        assert!(
+            errors.is_empty(),
+            "Unsatisfied expectations for `{}`:\n{}",
+            self.name,
+            errors.join("\n")
+        );
+    }
+}
+
+impl<Args, R> Drop for MockFn<Args, R> {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            self.checkpoint();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matchers;
+
+    use static_assertions::assert_impl_all;
+    use std::panic;
+
+    assert_impl_all!(MockFn<(String, u32), String>: Send, Sync);
+
+    #[test]
+    fn converting_times() {
+        let Times(count) = 3.into();
+        assert_eq!(
+            count,
+            CallCount {
+                min: 3,
+                max: Some(3)
+            }
+        );
+        let Times(count) = (1..3).into();
+        assert_eq!(
+            count,
+            CallCount {
+                min: 1,
+                max: Some(2)
+            }
+        );
+        let Times(count) = (1..=3).into();
+        assert_eq!(
+            count,
+            CallCount {
+                min: 1,
+                max: Some(3)
+            }
+        );
+        let Times(count) = (2..).into();
+        assert_eq!(count, CallCount { min: 2, max: None });
+        let Times(count) = (..2).into();
+        assert_eq!(
+            count,
+            CallCount {
+                min: 0,
+                max: Some(1)
+            }
+        );
+        let Times(count) = (..=2).into();
+        assert_eq!(
+            count,
+            CallCount {
+                min: 0,
+                max: Some(2)
+            }
+        );
+        let Times(count) = (..).into();
+        assert_eq!(count, CallCount::ANY);
+    }
+
+    #[test]
+    fn matching_expectations() {
+        let mut mock_fn = MockFn::<(u32, u32), u32>::new("add");
+        mock_fn
+            .expect()
+            .with(matchers::pred(|&(x, _): &(u32, u32)| x == 0))
+            .times(1..=2)
+            .returning(|&(_, y)| y);
+        mock_fn.expect().withf(|&(x, y)| x == y).never();
+        mock_fn.expect().return_const(42);
+
+        assert_eq!(mock_fn.call((0, 5)), 5);
+        assert_eq!(mock_fn.call((0, 3)), 3);
+        assert_eq!(mock_fn.call((0, 3)), 42);
+        assert_eq!(mock_fn.call((1, 2)), 42);
+        mock_fn.checkpoint();
+
+        mock_fn.expect().once().return_const(0);
+        assert_eq!(mock_fn.call((1, 1)), 0);
+    }
+
+    #[test]
+    fn unmatched_call() {
+        let mut mock_fn = MockFn::<(u32,), u32>::new("test");
+        mock_fn.expect().once().return_const(1);
+        assert_eq!(mock_fn.call((0,)), 1);
+
+        let call = panic::AssertUnwindSafe(|| mock_fn.call((0,)));
+        let err = panic::catch_unwind(call).unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert_eq!(err, "no matching expectation found for `test`");
+    }
+
+    #[test]
+    fn missing_return_value() {
+        let mut mock_fn = MockFn::<(), u32>::new("test");
+        mock_fn.expect();
+
+        let call = panic::AssertUnwindSafe(|| mock_fn.call(()));
+        let err = panic::catch_unwind(call).unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert!(err.starts_with("no return value set"), "{err}");
+    }
+
+    #[test]
+    fn failed_checkpoint() {
+        let mut mock_fn = MockFn::<(), ()>::new("test");
+        mock_fn.expect().times(2).return_const(());
+        mock_fn.expect().never();
+        mock_fn.call(());
+
+        let checkpoint = panic::AssertUnwindSafe(move || mock_fn.checkpoint());
+        let err = panic::catch_unwind(checkpoint).unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert!(
+            err.starts_with("Unsatisfied expectations for `test`"),
+            "{err}"
+        );
+        assert!(
+            err.contains("expectation #0: expected exactly 2 time(s), got 1 call(s)"),
+            "{err}"
+        );
+        assert!(!err.contains("expectation #1"), "{err}");
+    }
+}
@@ -0,0 +1,230 @@
+//! Mock states with a lifetime param.
+
+use core::{any::TypeId, cell::RefCell, marker::PhantomData, ptr};
+use std::collections::HashMap;
+
+use crate::{ActiveMock, CheckRealCall};
+
+thread_local! {
+    /// Per-thread stacks of pointers to the set scoped states, keyed by the type ID
+    /// of the `'static` version of the state type.
+    static SCOPED_STATES: RefCell<HashMap<TypeId, Vec<*const ()>>> = RefCell::default();
+}
+
+/// Mock state with a lifetime param, e.g., a state borrowing test fixtures.
+///
+/// Unlike [`Mock`](crate::Mock) states, scoped states are not placed in a `static`.
+/// Instead, they are set for the current thread for the duration of a closure
+/// using [`with_scoped_mock()`]. Mock impls receive the state via a reference
+/// with a shortened lifetime (i.e., `&'s State<'s>` for some call-local lifetime `'s`),
+/// so that they cannot smuggle borrowed data out of the scope. As a consequence,
+/// mock impls must be generic over the state lifetime (e.g., declared in `impl Spy<'_>`).
+///
+/// This trait is implemented by the [`Mock`](macro@crate::Mock) derive macro for states
+/// with a single lifetime param (and no type / const params). Functions mocked using
+/// such a state must specify the `scoped` attr, e.g., `#[mock(using = "SpyMock", scoped)]`.
+/// Scoped states have the following restrictions:
+///
+/// - States are always thread-local, and cannot be used together with the `shared`,
+///   `global` or `mut` attrs of the derive macro. Mutability can be achieved via
+///   interior mutability (e.g., `Cell` or `RefCell` fields), or via the borrowed fixtures.
+/// - The state must be covariant in its lifetime param. E.g., `&'a Mutex<Vec<u32>>`
+///   is a valid field, but `Cell<&'a u32>` is not.
+/// - Only sync functions / methods can be mocked. `instance`, `variants` and `for` attrs
+///   of the `mock` macro are not supported, as well as call counting and dispatch stats.
+///
+/// # Safety
+///
+/// [`Self::Static`] must be `Self` with the lifetime param replaced by `'static`,
+/// and its [`ScopedStatic`] impl must map the type back to `Self` with the provided lifetime.
+/// `Self` must be covariant in its lifetime param. These requirements are ensured
+/// by the derive macro.
+///
+/// # Examples
+///
+/// ```
+/// use mimicry::{mock, Mock};
+/// use std::sync::Mutex;
+///
+/// #[mock(using = "SpyMock", scoped)]
+/// fn answer(value: u32) -> u32 {
+///     value * 2
+/// }
+///
+/// #[derive(Mock)]
+/// #[mock(auto_check)]
+/// struct SpyMock<'a> {
+///     calls: &'a Mutex<Vec<u32>>,
+/// }
+///
+/// impl SpyMock<'_> {
+///     fn answer(&self, value: u32) -> u32 {
+///         self.calls.lock().unwrap().push(value);
+///         value
+///     }
+/// }
+///
+/// let calls = Mutex::default();
+/// let (sum, _) = mimicry::with_scoped_mock(SpyMock { calls: &calls }, || {
+///     answer(1) + answer(2)
+/// });
+/// assert_eq!(sum, 3);
+/// assert_eq!(answer(3), 6); // the mock is no longer set
+/// assert_eq!(*calls.lock().unwrap(), [1, 2]);
+/// ```
+pub unsafe trait ScopedMock<'a>: CheckRealCall + Sized + 'a {
+    /// Version of this state with the `'static` lifetime.
+    #[doc(hidden)]
+    type Static: for<'s> ScopedStatic<'s>;
+}
+
+/// Maps the `'static` version of a [`ScopedMock`] state to the state with an arbitrary lifetime.
+///
+/// # Safety
+///
+/// See [`ScopedMock`] for the safety requirements.
+#[doc(hidden)] // implemented by the `Mock` derive macro
+pub unsafe trait ScopedStatic<'s>: 'static {
+    /// State with the `'s` lifetime.
+    type Scoped: CheckRealCall + 's;
+}
+
+/// Guard removing a scoped state from [`SCOPED_STATES`] when dropped.
+#[derive(Debug)]
+struct ScopedGuard {
+    key: TypeId,
+    _active: ActiveMock,
+    // The state is set for the current thread, so the guard must not be sent to other threads.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ScopedGuard {
+    fn drop(&mut self) {
+        SCOPED_STATES.with(|states| {
+            let mut states = states.borrow_mut();
+            if let Some(stack) = states.get_mut(&self.key) {
+                stack.pop();
+            }
+        });
+    }
+}
+
+/// Sets `state` as the mock state for the current thread, runs the provided closure
+/// and returns its output together with the mock state. The mock is unset even
+/// if the closure panics. Scoped states can be nested; in this case, the innermost state
+/// is used by the mocked functions.
+///
+/// See [`ScopedMock`] for more details and examples.
+pub fn with_scoped_mock<'a, T: ScopedMock<'a>, R>(state: T, action: impl FnOnce() -> R) -> (R, T) {
+    let key = TypeId::of::<T::Static>();
+    let state_ptr = ptr::addr_of!(state).cast::<()>();
+    SCOPED_STATES.with(|states| {
+        let mut states = states.borrow_mut();
+        states.entry(key).or_default().push(state_ptr);
+    });
+    let guard = ScopedGuard {
+        key,
+        _active: ActiveMock::new(),
+        _not_send: PhantomData,
+    };
+    // `state` is not moved until the guard is dropped, so the pointer remains valid.
+    let output = action();
+    drop(guard);
+    (output, state)
+}
+
+/// Provides access to the scoped state with the specified `'static` version,
+/// or returns `None` if the state is not set for the current thread.
+#[doc(hidden)] // used by the `mock` attribute macro
+pub fn with_scoped_state<K, R>(
+    action: impl for<'s> FnOnce(&'s <K as ScopedStatic<'s>>::Scoped) -> R,
+) -> Option<R>
+where
+    K: for<'s> ScopedStatic<'s>,
+{
+    let key = TypeId::of::<K>();
+    let state_ptr = SCOPED_STATES.with(|states| {
+        let states = states.borrow();
+        states.get(&key).and_then(|stack| stack.last().copied())
+    })?;
+    let state_ptr = state_ptr.cast::<<K as ScopedStatic<'_>>::Scoped>();
+    // SAFETY: the pointer was registered in `with_scoped_mock()` for a state of type
+    // `State<'a>` such that `K == State<'static>`, and the state is alive while the pointer
+    // is registered. The pointer can only be accessed on the current thread while
+    // `with_scoped_mock()` has not returned, so the created reference lives shorter than `'a`.
+    // Since the state is covariant in its lifetime, `&'a State<'a>` may be coerced
+    // to `&'s State<'s>`, which is the type of the provided reference.
+    let state = unsafe { &*state_ptr };
+    Some(action(state))
+}
+
+/// Calls the mock impl for a scoped state, which must be set.
+#[doc(hidden)] // used by the `mock` attribute macro
+pub fn call_scoped_mock<K, R>(
+    action: impl for<'s> FnOnce(&'s <K as ScopedStatic<'s>>::Scoped) -> R,
+) -> R
+where
+    K: for<'s> ScopedStatic<'s>,
+{
+    with_scoped_state::<K, R>(action).expect("scoped mock state is unset during a mocked call")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    struct TestMock<'a> {
+        calls: &'a Mutex<Vec<u32>>,
+    }
+
+    impl CheckRealCall for TestMock<'_> {}
+
+    unsafe impl<'a> ScopedMock<'a> for TestMock<'a> {
+        type Static = TestMock<'static>;
+    }
+
+    unsafe impl<'s> ScopedStatic<'s> for TestMock<'static> {
+        type Scoped = TestMock<'s>;
+    }
+
+    fn record_call(value: u32) -> Option<()> {
+        with_scoped_state::<TestMock<'static>, _>(|state| {
+            state.calls.lock().unwrap().push(value);
+        })
+    }
+
+    #[test]
+    fn setting_scoped_states() {
+        let calls = Mutex::default();
+        let nested_calls = Mutex::default();
+        assert!(record_call(0).is_none());
+
+        let (value, _) = with_scoped_mock(TestMock { calls: &calls }, || {
+            record_call(1).unwrap();
+            with_scoped_mock(
+                TestMock {
+                    calls: &nested_calls,
+                },
+                || record_call(2),
+            );
+            record_call(3).unwrap();
+            42
+        });
+        assert_eq!(value, 42);
+        assert!(record_call(4).is_none());
+        assert_eq!(*calls.lock().unwrap(), [1, 3]);
+        assert_eq!(*nested_calls.lock().unwrap(), [2]);
+    }
+
+    #[test]
+    fn scoped_state_is_unset_on_panic() {
+        let calls = Mutex::default();
+        let result = std::panic::catch_unwind(|| {
+            with_scoped_mock(TestMock { calls: &calls }, || panic!("oops"));
+        });
+        assert!(result.is_err());
+        assert!(record_call(0).is_none());
+    }
+}
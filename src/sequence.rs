@@ -0,0 +1,222 @@
+//! Recording call sequences across mocked functions.
+
+use parking_lot::Mutex;
+
+use core::{fmt, mem};
+use std::sync::Arc;
+
+/// Shared recorder of ordered events, e.g., calls to different mocked functions.
+///
+/// `CallSequence` is intended to be placed in a [`Mock`](crate::Mock) state (or several
+/// states; clones of a `CallSequence` share the same event log). Mock impls
+/// [push](Self::push()) events, which can then be verified to have happened
+/// in a certain order. This is useful for protocol-style tests, in which the order of calls
+/// matters more than their count.
+///
+/// # Examples
+///
+/// ```
+/// use mimicry::{mock, CallSequence, Mock};
+///
+/// struct File;
+///
+/// #[mock(using = "FileMock")]
+/// impl File {
+///     fn open(path: &str) -> Self { File }
+///     fn read(&mut self) -> Vec<u8> { vec![] }
+///     fn close(self) {}
+/// }
+///
+/// #[derive(Default, Mock)]
+/// #[mock(auto_check)]
+/// struct FileMock {
+///     calls: CallSequence,
+/// }
+///
+/// impl FileMock {
+///     fn open(&self, _path: &str) -> File {
+///         self.calls.push("open");
+///         File
+///     }
+///
+///     fn read(&self, _file: &mut File) -> Vec<u8> {
+///         self.calls.push("read");
+///         b"test".to_vec()
+///     }
+///
+///     fn close(&self, _file: File) {
+///         self.calls.push("close");
+///     }
+/// }
+///
+/// let state = FileMock::default();
+/// let calls = state.calls.clone();
+/// let _guard = state.set_as_mock();
+/// let mut file = File::open("test.txt");
+/// file.read();
+/// file.read();
+/// file.close();
+///
+/// calls.verify(["open", "read", "read", "close"]);
+/// // Events may be interleaved with other events:
+/// calls.verify_in_order(["open", "close"]);
+/// ```
+pub struct CallSequence<E = &'static str> {
+    events: Arc<Mutex<Vec<E>>>,
+}
+
+impl<E: fmt::Debug> fmt::Debug for CallSequence<E> {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("CallSequence")
+            .field("events", &*self.events.lock())
+            .finish()
+    }
+}
+
+impl<E> Clone for CallSequence<E> {
+    fn clone(&self) -> Self {
+        Self {
+            events: Arc::clone(&self.events),
+        }
+    }
+}
+
+impl<E> Default for CallSequence<E> {
+    fn default() -> Self {
+        Self {
+            events: Arc::default(),
+        }
+    }
+}
+
+impl<E> CallSequence<E> {
+    /// Creates an empty sequence.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an event.
+    pub fn push(&self, event: impl Into<E>) {
+        self.events.lock().push(event.into());
+    }
+
+    /// Returns a copy of all recorded events.
+    pub fn events(&self) -> Vec<E>
+    where
+        E: Clone,
+    {
+        self.events.lock().clone()
+    }
+
+    /// Takes all recorded events, leaving the sequence empty.
+    pub fn take(&self) -> Vec<E> {
+        let mut events = self.events.lock();
+        mem::take(&mut *events)
+    }
+
+    /// Checks that the recorded events are exactly equal to `expected`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the recorded events differ from the expected ones.
+    pub fn verify<T>(&self, expected: impl IntoIterator<Item = T>)
+    where
+        E: PartialEq<T> + fmt::Debug,
+        T: fmt::Debug,
+    {
+        let expected: Vec<_> = expected.into_iter().collect();
+        let events = self.events.lock();
+        let is_match = events.len() == expected.len()
+            && events
+                .iter()
+                .zip(&expected)
+                .all(|(event, exp)| event == exp);
+        assert!(
+            is_match,
+            "Unexpected call sequence:\n  expected: {expected:?}\n    actual: {events:?}",
+            events = *events
+        );
+    }
+
+    /// Checks that the recorded events contain `expected` events in the specified relative order.
+    /// Other events may be interleaved with the expected ones.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the expected events are missing or happened in a different order.
+    pub fn verify_in_order<T>(&self, expected: impl IntoIterator<Item = T>)
+    where
+        E: PartialEq<T> + fmt::Debug,
+        T: fmt::Debug,
+    {
+        let expected: Vec<_> = expected.into_iter().collect();
+        let events = self.events.lock();
+        let mut events_iter = events.iter();
+        let missing_pos = expected
+            .iter()
+            .position(|exp| !events_iter.any(|event| event == exp));
+        if let Some(pos) = missing_pos {
+            panic!(
+                "Call sequence does not contain expected events in order: \
+                 missing {missing:?} (#{pos}) after previous expected events\n  \
+                 expected: {expected:?}\n    actual: {events:?}",
+                missing = expected[pos],
+                events = *events
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use static_assertions::assert_impl_all;
+    use std::panic::{self, AssertUnwindSafe};
+
+    assert_impl_all!(CallSequence: Send, Sync);
+    assert_impl_all!(CallSequence<String>: Send, Sync);
+
+    #[test]
+    fn verifying_sequence() {
+        let sequence = CallSequence::<String>::new();
+        let other_sequence = sequence.clone();
+        sequence.push("open");
+        other_sequence.push("read");
+        sequence.push("write");
+        sequence.push("close");
+
+        sequence.verify(["open", "read", "write", "close"]);
+        sequence.verify_in_order(["open", "close"]);
+        sequence.verify_in_order(["read", "write"]);
+        sequence.verify_in_order(Vec::<&str>::new());
+        assert_eq!(other_sequence.take(), ["open", "read", "write", "close"]);
+        assert!(sequence.events().is_empty());
+    }
+
+    #[test]
+    fn sequence_errors() {
+        let sequence = CallSequence::<&str>::new();
+        sequence.push("open");
+        sequence.push("read");
+        sequence.push("close");
+
+        let err = panic::catch_unwind(AssertUnwindSafe(|| sequence.verify(["open", "close"])))
+            .unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert!(err.starts_with("Unexpected call sequence"), "{err}");
+
+        let err = panic::catch_unwind(AssertUnwindSafe(|| {
+            sequence.verify_in_order(["close", "read"]);
+        }))
+        .unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert!(err.contains("missing \"read\" (#1)"), "{err}");
+
+        let err = panic::catch_unwind(AssertUnwindSafe(|| sequence.verify_in_order(["write"])))
+            .unwrap_err();
+        let err = err.downcast_ref::<String>().unwrap();
+        assert!(err.contains("missing \"write\" (#0)"), "{err}");
+    }
+}
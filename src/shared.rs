@@ -1,14 +1,18 @@
-//! Thread-safe mock state wrapper.
+//! Thread-safe mock state wrappers.
 
 use ouroboros::self_referencing;
-use parking_lot::{Mutex, MutexGuard, ReentrantMutex, ReentrantMutexGuard};
+use parking_lot::{
+    MappedRwLockReadGuard, Mutex, MutexGuard, ReentrantMutex, ReentrantMutexGuard, RwLock,
+    RwLockReadGuard,
+};
 
 use core::{
     cell::{Ref, RefCell},
-    ops,
+    mem, ops,
 };
+use std::sync::Arc;
 
-use crate::{GetMock, Guard, LockMock, SetMock};
+use crate::{stats::CallCounters, CountCalls, GetMock, Guard, LockMock, SetMock};
 
 /// Wrapper around [`Mock`](crate::Mock) state that provides cross-thread synchronization.
 ///
@@ -78,6 +82,7 @@ use crate::{GetMock, Guard, LockMock, SetMock};
 pub struct Shared<T> {
     inner: ReentrantMutex<RefCell<Option<T>>>,
     write_lock: Mutex<()>,
+    counters: RwLock<Option<Arc<CallCounters>>>,
 }
 
 impl<T> Default for Shared<T> {
@@ -85,10 +90,17 @@ impl<T> Default for Shared<T> {
         Self {
             inner: ReentrantMutex::new(RefCell::new(None)),
             write_lock: Mutex::new(()),
+            counters: RwLock::new(None),
         }
     }
 }
 
+impl<T> CountCalls for Shared<T> {
+    fn with_counters<R>(&self, action: impl FnOnce(&CallCounters) -> R) -> Option<R> {
+        self.counters.read().as_deref().map(action)
+    }
+}
+
 impl<T> Shared<T> {
     fn lock(&self) -> ReentrantMutexGuard<'_, RefCell<Option<T>>> {
         self.inner.lock()
@@ -106,18 +118,28 @@ impl<'a, T: 'static> GetMock<'a, T> for Shared<T> {
             None
         }
     }
+
+    fn is_set(&self) -> bool {
+        let guard = self.lock();
+        // The state is exclusively borrowed only if it's set and is being accessed via a guard.
+        let is_set = guard.try_borrow().map_or(true, |borrow| borrow.is_some());
+        is_set
+    }
 }
 
 impl<'a, T: 'static> SetMock<'a, T> for Shared<T> {
     type Guard = SharedGuard<'a, T>;
 
-    fn set(&self, state: T) -> SharedGuard<'_, T> {
+    fn set(&self, state: T, call_limit: usize) -> SharedGuard<'_, T> {
         let guard = self.write_lock.lock();
+        let counters = Arc::new(CallCounters::new(call_limit));
+        *self.counters.write() = Some(Arc::clone(&counters));
         *self.lock().borrow_mut() = Some(state);
 
         SharedGuard {
-            _guard: guard,
             mock: self,
+            counters,
+            write_guard: Some(guard),
         }
     }
 }
@@ -128,6 +150,10 @@ impl<'a, T: 'static> LockMock<'a, T> for Shared<T> {
     fn lock(&'a self) -> Self::EmptyGuard {
         self.write_lock.lock()
     }
+
+    fn try_lock(&'a self) -> Option<Self::EmptyGuard> {
+        self.write_lock.try_lock()
+    }
 }
 
 /// Shared reference to mock state.
@@ -162,24 +188,225 @@ impl<'a, T> SharedRef<'a, T> {
 #[cfg_attr(docsrs, doc(cfg(feature = "shared")))]
 pub struct SharedGuard<'a, T> {
     mock: &'a Shared<T>,
-    _guard: MutexGuard<'a, ()>,
+    counters: Arc<CallCounters>,
+    /// Exclusive lock on setting the state; `None` if the guard is leaked.
+    write_guard: Option<MutexGuard<'a, ()>>,
 }
 
 impl<T: 'static> Guard<T> for SharedGuard<'_, T> {
+    fn counters(&self) -> &CallCounters {
+        &self.counters
+    }
+
+    fn leak(mut self) {
+        // Release the lock, but keep the state set.
+        self.write_guard = None;
+        mem::forget(self);
+    }
+
     fn with<R>(&mut self, action: impl FnOnce(&mut T) -> R) -> R {
         let locked = self.mock.lock();
         let mut borrowed = locked.borrow_mut();
         action(borrowed.as_mut().unwrap())
     }
 
+    fn with_ref<R>(&self, action: impl FnOnce(&T) -> R) -> R {
+        let locked = self.mock.lock();
+        let borrowed = locked.borrow();
+        action(borrowed.as_ref().unwrap())
+    }
+
     fn into_inner(self) -> T {
         self.mock.lock().take().unwrap()
     }
+
+    fn try_into_inner(self) -> Result<T, Self> {
+        let state = self.mock.inner.try_lock().and_then(|locked| {
+            let mut borrowed = locked.try_borrow_mut().ok()?;
+            borrowed.take()
+        });
+        state.ok_or(self)
+    }
 }
 
 impl<T> Drop for SharedGuard<'_, T> {
     fn drop(&mut self) {
         self.mock.lock().take();
+        *self.mock.counters.write() = None;
+    }
+}
+
+/// Wrapper around [`Mock`](crate::Mock) state that provides cross-thread synchronization
+/// with concurrent access to the state.
+///
+/// This type rarely needs to be used directly; `#[derive(Mock)]` macro with
+/// a `#[mock(shared = "rw")]` attribute on the container will set it up automatically.
+///
+/// Unlike [`Shared`], this wrapper synchronizes access to the state via a read-write lock,
+/// so that mock impls can be called from multiple threads concurrently. This may be useful
+/// in high-contention scenarios, e.g., when spying on a function called from many threads.
+/// The state must be `Sync` (e.g., use atomics or mutexes for the mutable parts
+/// of the state), and the wrapper cannot be used together with the [`Mut`](crate::Mut)
+/// wrapper (i.e., `#[mock(mut)]`). Recursive mock calls are supported.
+///
+/// Setting the state is synchronized in the same way as for [`Shared`], and the same pitfalls
+/// apply. Note that [`MockGuard::with()`](crate::MockGuard::with()) blocks until all mock calls
+/// in progress are completed.
+///
+/// # Pitfalls
+///
+/// [`CallReal`](crate::CallReal) should be used with care. A [`RealCallSwitch`](crate::RealCallSwitch)
+/// is a part of the state, so it is shared by all concurrent mock calls: while a guard
+/// produced by [`CallReal::call_real()`](crate::CallReal::call_real()) in one mock impl
+/// is alive, calls on other threads are delegated to the real implementation as well.
+/// Thus, partial mocking / spying is only reliable if calls using it do not run concurrently.
+///
+/// # Examples
+///
+/// ```
+/// use mimicry::{mock, CheckRealCall, Mock};
+/// # use std::{sync::atomic::{AtomicU32, Ordering}, thread};
+///
+/// #[derive(Debug, Default, Mock)]
+/// #[mock(shared = "rw")]
+/// // ^ use the `SharedRw` wrapper
+/// struct MockState {
+///     calls: AtomicU32,
+/// }
+///
+/// impl CheckRealCall for MockState {}
+///
+/// impl MockState {
+///     fn answer(&self) -> u32 {
+///         // Mock impls may be called concurrently.
+///         self.calls.fetch_add(1, Ordering::Relaxed);
+///         23
+///     }
+/// }
+///
+/// #[mock(using = "MockState")]
+/// fn answer() -> u32 { 42 }
+///
+/// let mock_guard = MockState::default().set_as_mock();
+/// let threads: Vec<_> = (0..5).map(|_| thread::spawn(answer)).collect();
+/// for handle in threads {
+///     assert_eq!(handle.join().unwrap(), 23);
+/// }
+/// let state = mock_guard.into_inner();
+/// assert_eq!(state.calls.into_inner(), 5);
+/// ```
+#[derive(Debug)]
+#[cfg_attr(docsrs, doc(cfg(feature = "shared")))]
+pub struct SharedRw<T> {
+    inner: RwLock<Option<T>>,
+    write_lock: Mutex<()>,
+    counters: RwLock<Option<Arc<CallCounters>>>,
+}
+
+impl<T> Default for SharedRw<T> {
+    fn default() -> Self {
+        Self {
+            inner: RwLock::new(None),
+            write_lock: Mutex::new(()),
+            counters: RwLock::new(None),
+        }
+    }
+}
+
+impl<T> CountCalls for SharedRw<T> {
+    fn with_counters<R>(&self, action: impl FnOnce(&CallCounters) -> R) -> Option<R> {
+        self.counters.read().as_deref().map(action)
+    }
+}
+
+impl<'a, T: 'static> GetMock<'a, T> for SharedRw<T> {
+    type Ref = MappedRwLockReadGuard<'a, T>;
+
+    fn get(&'a self) -> Option<Self::Ref> {
+        // Recursive locking is necessary to support recursive calls to mocked functions.
+        let guard = self.inner.read_recursive();
+        RwLockReadGuard::try_map(guard, Option::as_ref).ok()
+    }
+}
+
+impl<'a, T: 'static> SetMock<'a, T> for SharedRw<T> {
+    type Guard = SharedRwGuard<'a, T>;
+
+    fn set(&self, state: T, call_limit: usize) -> SharedRwGuard<'_, T> {
+        let guard = self.write_lock.lock();
+        let counters = Arc::new(CallCounters::new(call_limit));
+        *self.counters.write() = Some(Arc::clone(&counters));
+        *self.inner.write() = Some(state);
+
+        SharedRwGuard {
+            mock: self,
+            counters,
+            write_guard: Some(guard),
+        }
+    }
+}
+
+impl<'a, T: 'static> LockMock<'a, T> for SharedRw<T> {
+    type EmptyGuard = MutexGuard<'a, ()>;
+
+    fn lock(&'a self) -> Self::EmptyGuard {
+        self.write_lock.lock()
+    }
+
+    fn try_lock(&'a self) -> Option<Self::EmptyGuard> {
+        self.write_lock.try_lock()
+    }
+}
+
+/// Exclusive lock on the [`SharedRw`] mock state.
+#[derive(Debug)]
+#[cfg_attr(docsrs, doc(cfg(feature = "shared")))]
+pub struct SharedRwGuard<'a, T> {
+    mock: &'a SharedRw<T>,
+    counters: Arc<CallCounters>,
+    /// Exclusive lock on setting the state; `None` if the guard is leaked.
+    write_guard: Option<MutexGuard<'a, ()>>,
+}
+
+impl<T: 'static> Guard<T> for SharedRwGuard<'_, T> {
+    fn counters(&self) -> &CallCounters {
+        &self.counters
+    }
+
+    fn leak(mut self) {
+        // Release the lock, but keep the state set.
+        self.write_guard = None;
+        mem::forget(self);
+    }
+
+    fn with<R>(&mut self, action: impl FnOnce(&mut T) -> R) -> R {
+        let mut locked = self.mock.inner.write();
+        action(locked.as_mut().unwrap())
+    }
+
+    fn with_ref<R>(&self, action: impl FnOnce(&T) -> R) -> R {
+        let locked = self.mock.inner.read();
+        action(locked.as_ref().unwrap())
+    }
+
+    fn into_inner(self) -> T {
+        self.mock.inner.write().take().unwrap()
+    }
+
+    fn try_into_inner(self) -> Result<T, Self> {
+        let state = self
+            .mock
+            .inner
+            .try_write()
+            .and_then(|mut locked| locked.take());
+        state.ok_or(self)
+    }
+}
+
+impl<T> Drop for SharedRwGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mock.inner.write().take();
+        *self.mock.counters.write() = None;
     }
 }
 
@@ -192,4 +419,6 @@ mod tests {
 
     assert_impl_all!(Shared<()>: Send, Sync);
     assert_impl_all!(Static<Shared<()>>: Send, Sync);
+    assert_impl_all!(SharedRw<()>: Send, Sync);
+    assert_impl_all!(Static<SharedRw<()>>: Send, Sync);
 }
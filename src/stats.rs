@@ -0,0 +1,262 @@
+//! Call counters and per-function statistics of calls dispatched to mock / real impls.
+
+use parking_lot::Mutex;
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use std::{borrow::Cow, collections::HashMap};
+
+/// Counters of calls dispatched to a mock state. A new set of counters is created each time
+/// the state is set; it is shared by the mock wrapper and the guard returned when setting
+/// the state, and is restored together with the state if guards are stacked.
+#[doc(hidden)] // only used via `CountCalls`
+#[derive(Debug)]
+pub struct CallCounters {
+    calls: AtomicUsize,
+    /// Maximum number of calls dispatched to the state.
+    call_limit: usize,
+    stats: DispatchStats,
+}
+
+impl CallCounters {
+    pub(crate) fn new(call_limit: usize) -> Self {
+        Self {
+            calls: AtomicUsize::new(0),
+            call_limit,
+            stats: DispatchStats::default(),
+        }
+    }
+
+    pub(crate) fn record_call(&self) {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn call_count(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn record_dispatch(&self, function: &'static str, mocked: bool) {
+        self.stats.record(function, mocked);
+    }
+
+    pub(crate) fn dispatch_stats(&self) -> &DispatchStats {
+        &self.stats
+    }
+
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.call_count() >= self.call_limit
+    }
+}
+
+/// Numbers of calls to a single function.
+#[derive(Debug, Clone, Copy, Default)]
+struct Counts {
+    mock: usize,
+    real: usize,
+}
+
+/// Statistics of calls to mocked functions dispatched to the mock impls or delegated
+/// to the real impls (e.g., via [`CallReal`](crate::CallReal)) while the mock state is set.
+/// Calls made while the state is not set are not recorded.
+///
+/// Statistics are only collected for mock states with the `stats` attribute,
+/// and are available via [`MockGuard::dispatch_stats()`](crate::MockGuard::dispatch_stats())
+/// or a `dispatch_stats()` function generated by the [`Mock`](crate::Mock) derive macro. Like the [call count](crate::MockGuard::call_count()), statistics are collected
+/// separately each time the state is set.
+///
+/// Functions are identified by their path, e.g., `my_crate::module::function`
+/// or `my_crate::Type::method`. Generic args are not a part of the path, and trait methods
+/// are identified by the implementing type (e.g., `my_crate::Type::method` for
+/// `<my_crate::Type as Trait>::method`). Paths are obtained via [`type_name()`],
+/// so they may be affected by its quirks; e.g., functions nested in other functions are
+/// identified with their parent function path. Methods of this type accept either
+/// a full path or its suffix (e.g., `method` or `Type::method`); in the latter case,
+/// the numbers of calls to all matching functions are summed.
+///
+/// [`type_name()`]: core::any::type_name()
+///
+/// # Examples
+///
+/// ```
+/// # use mimicry::{mock, CallReal, Mock, RealCallSwitch};
+/// #[mock(using = "ValueMock")]
+/// fn answer(value: usize) -> usize { value }
+///
+/// #[derive(Default, Mock, CallReal)]
+/// #[mock(stats)]
+/// struct ValueMock {
+///     switch: RealCallSwitch,
+/// }
+///
+/// impl ValueMock {
+///     fn answer(&self, value: usize) -> usize {
+///         if value == 0 {
+///             self.call_real().scope(|| answer(value))
+///         } else {
+///             value + 1
+///         }
+///     }
+/// }
+///
+/// let guard = ValueMock::default().set_as_mock();
+/// assert_eq!(answer(0), 0);
+/// assert_eq!(answer(1), 2);
+/// let stats = guard.dispatch_stats();
+/// assert_eq!(stats.mock_calls("answer"), 2);
+/// assert_eq!(stats.real_calls("answer"), 1);
+/// ```
+#[derive(Default)]
+pub struct DispatchStats {
+    counts: Mutex<HashMap<&'static str, Counts>>,
+}
+
+impl fmt::Debug for DispatchStats {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("DispatchStats")
+            .field("counts", &*self.counts.lock())
+            .finish()
+    }
+}
+
+impl Clone for DispatchStats {
+    fn clone(&self) -> Self {
+        Self {
+            counts: Mutex::new(self.counts.lock().clone()),
+        }
+    }
+}
+
+impl DispatchStats {
+    pub(crate) fn record(&self, function: &'static str, mocked: bool) {
+        let mut counts = self.counts.lock();
+        let counts = counts.entry(function).or_default();
+        if mocked {
+            counts.mock += 1;
+        } else {
+            counts.real += 1;
+        }
+    }
+
+    fn sum(&self, function: &str, selector: fn(&Counts) -> usize) -> usize {
+        let counts = self.counts.lock();
+        counts
+            .iter()
+            .filter(|(path, _)| Self::matches(path, function))
+            .map(|(_, counts)| selector(counts))
+            .sum()
+    }
+
+    pub(crate) fn matches(path: &str, function: &str) -> bool {
+        Self::normalize_path(path)
+            .strip_suffix(function)
+            .map_or(false, |prefix| prefix.is_empty() || prefix.ends_with("::"))
+    }
+
+    /// Normalizes a function path returned by `type_name()`: removes generic args
+    /// and closure segments, and replaces qualified paths (`<Type as Trait>::method`)
+    /// with `Type::method`.
+    fn normalize_path(path: &str) -> Cow<'_, str> {
+        if !path.contains(['<', '{']) {
+            return Cow::Borrowed(path);
+        }
+
+        let mut normalized = String::with_capacity(path.len());
+        let mut generics_depth = 0_usize;
+        let mut prev_char = None;
+        for ch in path.chars() {
+            let after_ident =
+                prev_char.map_or(false, |prev: char| prev.is_alphanumeric() || prev == '_');
+            match ch {
+                '<' if generics_depth > 0 || after_ident => generics_depth += 1,
+                '>' if generics_depth > 0 => generics_depth -= 1,
+                _ if generics_depth > 0 => { /* skip generic args */ }
+                _ => normalized.push(ch),
+            }
+            prev_char = Some(ch);
+        }
+
+        if let Some(qualified) = normalized.strip_prefix('<') {
+            let unqualified = qualified.split_once(" as ").and_then(|(ty, rest)| {
+                let (_, method) = rest.split_once('>')?;
+                Some(format!("{ty}{method}"))
+            });
+            if let Some(unqualified) = unqualified {
+                normalized = unqualified;
+            }
+        }
+        Cow::Owned(normalized.replace("::{{closure}}", ""))
+    }
+
+    /// Returns the number of calls to the specified function dispatched to the mock impl.
+    pub fn mock_calls(&self, function: &str) -> usize {
+        self.sum(function, |counts| counts.mock)
+    }
+
+    /// Returns the number of calls to the specified function delegated to the real impl
+    /// while the mock state was set.
+    pub fn real_calls(&self, function: &str) -> usize {
+        self.sum(function, |counts| counts.real)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use static_assertions::assert_impl_all;
+
+    assert_impl_all!(DispatchStats: Send, Sync);
+
+    #[test]
+    fn matching_function_paths() {
+        let stats = DispatchStats::default();
+        stats.record("my_crate::tests::answer", true);
+        stats.record("my_crate::tests::answer", false);
+        stats.record("my_crate::answer", true);
+        stats.record("my_crate::tests::other_answer", true);
+
+        assert_eq!(stats.mock_calls("my_crate::tests::answer"), 1);
+        assert_eq!(stats.mock_calls("tests::answer"), 1);
+        assert_eq!(stats.mock_calls("answer"), 2);
+        assert_eq!(stats.real_calls("answer"), 1);
+        assert_eq!(stats.mock_calls("other_answer"), 1);
+        assert_eq!(stats.mock_calls("swer"), 0);
+        assert_eq!(stats.real_calls("other_answer"), 0);
+    }
+
+    #[test]
+    fn normalizing_function_paths() {
+        let paths = [
+            ("my_crate::answer", "my_crate::answer"),
+            ("my_crate::Type<_>::new", "my_crate::Type::new"),
+            ("my_crate::Type<_, Vec<_>>::new", "my_crate::Type::new"),
+            (
+                "<my_crate::Type<u8> as my_crate::Trait<Vec<u8>>>::method",
+                "my_crate::Type::method",
+            ),
+            ("<&str as my_crate::Trait>::method", "&str::method"),
+            ("my_crate::answer::{{closure}}", "my_crate::answer"),
+        ];
+        for (path, expected) in paths {
+            assert_eq!(DispatchStats::normalize_path(path), expected);
+        }
+    }
+
+    #[test]
+    fn matching_method_paths() {
+        let stats = DispatchStats::default();
+        stats.record("my_crate::Type<_>::new", true);
+        stats.record("my_crate::Other::new", true);
+        stats.record("<my_crate::Type<u8> as my_crate::Trait>::method", false);
+        stats.record("my_crate::tests::answer::{{closure}}", true);
+
+        assert_eq!(stats.mock_calls("Type::new"), 1);
+        assert_eq!(stats.mock_calls("my_crate::Other::new"), 1);
+        assert_eq!(stats.mock_calls("new"), 2);
+        assert_eq!(stats.real_calls("Type::method"), 1);
+        assert_eq!(stats.mock_calls("tests::answer"), 1);
+    }
+}
@@ -1,8 +1,18 @@
 //! Thread-local mock state wrapper.
 
-use core::cell::{Ref, RefCell, RefMut};
+#[cfg(feature = "fold_states")]
+use ouroboros::self_referencing;
+#[cfg(feature = "fold_states")]
+use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
 
-use crate::{GetMock, Guard, SetMock};
+use core::{
+    cell::{Ref, RefCell},
+    marker::PhantomData,
+    mem, ops,
+};
+use std::{sync::Arc, thread};
+
+use crate::{stats::CallCounters, CountCalls, GetMock, Guard, SetMock};
 
 /// Thread-local mock state wrapper.
 ///
@@ -13,7 +23,7 @@ use crate::{GetMock, Guard, SetMock};
 /// is spawned during test, mocked functions called from this thread will always use the real
 /// implementations. This behavior is fine in simple cases, i.e., unless mocked functions
 /// are called from multiple threads spawned by a single test. If cross-thread mocking is required,
-/// consider [`Shared`](crate::Shared) wrapper.
+/// consider [`Shared`](crate::Shared) or [`Global`](crate::Global) wrappers.
 ///
 /// # Examples
 ///
@@ -37,9 +47,44 @@ use crate::{GetMock, Guard, SetMock};
 ///     // Can check the state here...
 /// }
 /// ```
+///
+/// ## Aggregating per-thread states
+///
+/// With the `fold_states` crate feature, states set on all threads can be aggregated
+/// via `Self::fold_states()` (or the corresponding `Mock::fold_states()` method), e.g.,
+/// to collect metrics recorded by worker threads. Note that a state is unset once
+/// the corresponding guard is dropped, so worker threads should keep their guards
+/// until the states are folded. Alternatively (and without the feature), states
+/// can be returned from threads:
+///
+/// ```
+/// # use mimicry::{mock, Mock};
+/// # use std::thread;
+/// #[derive(Default, Mock)]
+/// #[mock(mut)]
+/// struct CallCounter(usize);
+/// # impl CallCounter {
+/// #     fn work(this: &mimicry::Mut<Self>) { this.borrow().0 += 1; }
+/// # }
+/// # #[mock(using = "CallCounter")]
+/// # fn work() {}
+///
+/// let handles: Vec<_> = (0..4)
+///     .map(|i| thread::spawn(move || {
+///         let guard = CallCounter::default().set_as_mock();
+///         (0..=i).for_each(|_| work());
+///         guard.into_inner()
+///     }))
+///     .collect();
+/// let total_calls = handles
+///     .into_iter()
+///     .map(|handle| handle.join().unwrap())
+///     .fold(0, |acc, state| acc + state.0);
+/// assert_eq!(total_calls, 1 + 2 + 3 + 4);
+/// ```
 #[derive(Debug)]
 pub struct ThreadLocal<T: Send> {
-    tls: thread_local::ThreadLocal<ThreadLocalInner<T>>,
+    tls: thread_local::ThreadLocal<StacksCell<T>>,
 }
 
 impl<T: Send> Default for ThreadLocal<T> {
@@ -50,77 +95,261 @@ impl<T: Send> Default for ThreadLocal<T> {
     }
 }
 
+#[cfg(feature = "fold_states")]
+#[cfg_attr(docsrs, doc(cfg(feature = "fold_states")))]
+impl<T: Send> ThreadLocal<T> {
+    /// Folds states currently set on all threads, including stacked states on a single thread.
+    /// This can be used to aggregate states set by several threads (e.g., metrics recorded
+    /// by worker threads). States are not ordered in any particular way.
+    ///
+    /// Folding blocks while the states on a thread are accessed by this thread
+    /// (e.g., while a mock impl is executed).
+    ///
+    /// # Panics
+    ///
+    /// Panics if a state set on the current thread is being accessed, e.g., if called
+    /// from a guard closure.
+    pub fn fold_states<R>(&self, init: R, mut fold: impl FnMut(R, &T) -> R) -> R {
+        self.tls.iter().fold(init, |acc, stacks| {
+            let stacks = stacks.lock();
+            let states = stacks.states.borrow();
+            states.iter().fold(acc, &mut fold)
+        })
+    }
+
+    /// Drains states set on all threads.
+    pub fn into_states(self) -> Vec<T> {
+        self.tls
+            .into_iter()
+            .flat_map(|stacks| stacks.into_inner().states.into_inner())
+            .collect()
+    }
+}
+
+/// Per-thread mock states.
 #[derive(Debug)]
-struct ThreadLocalInner<T> {
-    inner: RefCell<Option<T>>,
-    write_lock: RefCell<()>,
+struct Stacks<T> {
+    /// States set on a thread. Since guards on a single thread may be stacked,
+    /// the last state is the current one.
+    states: RefCell<Vec<T>>,
+    /// Call counters for each of `states`. Stored separately so that counters can be accessed
+    /// while a state is borrowed.
+    counters: RefCell<Vec<Arc<CallCounters>>>,
 }
 
-impl<T> Default for ThreadLocalInner<T> {
+impl<T> Default for Stacks<T> {
     fn default() -> Self {
         Self {
-            inner: RefCell::new(None),
-            write_lock: RefCell::new(()),
+            states: RefCell::new(Vec::new()),
+            counters: RefCell::new(Vec::new()),
         }
     }
 }
 
+/// With the `fold_states` feature, per-thread states are wrapped in a lock, so that they can be
+/// read from other threads. The lock is only contended if states are
+/// [folded](ThreadLocal::fold_states()) on another thread, but it still adds overhead
+/// to each mocked call. The lock is reentrant to support recursive calls to mocked functions.
+#[cfg(feature = "fold_states")]
+type StacksCell<T> = ReentrantMutex<Stacks<T>>;
+#[cfg(not(feature = "fold_states"))]
+type StacksCell<T> = Stacks<T>;
+
+#[cfg(feature = "fold_states")]
+fn lock<T>(cell: &StacksCell<T>) -> impl ops::Deref<Target = Stacks<T>> + '_ {
+    cell.lock()
+}
+
+#[cfg(not(feature = "fold_states"))]
+fn lock<T>(cell: &StacksCell<T>) -> impl ops::Deref<Target = Stacks<T>> + '_ {
+    cell
+}
+
+impl<T: Send> CountCalls for ThreadLocal<T> {
+    fn with_counters<R>(&self, action: impl FnOnce(&CallCounters) -> R) -> Option<R> {
+        let stacks = lock(self.tls.get_or_default());
+        let counters = stacks.counters.borrow();
+        counters.last().map(|counters| action(counters))
+    }
+}
+
 impl<'a, T: Send + 'static> GetMock<'a, T> for ThreadLocal<T> {
+    #[cfg(feature = "fold_states")]
+    type Ref = ThreadLocalRef<'a, T>;
+    #[cfg(not(feature = "fold_states"))]
     type Ref = Ref<'a, T>;
 
-    fn get(&'a self) -> Option<Ref<'a, T>> {
-        let cell = self.tls.get_or_default();
-        let borrow = cell.inner.borrow();
-        if borrow.is_some() {
-            Some(Ref::map(borrow, |option| option.as_ref().unwrap()))
+    #[cfg(feature = "fold_states")]
+    fn get(&'a self) -> Option<Self::Ref> {
+        let stacks = self.tls.get_or_default().lock();
+        if stacks.states.borrow().is_empty() {
+            None
         } else {
+            Some(ThreadLocalRef::from_guard(stacks))
+        }
+    }
+
+    #[cfg(not(feature = "fold_states"))]
+    fn get(&'a self) -> Option<Self::Ref> {
+        let states = self.tls.get_or_default().states.borrow();
+        if states.is_empty() {
             None
+        } else {
+            Some(Ref::map(states, |states| states.last().unwrap()))
         }
     }
+
+    fn is_set(&'a self) -> bool {
+        let stacks = lock(self.tls.get_or_default());
+        // The state is exclusively borrowed only if it's set and is being accessed via a guard.
+        let is_set = stacks
+            .states
+            .try_borrow()
+            .map_or(true, |states| !states.is_empty());
+        is_set
+    }
 }
 
 impl<'a, T: Send + 'static> SetMock<'a, T> for ThreadLocal<T> {
     type Guard = ThreadLocalGuard<'a, T>;
 
-    fn set(&self, state: T) -> ThreadLocalGuard<'_, T> {
+    fn set(&self, state: T, call_limit: usize) -> ThreadLocalGuard<'_, T> {
         let cell = self.tls.get_or_default();
-        let guard = cell.write_lock.try_borrow_mut().unwrap_or_else(|_| {
-            panic!("cannot set mock state while the previous state is active");
-        });
-        *cell.inner.borrow_mut() = Some(state);
+        let counters = Arc::new(CallCounters::new(call_limit));
+        let stacks = lock(cell);
+        let mut states = stacks.states.borrow_mut();
+        let depth = states.len();
+        states.push(state);
+        drop(states);
+        stacks.counters.borrow_mut().push(Arc::clone(&counters));
 
         ThreadLocalGuard {
-            mock: &cell.inner,
-            _guard: guard,
+            stacks: cell,
+            depth,
+            counters,
+            _not_send: PhantomData,
         }
     }
 }
 
+/// Shared reference to a thread-local mock state.
+#[cfg(feature = "fold_states")]
+#[self_referencing]
+pub struct ThreadLocalRef<'a, T> {
+    guard: ReentrantMutexGuard<'a, Stacks<T>>,
+    #[borrows(guard)]
+    #[covariant]
+    state: Ref<'this, T>,
+}
+
+#[cfg(feature = "fold_states")]
+impl<T> ops::Deref for ThreadLocalRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.borrow_state()
+    }
+}
+
+#[cfg(feature = "fold_states")]
+impl<'a, T> ThreadLocalRef<'a, T> {
+    fn from_guard(guard: ReentrantMutexGuard<'a, Stacks<T>>) -> Self {
+        ThreadLocalRefBuilder {
+            guard,
+            state_builder: |guard| Ref::map(guard.states.borrow(), |states| states.last().unwrap()),
+        }
+        .build()
+    }
+}
+
 /// Exclusive guard on a [`ThreadLocal`] mock.
 ///
 /// This guard is mostly useful for mock state manipulation; unlike
 /// [`SharedGuard`](crate::SharedGuard), it does not provide meaningful synchronization.
-/// If [`SetMock::set()`] is called on a thread that has an active guard, such a call will
-/// panic; calls on other threads (i.e., in tests running concurrently) are not affected.
+/// If [`SetMock::set()`] is called on a thread that has an active guard, the previous state
+/// (together with its call counters) is saved and is restored once the new guard is dropped.
+/// Thus, guards on a single thread form a stack and must be dropped in the reverse order
+/// of their creation; dropping a guard while a guard created after it is alive panics.
+/// Calls on other threads (i.e., in tests running concurrently) are not affected.
 #[derive(Debug)]
 pub struct ThreadLocalGuard<'a, T> {
-    mock: &'a RefCell<Option<T>>,
-    _guard: RefMut<'a, ()>,
+    stacks: &'a StacksCell<T>,
+    /// Index of the guarded state in the stack.
+    depth: usize,
+    counters: Arc<CallCounters>,
+    /// The guard should be dropped on the thread that created it.
+    _not_send: PhantomData<*const ()>,
 }
 
 impl<T> Drop for ThreadLocalGuard<'_, T> {
     fn drop(&mut self) {
-        self.mock.borrow_mut().take();
+        let stacks = lock(self.stacks);
+        let mut states = stacks.states.borrow_mut();
+        let is_top = states.len() <= self.depth + 1;
+        // States above this one are removed as well, so that the previous state is restored
+        // even if the guards are dropped out of order (e.g., when unwinding).
+        states.truncate(self.depth);
+        drop(states);
+        stacks.counters.borrow_mut().truncate(self.depth);
+        drop(stacks);
+
+        assert!(is_top || thread::panicking(), "{}", NON_LIFO_MSG);
     }
 }
 
+const NON_LIFO_MSG: &str = "Thread-local mock guard is dropped or consumed while a guard \
+    created after it on the same thread is alive. Guards must be dropped in the reverse order \
+    of their creation";
+const UNSET_MSG: &str = "Thread-local mock state was unset by a guard created before this one";
+
 impl<T> Guard<T> for ThreadLocalGuard<'_, T> {
+    fn counters(&self) -> &CallCounters {
+        &self.counters
+    }
+
+    fn leak(self) {
+        mem::forget(self);
+    }
+
     fn with<R>(&mut self, action: impl FnOnce(&mut T) -> R) -> R {
-        action(self.mock.borrow_mut().as_mut().unwrap())
+        let stacks = lock(self.stacks);
+        let mut states = stacks.states.borrow_mut();
+        action(states.get_mut(self.depth).expect(UNSET_MSG))
+    }
+
+    fn with_ref<R>(&self, action: impl FnOnce(&T) -> R) -> R {
+        let stacks = lock(self.stacks);
+        let states = stacks.states.borrow();
+        action(states.get(self.depth).expect(UNSET_MSG))
     }
 
     fn into_inner(self) -> T {
-        self.mock.borrow_mut().take().unwrap()
+        let stacks = lock(self.stacks);
+        let mut states = stacks.states.borrow_mut();
+        let state = if states.len() == self.depth + 1 {
+            states.pop()
+        } else {
+            None
+        };
+        drop(states);
+        drop(stacks);
+        drop(self); // panics if there are states above this one
+        state.expect(UNSET_MSG)
+    }
+
+    fn try_into_inner(self) -> Result<T, Self> {
+        let stacks = lock(self.stacks);
+        let state = match stacks.states.try_borrow_mut() {
+            Ok(mut states) if states.len() == self.depth + 1 => states.pop(),
+            _ => None,
+        };
+        drop(stacks);
+        if let Some(state) = state {
+            drop(self);
+            Ok(state)
+        } else {
+            Err(self)
+        }
     }
 }
 
@@ -134,4 +363,32 @@ mod tests {
 
     assert_impl_all!(ThreadLocal<Cell<u8>>: Send, Sync);
     assert_impl_all!(Static<ThreadLocal<Cell<u8>>>: Send, Sync);
+
+    #[cfg(feature = "fold_states")]
+    #[test]
+    fn folding_and_draining_states() {
+        let mock = Arc::new(ThreadLocal::<u32>::default());
+        let handles: Vec<_> = (1..=3)
+            .map(|i| {
+                let mock = Arc::clone(&mock);
+                thread::spawn(move || {
+                    mock.set(i, usize::MAX).leak();
+                    mock.set(i * 10, usize::MAX).leak();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let guard = mock.set(100, usize::MAX);
+        assert_eq!(mock.fold_states(0, |acc, &state| acc + state), 166);
+        drop(guard);
+        assert_eq!(mock.fold_states(0, |acc, &state| acc + state), 66);
+
+        let mock = Arc::try_unwrap(mock).unwrap();
+        let mut states = mock.into_states();
+        states.sort_unstable();
+        assert_eq!(states, [1, 2, 3, 10, 20, 30]);
+    }
 }
@@ -1,7 +1,17 @@
 //! Lower-level traits used to generalize the concept of mock state shared between tests
 //! and the tested code.
 
-use core::{cell::Cell, future::Future, ops};
+use parking_lot::Mutex;
+
+use crate::{stats::CallCounters, DispatchStats};
+
+use core::{
+    fmt,
+    future::Future,
+    mem, ops,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::sync::Arc;
 
 /// Interface to get mock state.
 #[doc(hidden)] // only used by generated code
@@ -14,20 +24,82 @@ pub trait GetMock<'a, T> {
 
     /// Returns a reference to the shared mock state, or `None` if the mock is not set.
     fn get(&'a self) -> Option<Self::Ref>;
+
+    /// Checks whether the mock state is set without dispatching calls to it.
+    fn is_set(&'a self) -> bool {
+        self.get().is_some()
+    }
+}
+
+/// Interface to count calls dispatched to the mock state.
+#[doc(hidden)] // only used by generated code
+pub trait CountCalls {
+    /// Performs an action on the call counters of the current mock state. Returns `None`
+    /// if the state is not set.
+    fn with_counters<R>(&self, action: impl FnOnce(&CallCounters) -> R) -> Option<R>;
+
+    /// Records a call dispatched to the mock impl.
+    fn record_call(&self) {
+        self.with_counters(CallCounters::record_call);
+    }
+
+    /// Returns the number of calls dispatched to the mock impl since the current state was set.
+    fn call_count(&self) -> usize {
+        self.with_counters(CallCounters::call_count).unwrap_or(0)
+    }
+
+    /// Records a call dispatched to the mock impl or delegated to the real impl.
+    fn record_dispatch(&self, function: &'static str, mocked: bool) {
+        self.with_counters(|counters| counters.record_dispatch(function, mocked));
+    }
+
+    /// Returns a snapshot of per-function dispatch statistics since the current state was set.
+    fn dispatch_stats(&self) -> DispatchStats {
+        self.with_counters(|counters| counters.dispatch_stats().clone())
+            .unwrap_or_default()
+    }
+
+    /// Checks whether the call limit specified when [setting](SetMock::set()) the current state
+    /// is reached.
+    fn is_exhausted(&self) -> bool {
+        self.with_counters(CallCounters::is_exhausted)
+            .unwrap_or(false)
+    }
 }
 
 /// Interface to set up mock state.
 pub trait SetMock<'a, T> {
     type Guard: 'a + Guard<T>;
 
-    fn set(&'a self, state: T) -> Self::Guard;
+    /// Sets the state. No more than `call_limit` calls will be dispatched to the state;
+    /// afterwards, it behaves as if it were unset.
+    fn set(&'a self, state: T, call_limit: usize) -> Self::Guard;
 }
 
 /// Guard for setting mock state from the test code.
 pub trait Guard<T> {
+    /// Returns call counters for the guarded state.
+    fn counters(&self) -> &CallCounters;
+
+    /// Releases the exclusive lock held by this guard (if any) without unsetting the state.
+    fn leak(self);
+
     fn with<R>(&mut self, action: impl FnOnce(&mut T) -> R) -> R;
 
+    /// Same as `with()`, but provides shared access to the state.
+    fn with_ref<R>(&self, action: impl FnOnce(&T) -> R) -> R;
+
     fn into_inner(self) -> T;
+
+    /// Same as `into_inner()`, but returns the guard back if the state cannot be taken
+    /// without blocking or panicking (e.g., if it's missing or is being accessed).
+    fn try_into_inner(self) -> Result<T, Self>
+    where
+        Self: Sized;
+
+    fn replace(&mut self, state: T) -> T {
+        self.with(|current| mem::replace(current, state))
+    }
 }
 
 /// Interface to lock mock state changes without [setting](SetMock) the state.
@@ -42,6 +114,10 @@ pub trait LockMock<'a, T>: SetMock<'a, T> {
     ///
     /// [shared mocks]: crate::Shared
     fn lock(&'a self) -> Self::EmptyGuard;
+
+    /// Attempts to lock access to the mock state without blocking. Returns `None`
+    /// if the access is currently locked.
+    fn try_lock(&'a self) -> Option<Self::EmptyGuard>;
 }
 
 /// Wrapper that allows proxying exclusive accesses to the wrapped object. `Wrap<T>`
@@ -53,6 +129,8 @@ pub trait Wrap<T>: From<T> {
     fn into_inner(self) -> T;
     /// Returns an exclusive reference to the wrapped value.
     fn as_mut(&mut self) -> &mut T;
+    /// Provides shared access to the wrapped value.
+    fn with_inner<R>(&self, action: impl FnOnce(&T) -> R) -> R;
 }
 
 impl<T> Wrap<T> for T {
@@ -63,9 +141,23 @@ impl<T> Wrap<T> for T {
     fn as_mut(&mut self) -> &mut T {
         self
     }
+
+    fn with_inner<R>(&self, action: impl FnOnce(&T) -> R) -> R {
+        action(self)
+    }
 }
 
 /// Checks whether it is necessary to delegate to real impl instead of the mock.
+#[cfg_attr(
+    diagnostic_attrs,
+    diagnostic::on_unimplemented(
+        message = "mock state `{Self}` does not implement `CheckRealCall`",
+        label = "`CheckRealCall` is not implemented",
+        note = "use `#[mock(auto_check)]` if calls should always be dispatched to the mock impls",
+        note = "otherwise, derive `CallReal` (requires a `RealCallSwitch` field) \
+            or implement `CheckRealCall` manually"
+    )
+)]
 pub trait CheckRealCall {
     /// Performs the check.
     ///
@@ -75,6 +167,59 @@ pub trait CheckRealCall {
     }
 }
 
+/// Selects a variant of the mock impl to dispatch calls to.
+///
+/// This trait is consulted for mocked functions / methods with the `variants` attribute,
+/// such as `#[mock(using = "MyMock", variants(failing))]`. If [`Self::mock_variant()`]
+/// returns the name of one of the declared variants, the call is dispatched
+/// to the mock impl with the name suffixed with `_{variant}` (e.g., `fetch_failing`);
+/// otherwise, the call is dispatched to the primary mock impl (e.g., `fetch`).
+/// This allows switching mock behavior at runtime without dispatching in every mock impl.
+///
+/// The trait is implemented for the [`Mut`](crate::Mut) wrapper if it's implemented
+/// for the wrapped state.
+///
+/// # Examples
+///
+/// ```
+/// # use mimicry::{mock, CheckRealCall, Mock, MockVariant};
+/// #[mock(using = "FetchMock", variants(failing))]
+/// fn fetch(url: &str) -> Result<String, String> {
+///     Ok(format!("real response from {url}"))
+/// }
+///
+/// #[derive(Default, Mock)]
+/// #[mock(auto_check)]
+/// struct FetchMock {
+///     offline: bool,
+/// }
+///
+/// impl MockVariant for FetchMock {
+///     fn mock_variant(&self) -> Option<&'static str> {
+///         if self.offline { Some("failing") } else { None }
+///     }
+/// }
+///
+/// impl FetchMock {
+///     fn fetch(&self, url: &str) -> Result<String, String> {
+///         Ok(format!("mock response from {url}"))
+///     }
+///
+///     fn fetch_failing(&self, url: &str) -> Result<String, String> {
+///         Err(format!("cannot reach {url}"))
+///     }
+/// }
+///
+/// let mut guard = FetchMock::default().set_as_mock();
+/// assert_eq!(fetch("test").unwrap(), "mock response from test");
+/// guard.with(|state| state.offline = true);
+/// assert_eq!(fetch("test").unwrap_err(), "cannot reach test");
+/// ```
+pub trait MockVariant {
+    /// Returns the name of the mock impl variant to use, or `None` to use the primary mock impl.
+    fn mock_variant(&self) -> Option<&'static str>;
+}
+
 /// Controls delegation to real impls. The provided `call_*` methods in this trait can be used
 /// for partial mocking and spying.
 ///
@@ -82,14 +227,14 @@ pub trait CheckRealCall {
 /// for manual implementation. The trait is also implemented for the [`Mut`](crate::Mut)
 /// and [`MockRef`](crate::MockRef) wrappers.
 ///
-/// # Call guard checks
+/// # Nested guards
 ///
-/// [`RealCallGuard`]s returned by [`Self::call_real()`] and [`Self::call_real_once()`]
-/// must not overlap in terms of their lifetime; otherwise, confusion would arise as to
-/// which calls exactly should be delegated to real implementations. This is checked
-/// in runtime when creating a guard.
+/// [`RealCallGuard`]s returned by [`Self::call_real()`] and other `call_real_*()` methods
+/// may be nested. The innermost alive guard determines which calls are delegated
+/// to real implementations; once it is dropped, the mode set by the enclosing guard
+/// (if any) is restored.
 ///
-/// ```should_panic
+/// ```
 /// # use mimicry::{mock, CallReal, Mock, RealCallSwitch};
 /// #[mock(using = "MyMock")]
 /// fn answer() -> u32 { 42 }
@@ -104,12 +249,43 @@ pub trait CheckRealCall {
 ///     fn answer(&self) -> u32 {
 ///         let _guard = self.call_real();
 ///         let real_answer = self.call_real_once().scope(answer);
+///         assert!(self.is_real_active()); // the outer guard is in effect again
+///         real_answer + answer()
+///     }
+/// }
+///
+/// let _guard = MyMock::default().set_as_mock();
+/// assert_eq!(answer(), 84);
+/// ```
+///
+/// # Strict guard checks
+///
+/// Nested guards may be confusing as to which calls exactly are delegated to real
+/// implementations. To prohibit nesting, use a [strict switch](RealCallSwitch::strict()).
+/// In this case, creating a guard while another guard is alive panics.
+///
+/// ```should_panic
+/// # use mimicry::{mock, CallReal, Mock, RealCallSwitch};
+/// #[mock(using = "MyMock")]
+/// fn answer() -> u32 { 42 }
+///
+/// #[derive(Mock, CallReal)]
+/// struct MyMock {
+///     // mock state...
+///     _switch: RealCallSwitch,
+/// }
+///
+/// impl MyMock {
+///     fn answer(&self) -> u32 {
+///         let _guard = self.call_real();
+///         let real_answer = self.call_real_once().scope(answer);
 ///         // ^ will panic here: there is an alive call switch guard
 ///         real_answer + 1
 ///     }
 /// }
 ///
-/// let _guard = MyMock::default().set_as_mock();
+/// let state = MyMock { _switch: RealCallSwitch::strict() };
+/// let _guard = state.set_as_mock();
 /// answer(); // triggers the panic
 /// ```
 // Unfortunately, we cannot define `call_real(&mut self, ..)` to move guard checks
@@ -119,20 +295,33 @@ pub trait CallReal {
     #[doc(hidden)] // low-level implementation detail
     fn access_switch<R>(&self, action: impl FnOnce(&RealCallSwitch) -> R) -> R;
 
+    /// Checks whether a [`RealCallGuard`] produced by one of the `call_real*()` methods
+    /// is currently in effect, i.e., whether some further calls may be delegated
+    /// to the real implementation.
+    ///
+    /// Unlike the [`CheckRealCall`] implementation, this check does not affect the switch
+    /// state; e.g., it does not count towards the calls delegated by [`Self::call_real_once()`].
+    /// A predicate provided to [`Self::call_real_if()`] is not evaluated either; the switch
+    /// is considered active for the entire guard lifetime.
+    fn is_real_active(&self) -> bool {
+        <Self as CallReal>::access_switch(self, RealCallSwitch::is_active)
+    }
+
     /// Delegates all calls to the mocked functions / methods to the real implementation until
     /// the returned [`RealCallGuard`] is dropped.
     ///
     /// # Panics
     ///
-    /// Panics if the real / mock implementation switch is already set to "real"
-    /// (e.g., there is an alive guard produced by an earlier call to [`Self::call_real()`]).
-    /// This may lead to unexpected switch value for the further calls and is thus prohibited.
+    /// Panics if the real / mock implementation switch is [strict](RealCallSwitch::strict())
+    /// and is already set to "real" (e.g., there is an alive guard produced by an earlier call
+    /// to [`Self::call_real()`]).
     fn call_real(&self) -> RealCallGuard<'_, Self> {
-        <Self as CallReal>::access_switch(self, |switch| {
-            switch.assert_inactive();
-            switch.0.set(RealCallMode::Always);
-        });
-        RealCallGuard { controller: self }
+        let depth =
+            <Self as CallReal>::access_switch(self, |switch| switch.activate(RealCallMode::Always));
+        RealCallGuard {
+            controller: self,
+            depth,
+        }
     }
 
     /// Delegates the first call to the mocked functions / methods to the real implementation until
@@ -142,11 +331,163 @@ pub trait CallReal {
     ///
     /// Panics under the same circumstances as [`Self::call_real()`].
     fn call_real_once(&self) -> RealCallGuard<'_, Self> {
-        <Self as CallReal>::access_switch(self, |switch| {
-            switch.assert_inactive();
-            switch.0.set(RealCallMode::Once);
+        self.call_real_n(1)
+    }
+
+    /// Delegates the first `count` calls to the mocked functions / methods to the real
+    /// implementation until the returned [`RealCallGuard`] is dropped. Further calls
+    /// will be directed to the mock.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same circumstances as [`Self::call_real()`].
+    fn call_real_n(&self, count: usize) -> RealCallGuard<'_, Self> {
+        let depth = <Self as CallReal>::access_switch(self, |switch| {
+            switch.activate(RealCallMode::Count(count))
+        });
+        RealCallGuard {
+            controller: self,
+            depth,
+        }
+    }
+
+    /// Delegates calls to the mocked functions / methods to the real implementation
+    /// if the provided `predicate` returns `true`. The predicate is evaluated on each call
+    /// until the returned [`RealCallGuard`] is dropped.
+    ///
+    /// The predicate must be `Send` and `Sync` since it is stored in the mock state,
+    /// which may be shared across threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same circumstances as [`Self::call_real()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, CallReal, Mock, RealCallSwitch};
+    /// # use std::sync::{atomic::{AtomicU32, Ordering}, Arc};
+    /// #[mock(using = "CountingMock")]
+    /// fn count(n: u32) -> u32 {
+    ///     if n == 0 { 0 } else { 1 + count(n - 1) }
+    /// }
+    ///
+    /// #[derive(Default, Mock, CallReal)]
+    /// struct CountingMock {
+    ///     mock_calls: Arc<AtomicU32>,
+    ///     switch: RealCallSwitch,
+    /// }
+    ///
+    /// impl CountingMock {
+    ///     fn count(&self, n: u32) -> u32 {
+    ///         let mock_calls = Arc::clone(&self.mock_calls);
+    ///         mock_calls.fetch_add(1, Ordering::Relaxed);
+    ///         // Delegate to the real impl every other call.
+    ///         let guard = self.call_real_if(move || {
+    ///             mock_calls.load(Ordering::Relaxed) % 2 == 1
+    ///         });
+    ///         guard.scope(|| count(n))
+    ///     }
+    /// }
+    ///
+    /// let guard = CountingMock::default().set_as_mock();
+    /// assert_eq!(count(5), 5);
+    /// let mock_calls = guard.into_inner().mock_calls;
+    /// assert_eq!(mock_calls.load(Ordering::Relaxed), 1);
+    /// ```
+    fn call_real_if<F>(&self, predicate: F) -> RealCallGuard<'_, Self>
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        let depth = <Self as CallReal>::access_switch(self, |switch| {
+            switch.activate(RealCallMode::Predicate(Arc::new(predicate)))
         });
-        RealCallGuard { controller: self }
+        RealCallGuard {
+            controller: self,
+            depth,
+        }
+    }
+
+    /// Executes the provided closure under a [`Self::call_real()`] guard and returns its output.
+    /// This is a shortcut for `self.call_real().scope(real)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same circumstances as [`Self::call_real()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, CallReal, Mock, RealCallSwitch};
+    /// #[mock(using = "ValueMock")]
+    /// fn answer(x: u32) -> u32 { x + 1 }
+    ///
+    /// #[derive(Default, Mock, CallReal)]
+    /// struct ValueMock {
+    ///     switch: RealCallSwitch,
+    /// }
+    ///
+    /// impl ValueMock {
+    ///     fn answer(&self, x: u32) -> u32 {
+    ///         self.call_real_with(|| answer(x)) * 2
+    ///     }
+    /// }
+    ///
+    /// let _guard = ValueMock::default().set_as_mock();
+    /// assert_eq!(answer(20), 42);
+    /// ```
+    fn call_real_with<R>(&self, real: impl FnOnce() -> R) -> R {
+        self.call_real().scope(real)
+    }
+
+    /// Executes the provided closure under a [`Self::call_real_once()`] guard and returns
+    /// its output. This is a shortcut for `self.call_real_once().scope(real)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same circumstances as [`Self::call_real()`].
+    fn call_real_once_with<R>(&self, real: impl FnOnce() -> R) -> R {
+        self.call_real_once().scope(real)
+    }
+
+    /// Calls the real implementation by executing `real` under a [`Self::call_real()`] guard,
+    /// passes a reference to the result to `record` and returns the result. This is a shortcut
+    /// for a common spying pattern.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same circumstances as [`Self::call_real()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, CallReal, Mock, RealCallSwitch};
+    /// # use std::sync::Mutex;
+    /// #[mock(using = "SpyMock")]
+    /// fn answer(x: u32) -> u32 { x + 1 }
+    ///
+    /// #[derive(Default, Mock, CallReal)]
+    /// struct SpyMock {
+    ///     answers: Mutex<Vec<u32>>,
+    ///     switch: RealCallSwitch,
+    /// }
+    ///
+    /// impl SpyMock {
+    ///     fn answer(&self, x: u32) -> u32 {
+    ///         self.spy(|| answer(x), |&value| self.answers.lock().unwrap().push(value))
+    ///     }
+    /// }
+    ///
+    /// let guard = SpyMock::default().set_as_mock();
+    /// assert_eq!(answer(1), 2);
+    /// assert_eq!(answer(41), 42);
+    /// let answers = guard.into_inner().answers.into_inner().unwrap();
+    /// assert_eq!(answers, [2, 42]);
+    /// ```
+    fn spy<R>(&self, real: impl FnOnce() -> R, record: impl FnOnce(&R)) -> R {
+        let result = self.call_real_with(real);
+        record(&result);
+        result
     }
 }
 
@@ -156,11 +497,24 @@ impl<T: CallReal> CheckRealCall for T {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
 enum RealCallMode {
     Inactive,
     Always,
-    Once,
+    /// Delegate the specified number of calls.
+    Count(usize),
+    /// Delegate calls for which the predicate returns `true`.
+    Predicate(Arc<dyn Fn() -> bool + Send + Sync>),
+}
+
+impl fmt::Debug for RealCallMode {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inactive => formatter.write_str("Inactive"),
+            Self::Always => formatter.write_str("Always"),
+            Self::Count(count) => formatter.debug_tuple("Count").field(count).finish(),
+            Self::Predicate(_) => formatter.debug_tuple("Predicate").field(&"_").finish(),
+        }
+    }
 }
 
 impl Default for RealCallMode {
@@ -169,9 +523,18 @@ impl Default for RealCallMode {
     }
 }
 
+impl RealCallMode {
+    fn is_inactive(&self) -> bool {
+        matches!(self, Self::Inactive | Self::Count(0))
+    }
+}
+
 /// Switch between real and mocked implementations.
 ///
 /// A field of this type should be present on a struct for `#[derive(CallReal)]` to work.
+/// The switch is thread-safe, so it can be used in [`Shared`](crate::Shared) mock states
+/// accessed from multiple threads. (For [`SharedRw`](crate::SharedRw) states,
+/// mind the [pitfalls](crate::SharedRw#pitfalls) of concurrent access.)
 ///
 /// # Examples
 ///
@@ -191,25 +554,112 @@ impl Default for RealCallMode {
 /// }
 /// ```
 #[derive(Debug, Default)]
-pub struct RealCallSwitch(Cell<RealCallMode>);
+pub struct RealCallSwitch {
+    /// Stack of modes set by alive guards; the last mode is in effect.
+    modes: Mutex<Vec<RealCallMode>>,
+    /// Whether `modes` is non-empty. Allows to skip locking `modes` in the common case
+    /// when no guards are alive.
+    has_guards: AtomicBool,
+    strict: bool,
+}
 
 impl RealCallSwitch {
+    /// Creates a strict switch. Unlike the default switch, a strict switch prohibits
+    /// nesting [`RealCallGuard`]s; creating a guard while another guard is alive will panic.
+    /// See [`CallReal`] docs for more details.
+    pub fn strict() -> Self {
+        Self {
+            modes: Mutex::default(),
+            has_guards: AtomicBool::new(false),
+            strict: true,
+        }
+    }
+
     fn should_delegate(&self) -> bool {
-        let mode = self.0.get();
-        if mode == RealCallMode::Once {
-            self.0.set(RealCallMode::Inactive);
+        if !self.has_guards.load(Ordering::Acquire) {
+            return false;
+        }
+        let mut modes = self.modes.lock();
+        let mode = match modes.last_mut() {
+            Some(mode) => mode,
+            None => return false,
+        };
+        let predicate = match mode {
+            RealCallMode::Inactive => return false,
+            RealCallMode::Always => return true,
+            RealCallMode::Count(count) => {
+                let should_delegate = *count > 0;
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    *mode = RealCallMode::Inactive;
+                }
+                return should_delegate;
+            }
+            RealCallMode::Predicate(predicate) => Arc::clone(predicate),
+        };
+        drop(modes);
+        // The lock must not be held while evaluating the predicate: the predicate
+        // may call mocked functions, which would access the switch again.
+        predicate()
+    }
+
+    /// Checks whether a [`RealCallGuard`] is currently in effect for this switch, i.e., whether
+    /// some further calls may be delegated to the real implementation. This is useful
+    /// for diagnostics, e.g., when debugging recursive mock impls.
+    ///
+    /// Usually, it's more convenient to use [`CallReal::is_real_active()`] on the mock state.
+    /// Like that method, this check does not affect the switch state.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{CallReal, Mock, RealCallSwitch};
+    /// #[derive(Default, Mock, CallReal)]
+    /// struct MockState {
+    ///     switch: RealCallSwitch,
+    /// }
+    ///
+    /// let mut guard = MockState::default().set_as_mock();
+    /// guard.with(|state| {
+    ///     assert!(!state.switch.is_active());
+    ///     let real_guard = state.call_real_once();
+    ///     assert!(state.switch.is_active());
+    ///     drop(real_guard);
+    ///     assert!(!state.switch.is_active());
+    /// });
+    /// ```
+    pub fn is_active(&self) -> bool {
+        if !self.has_guards.load(Ordering::Acquire) {
+            return false;
         }
-        mode != RealCallMode::Inactive
+        self.modes
+            .lock()
+            .last()
+            .map_or(false, |mode| !mode.is_inactive())
     }
 
-    fn assert_inactive(&self) {
-        assert_eq!(
-            self.0.get(),
-            RealCallMode::Inactive,
-            "Real / mock switch is set to \"real\" when `call_real()` or `call_real_once()` \
-             is called. This may lead to unexpected switch value for the further calls \
-             and is thus prohibited"
-        );
+    /// Pushes `mode` to the stack and returns the stack depth before the push.
+    fn activate(&self, mode: RealCallMode) -> usize {
+        let mut modes = self.modes.lock();
+        if self.strict {
+            let is_active = modes.last().map_or(false, |mode| !mode.is_inactive());
+            assert!(
+                !is_active,
+                "Real / mock switch is set to \"real\" when `call_real()` or another \
+                 `call_real_*()` method is called. This may lead to unexpected switch value \
+                 for the further calls and is thus prohibited for strict switches"
+            );
+        }
+        let depth = modes.len();
+        modes.push(mode);
+        self.has_guards.store(true, Ordering::Release);
+        depth
+    }
+
+    fn deactivate(&self, depth: usize) {
+        let mut modes = self.modes.lock();
+        modes.truncate(depth);
+        self.has_guards.store(!modes.is_empty(), Ordering::Release);
     }
 }
 
@@ -221,13 +671,13 @@ impl RealCallSwitch {
 #[must_use = "If unused, the guard won't affect any calls"]
 pub struct RealCallGuard<'a, T: CallReal + ?Sized> {
     controller: &'a T,
+    depth: usize,
 }
 
 impl<T: CallReal + ?Sized> Drop for RealCallGuard<'_, T> {
     fn drop(&mut self) {
-        self.controller.access_switch(|switch| {
-            switch.0.set(RealCallMode::Inactive);
-        });
+        self.controller
+            .access_switch(|switch| switch.deactivate(self.depth));
     }
 }
 
@@ -239,6 +689,52 @@ impl<T: CallReal + ?Sized> RealCallGuard<'_, T> {
         result
     }
 
+    /// Executes the provided fallible closure under this guard, drops the guard and then
+    /// returns the closure output. Works the same as [`Self::scope()`], but makes it explicit
+    /// that the guard is dropped before an error is propagated, both if the closure itself
+    /// exits early via `?`, and if the output of this method is propagated via `?`.
+    /// Thus, mocked functions called after the error is propagated are not delegated
+    /// to the real impl.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error returned by the closure.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mimicry::{mock, CallReal, Mock, RealCallSwitch};
+    /// #[mock(using = "ParseMock")]
+    /// fn parse(s: &str) -> Result<u32, String> {
+    ///     s.parse().map_err(|err| format!("{err}"))
+    /// }
+    ///
+    /// #[derive(Default, Mock, CallReal)]
+    /// struct ParseMock {
+    ///     switch: RealCallSwitch,
+    /// }
+    ///
+    /// impl ParseMock {
+    ///     fn parse(&self, s: &str) -> Result<u32, String> {
+    ///         let (x, y) = s.split_once('+').ok_or("no `+`")?;
+    ///         let sum = self
+    ///             .call_real()
+    ///             .try_scope(|| Ok::<_, String>(parse(x)? + parse(y)?))?;
+    ///         assert!(!self.is_real_active());
+    ///         Ok(sum)
+    ///     }
+    /// }
+    ///
+    /// let _guard = ParseMock::default().set_as_mock();
+    /// assert_eq!(parse("2+3"), Ok(5));
+    /// assert!(parse("2+x").is_err());
+    /// ```
+    pub fn try_scope<R, E>(self, action: impl FnOnce() -> Result<R, E>) -> Result<R, E> {
+        let result = action();
+        drop(self);
+        result
+    }
+
     /// Executes the provided future under this guard and then drops it.
     pub async fn async_scope<Fut: Future>(self, action: Fut) -> Fut::Output {
         let result = action.await;
@@ -246,3 +742,43 @@ impl<T: CallReal + ?Sized> RealCallGuard<'_, T> {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use static_assertions::assert_impl_all;
+
+    assert_impl_all!(RealCallSwitch: Send, Sync);
+
+    impl CallReal for RealCallSwitch {
+        fn access_switch<R>(&self, action: impl FnOnce(&RealCallSwitch) -> R) -> R {
+            action(self)
+        }
+    }
+
+    #[test]
+    fn switch_tracks_alive_guards() {
+        let switch = RealCallSwitch::default();
+        assert!(!switch.has_guards.load(Ordering::Relaxed));
+        assert!(!switch.should_delegate());
+
+        let outer_guard = switch.call_real_n(1);
+        assert!(switch.has_guards.load(Ordering::Relaxed));
+        {
+            let _inner_guard = switch.call_real();
+            assert!(switch.should_delegate());
+            assert!(switch.should_delegate());
+        }
+        assert!(switch.has_guards.load(Ordering::Relaxed));
+        assert!(switch.should_delegate());
+        assert!(!switch.should_delegate());
+        // The guard is still alive, but no longer delegates calls.
+        assert!(switch.has_guards.load(Ordering::Relaxed));
+        assert!(!switch.is_active());
+
+        drop(outer_guard);
+        assert!(!switch.has_guards.load(Ordering::Relaxed));
+        assert!(!switch.should_delegate());
+    }
+}
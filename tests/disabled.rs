@@ -0,0 +1,77 @@
+//! Tests for disabling mocking with the `mimicry_disabled` cfg flag. Run with
+//! `RUSTFLAGS="--cfg mimicry_disabled" cargo test --test disabled`.
+
+#![cfg(mimicry_disabled)]
+
+use async_std::task::block_on;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use mimicry::{mock, Mock, MockRef};
+
+#[mock(using = "ValueMock")]
+fn answer() -> u32 {
+    42
+}
+
+#[mock(using = "ValueMock")]
+async fn async_answer() -> u32 {
+    42
+}
+
+#[mock(using = "ValueMock")]
+fn counter() -> impl Fn() -> u32 + Send {
+    || 42
+}
+
+#[derive(Debug)]
+struct Calculator(u32);
+
+#[mock(using = "ValueMock")]
+impl Calculator {
+    fn add(&self, x: u32) -> u32 {
+        self.0 + x
+    }
+}
+
+#[derive(Default, Mock)]
+#[mock(auto_check)]
+struct ValueMock {
+    calls: AtomicU32,
+}
+
+// Mock impls are not called if mocking is disabled.
+#[allow(dead_code)]
+impl ValueMock {
+    fn answer(&self) -> u32 {
+        self.calls.fetch_add(1, Ordering::Relaxed)
+    }
+
+    async fn async_answer(this: MockRef<Self>) -> u32 {
+        this.with(Self::answer)
+    }
+
+    fn counter(&self) -> impl Fn() -> u32 + Send {
+        || 0
+    }
+
+    fn add(&self, _: &Calculator, _: u32) -> u32 {
+        self.answer()
+    }
+}
+
+fn assert_send<T: Send>(value: T) -> T {
+    value
+}
+
+#[test]
+fn mocks_are_not_applied() {
+    let guard = ValueMock::default().set_as_mock();
+    assert_eq!(answer(), 42);
+    assert_eq!(block_on(async_answer()), 42);
+    assert_eq!(assert_send(counter())(), 42);
+    assert_eq!(Calculator(40).add(2), 42);
+
+    assert_eq!(guard.call_count(), 0);
+    assert_eq!(guard.into_inner().calls.into_inner(), 0);
+}
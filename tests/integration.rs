@@ -4,12 +4,19 @@ use std::{
     borrow::Borrow,
     collections::HashMap,
     hash::Hash,
-    mem,
-    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    mem, panic,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
     thread,
 };
 
-use mimicry::{mock, CallReal, Mock, MockRef, Mut, RealCallSwitch};
+use mimicry::{
+    mock, Answers, CallReal, ClosureMock, Mock, MockGuard, MockRef, MockVariant, Mut,
+    RealCallSwitch,
+};
 
 #[test]
 fn mock_basics() {
@@ -124,13 +131,36 @@ fn arg_destructuring_and_early_returns() {
         }
     }
 
-    let _guard = DestructureMock::default().set_as_mock();
+    let _guard = DestructureMock.set_as_mock();
     assert_eq!(
         destructure(&[-1; 4], Point { x: 3, y: 4 }).unwrap(),
         Point { x: 3, y: 4 }
     );
 }
 
+#[test]
+fn auto_check_attr_implementing_check_real_call() {
+    #[mock(using = "AutoCheckMock")]
+    fn answer() -> usize {
+        42
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct AutoCheckMock;
+
+    impl AutoCheckMock {
+        fn answer(&self) -> usize {
+            23
+        }
+    }
+
+    assert!(!mimicry::CheckRealCall::should_call_real(&AutoCheckMock));
+    let _guard = AutoCheckMock.set_as_mock();
+    assert_eq!(answer(), 23);
+}
+
 #[test]
 fn mock_consuming_args() {
     #[mock(using = "ConsumeMock::consume")]
@@ -161,6 +191,118 @@ fn mock_consuming_args() {
     assert!(consume(bytes).is_none());
 }
 
+#[test]
+fn sharing_mock_impl_among_functions() {
+    #[mock(using = "LenMock::len")]
+    fn len(s: &str) -> usize {
+        s.len()
+    }
+
+    #[mock(using = "LenMock::len")]
+    fn string_len(s: &String) -> usize {
+        s.len()
+    }
+
+    #[mock(using = "LenMock::len")]
+    fn len_after_push(s: &mut String) -> usize {
+        s.push('!');
+        s.len()
+    }
+
+    #[mock(using = "LenMock::len_owned")]
+    fn owned_len(s: String) -> usize {
+        s.len()
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(mut, shared))]
+    #[cfg_attr(not(feature = "shared"), mock(mut))]
+    struct LenMock {
+        args: Vec<String>,
+    }
+
+    impl LenMock {
+        fn len(this: &Mut<Self>, s: &str) -> usize {
+            this.borrow().args.push(s.to_owned());
+            s.chars().count()
+        }
+
+        fn len_owned(this: &Mut<Self>, s: String) -> usize {
+            Self::len(this, &s)
+        }
+    }
+
+    let guard = LenMock::default().set_as_mock();
+    assert_eq!(len("ü"), 1);
+    assert_eq!(string_len(&"äö".to_owned()), 2);
+    assert_eq!(len_after_push(&mut "ß".to_owned()), 1);
+    assert_eq!(owned_len("test".to_owned()), 4);
+    assert_eq!(guard.into_inner().args, ["ü", "äö", "ß", "test"]);
+}
+
+#[test]
+fn mock_consuming_receiver() {
+    #[derive(Debug, PartialEq)]
+    struct Widget {
+        name: String,
+        width: u32,
+    }
+
+    #[derive(Default)]
+    struct WidgetBuilder {
+        name: String,
+        width: Option<u32>,
+    }
+
+    #[mock(using = "BuilderMock")]
+    impl WidgetBuilder {
+        fn width(mut self, width: u32) -> Self {
+            self.width = Some(width);
+            self
+        }
+
+        fn build(self) -> Widget {
+            Widget {
+                name: self.name,
+                width: self.width.unwrap_or(100),
+            }
+        }
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct BuilderMock {
+        switch: RealCallSwitch,
+    }
+
+    impl BuilderMock {
+        // The receiver is passed to the mock impl by value.
+        fn width(&self, builder: WidgetBuilder, width: u32) -> WidgetBuilder {
+            self.call_real().scope(|| builder.width(width * 2))
+        }
+
+        fn build(&self, mut builder: WidgetBuilder) -> Widget {
+            builder.name = format!("mock {}", builder.name);
+            self.call_real().scope(|| builder.build())
+        }
+    }
+
+    let builder = WidgetBuilder {
+        name: "test".to_owned(),
+        width: None,
+    };
+    let widget = builder.width(10).build();
+    assert_eq!(widget.width, 10);
+    assert_eq!(widget.name, "test");
+
+    let _guard = BuilderMock::default().set_as_mock();
+    let widget = WidgetBuilder::default().width(10).build();
+    assert_eq!(widget.width, 20);
+    assert_eq!(widget.name, "mock ");
+    let widget = WidgetBuilder::default().build();
+    assert_eq!(widget.width, 100);
+}
+
 #[test]
 fn mock_for_generic_function() {
     #[mock(using = "GenericMock")]
@@ -169,10 +311,10 @@ fn mock_for_generic_function() {
     }
 
     #[mock(using = "GenericMock")]
-    fn get_key<K, Q: ?Sized>(map: &HashMap<K, usize>, key: &Q) -> usize
+    fn get_key<K, Q>(map: &HashMap<K, usize>, key: &Q) -> usize
     where
         K: Borrow<Q> + Eq + Hash,
-        Q: Eq + Hash,
+        Q: Eq + Hash + ?Sized,
     {
         map.get(key).copied().unwrap_or(0)
     }
@@ -191,10 +333,10 @@ fn mock_for_generic_function() {
             this.call_real().scope(|| len(value))
         }
 
-        fn get_key<K, Q: ?Sized>(this: &Mut<Self>, map: &HashMap<K, usize>, key: &Q) -> usize
+        fn get_key<K, Q>(this: &Mut<Self>, map: &HashMap<K, usize>, key: &Q) -> usize
         where
             K: Borrow<Q> + Eq + Hash,
-            Q: Eq + Hash,
+            Q: Eq + Hash + ?Sized,
         {
             let response = this.call_real().scope(|| get_key(map, key));
             this.borrow().get_key_responses.push(response);
@@ -218,212 +360,2070 @@ fn mock_for_generic_function() {
 }
 
 #[test]
-fn mock_in_impl() {
-    struct Wrapper<T>(T);
-
-    impl<T: AsRef<str>> Wrapper<T> {
-        #[mock(using = "MockState")]
-        fn len(&self) -> usize {
-            self.0.as_ref().len()
-        }
+fn mock_for_pinned_type_of_generic_function() {
+    #[mock(using = "PinnedMock", for = "u32")]
+    fn len<T: ToString + 'static>(value: &T) -> usize {
+        value.to_string().len()
     }
 
-    #[mock(using = "MockState")]
-    impl Wrapper<String> {
-        fn push(&mut self, value: impl AsRef<str>) -> &mut Self {
-            self.0.push_str(value.as_ref());
-            self
-        }
+    #[mock(using = "PinnedMock", for = "u32")]
+    fn parse<T: FromStr + 'static>(s: &str) -> Option<T> {
+        s.parse().ok()
+    }
 
-        #[mock(using = "MockState::mock_take")]
-        fn take(&mut self) -> String {
-            mem::take(&mut self.0)
-        }
+    #[mock(using = "PinnedMock", for = "u32")]
+    fn push<T: 'static>(values: &mut Vec<T>, value: T) {
+        values.push(value);
     }
 
-    #[derive(Mock, CallReal)]
-    #[cfg_attr(feature = "shared", mock(shared))]
-    struct MockState {
-        min_length: usize,
-        switch: RealCallSwitch,
+    #[mock(using = "PinnedMock::size", for = "u32")]
+    fn size_of<T: 'static>() -> usize {
+        mem::size_of::<T>()
     }
 
-    impl MockState {
-        fn len<T: AsRef<str>>(&self, wrapper: &Wrapper<T>) -> usize {
-            if wrapper.0.as_ref() == "test" {
-                42
-            } else {
-                self.call_real().scope(|| wrapper.len())
-            }
+    #[derive(Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct PinnedMock;
+
+    impl PinnedMock {
+        fn len(&self, value: &u32) -> usize {
+            (*value).try_into().unwrap()
         }
 
-        fn push<'a>(
-            &self,
-            wrapper: &'a mut Wrapper<String>,
-            s: impl AsRef<str>,
-        ) -> &'a mut Wrapper<String> {
-            if s.as_ref().len() < self.min_length {
-                wrapper
-            } else {
-                self.call_real().scope(|| wrapper.push(s))
-            }
+        fn parse(&self, s: &str) -> Option<u32> {
+            Some(s.len().try_into().unwrap())
         }
 
-        fn mock_take(&self, this: &mut Wrapper<String>) -> String {
-            this.0.pop().map_or_else(String::new, String::from)
+        fn push(&self, values: &mut Vec<u32>, value: u32) {
+            values.push(value * 2);
+        }
+
+        fn size(&self) -> usize {
+            0
         }
     }
 
-    let state = MockState {
-        min_length: 3,
-        switch: RealCallSwitch::default(),
-    };
-    let guard = state.set_as_mock();
-    assert_eq!(Wrapper("test!").len(), 5);
-    assert_eq!(Wrapper("test").len(), 42);
-    assert_eq!(Wrapper(String::from("test")).len(), 42);
-    assert_eq!(Wrapper("test??").len(), 6);
+    let _guard = PinnedMock.set_as_mock();
+    assert_eq!(len(&12_345_u32), 12_345);
+    assert_eq!(len(&"test".to_owned()), 4);
+    assert_eq!(len(&12_345_u64), 5);
+    assert_eq!(parse::<u32>("42"), Some(2));
+    assert_eq!(parse::<u64>("42"), Some(42));
+    assert_eq!(parse::<String>("42").unwrap(), "42");
+
+    let mut values = vec![1_u32];
+    push(&mut values, 2);
+    assert_eq!(values, [1, 4]);
+    let mut strings = vec![];
+    push(&mut strings, "test");
+    assert_eq!(strings, ["test"]);
+
+    assert_eq!(size_of::<u32>(), 0);
+    assert_eq!(size_of::<u64>(), 8);
+}
 
-    let mut wrapper = Wrapper(String::new());
-    wrapper.push("??").push("test").push("!").push("...");
-    assert_eq!(wrapper.0, "test...");
+#[test]
+fn mock_for_const_generic_function() {
+    #[mock(using = "ConstGenericMock")]
+    fn fill<const N: usize>(buf: &mut [u8; N]) {
+        buf.fill(1);
+    }
 
-    let taken = wrapper.take();
-    assert_eq!(taken, ".");
-    assert_eq!(wrapper.0, "test..");
+    #[mock(using = "ConstGenericMock")]
+    fn chunk_count<const N: usize>(len: usize) -> usize {
+        (len + N - 1) / N
+    }
 
-    drop(guard);
-    wrapper.push(":D");
-    assert_eq!(wrapper.0, "test..:D");
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(mut, shared))]
+    #[cfg_attr(not(feature = "shared"), mock(mut))]
+    struct ConstGenericMock {
+        lengths: Vec<usize>,
+    }
+
+    impl ConstGenericMock {
+        fn fill<const N: usize>(this: &Mut<Self>, buf: &mut [u8; N]) {
+            this.borrow().lengths.push(N);
+            this.call_real().scope(|| fill(buf));
+            buf[0] = 0;
+        }
+
+        // `N` cannot be inferred from the args or the return type, so it is passed explicitly.
+        fn chunk_count<const N: usize>(this: &Mut<Self>, len: usize) -> usize {
+            this.borrow().lengths.push(N);
+            len / N
+        }
+    }
+
+    let mut buf = [0_u8; 4];
+    fill(&mut buf);
+    assert_eq!(buf, [1; 4]);
+    assert_eq!(chunk_count::<4>(10), 3);
+
+    let guard = ConstGenericMock::default().set_as_mock();
+    let mut buf = [0_u8; 3];
+    fill(&mut buf);
+    assert_eq!(buf, [0, 1, 1]);
+    assert_eq!(chunk_count::<4>(10), 2);
+    assert_eq!(guard.into_inner().lengths, [3, 4]);
 }
 
 #[test]
-fn mock_in_impl_trait() {
-    #[derive(Default)]
-    struct Flip {
-        state: u8,
+fn mock_for_function_with_impl_trait_args() {
+    #[mock(using = "ImplTraitMock")]
+    fn describe(values: impl IntoIterator<Item = u32>, label: impl AsRef<str>) -> String {
+        let sum: u32 = values.into_iter().sum();
+        format!("{}: {sum}", label.as_ref())
     }
 
-    #[mock(using = "IterMock", rename = "iter_{}")]
-    impl Iterator for Flip {
-        type Item = u8;
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct ImplTraitMock;
+
+    impl ImplTraitMock {
+        // `impl Trait` args are forwarded as is, so the mock impl must be generic as well.
+        // It can narrow the args to concrete types in its body.
+        fn describe<I>(&self, values: I, label: impl AsRef<str>) -> String
+        where
+            I: IntoIterator<Item = u32>,
+        {
+            let values: Vec<_> = values.into_iter().collect();
+            Self::describe_narrowed(&values, label.as_ref())
+        }
 
-        fn next(&mut self) -> Option<Self::Item> {
-            self.state = 1 - self.state;
-            Some(self.state)
+        fn describe_narrowed(values: &[u32], label: &str) -> String {
+            format!("{label}: {values:?}")
         }
     }
 
-    struct Const(u8);
+    assert_eq!(describe([1, 2, 3], "sum"), "sum: 6");
+    let _guard = ImplTraitMock.set_as_mock();
+    assert_eq!(describe([1, 2, 3], "sum"), "sum: [1, 2, 3]");
+    assert_eq!(describe(0..2, String::from("range")), "range: [0, 1]");
+}
 
-    impl Iterator for Const {
-        type Item = u8;
+#[test]
+fn trying_to_take_mock_state() {
+    #[mock(using = "TakenMock")]
+    fn answer() -> u32 {
+        42
+    }
 
-        #[mock(using = "IterMock::iter_next")]
-        fn next(&mut self) -> Option<Self::Item> {
-            Some(self.0)
+    #[derive(Debug, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct TakenMock(u32);
+
+    impl TakenMock {
+        fn answer(&self) -> u32 {
+            self.0
         }
     }
 
+    let guard = TakenMock(5).set_as_mock();
+    assert_eq!(answer(), 5);
+    let state_ref = MockRef::<TakenMock>::new(TakenMock::instance());
+    let guard = state_ref.with(|state| {
+        assert_eq!(state.0, 5);
+        guard.try_into_inner().unwrap_err()
+    });
+    assert_eq!(answer(), 5);
+
+    let state = guard.try_into_inner().unwrap();
+    assert_eq!(state.0, 5);
+    assert_eq!(answer(), 42);
+}
+
+#[test]
+fn generic_mock_state() {
     #[derive(Default, Mock)]
-    #[cfg_attr(feature = "shared", mock(shared))]
-    struct IterMock {
-        count: AtomicU32,
+    #[cfg_attr(feature = "shared", mock(mut, shared))]
+    #[cfg_attr(not(feature = "shared"), mock(mut))]
+    struct Recorder<T: Clone + Send> {
+        payloads: Vec<T>,
     }
 
-    impl IterMock {
-        fn iter_next<I>(&self, _: &mut I) -> Option<u8> {
-            let count = self.count.fetch_add(1, Ordering::Relaxed);
-            u8::try_from(count).ok()
+    impl<T: Clone + Send + 'static> Recorder<T> {
+        fn send(this: &Mut<Self>, payload: &T) -> bool {
+            this.borrow().payloads.push(payload.clone());
+            true
         }
     }
 
-    impl mimicry::CheckRealCall for IterMock {}
+    #[mock(using = "Recorder::<String>")]
+    fn send(_payload: &String) -> bool {
+        false
+    }
 
-    let mut flip = Flip::default();
-    assert_eq!(flip.by_ref().take(5).collect::<Vec<_>>(), [1, 0, 1, 0, 1]);
+    #[mock(using = "Recorder::<u64>", rename = "send")]
+    fn send_number(_payload: &u64) -> bool {
+        false
+    }
 
-    let guard = IterMock::default().set_as_mock();
-    assert_eq!(flip.by_ref().take(5).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
-    let mut zero = Const(0);
-    assert_eq!(zero.by_ref().take(3).collect::<Vec<_>>(), [5, 6, 7]);
+    let strings_guard = Recorder::<String>::default().set_as_mock();
+    assert!(send(&"test".to_owned()));
+    assert!(!send_number(&42));
+    let numbers_guard = Recorder::<u64>::default().set_as_mock();
+    assert!(send_number(&42));
+    assert!(send(&"other".to_owned()));
 
-    let mut chained = zero.take(2).chain(flip.take(2));
-    assert_eq!(chained.by_ref().take(3).collect::<Vec<_>>(), [8, 9, 10]);
-    drop(guard);
-    assert_eq!(chained.next(), Some(0)); // "real" next value from `flip`
+    assert_eq!(strings_guard.into_inner().payloads, ["test", "other"]);
+    assert_eq!(numbers_guard.into_inner().payloads, [42]);
+    assert!(!send(&"test".to_owned()));
 }
 
 #[test]
-fn recursive_fn() {
-    #[mock(using = "FactorialMock")]
-    fn factorial(n: u64, acc: &mut u64) -> u64 {
-        if n <= 1 {
-            *acc
-        } else {
-            *acc = acc.overflowing_mul(n).0;
-            factorial(n - 1, acc)
-        }
-    }
+fn phantom_typed_mock_state() {
+    use std::{marker::PhantomData, rc::Rc};
 
-    #[derive(Default, Mock, CallReal)]
-    #[cfg_attr(feature = "shared", mock(shared))]
-    struct FactorialMock {
-        fallback_once: AtomicBool,
-        switch: RealCallSwitch,
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(mut, shared))]
+    #[cfg_attr(not(feature = "shared"), mock(mut))]
+    struct TypedMock<T> {
+        // `fn() -> T` is `Send + Sync` regardless of `T`
+        _ty: PhantomData<fn() -> T>,
+        calls: Vec<String>,
     }
 
-    impl FactorialMock {
-        fn factorial(&self, n: u64, acc: &mut u64) -> u64 {
-            if n < 5 {
-                *acc // finish the recursion early
-            } else if self.fallback_once.load(Ordering::Relaxed) {
-                self.call_real_once().scope(|| factorial(n, acc))
-            } else {
-                // Fallback should be applied to both calls here
-                let _guard = self.call_real();
-                factorial(n, acc) * factorial(n - 5, &mut 1)
-            }
+    impl<T> TypedMock<T> {
+        fn tag(this: &Mut<Self>, tag: &str) -> usize {
+            this.borrow().calls.push(tag.to_owned());
+            0
         }
     }
 
-    assert_eq!(factorial(4, &mut 1), 24);
-
-    let mut guard = FactorialMock::default().set_as_mock();
-    assert_eq!(factorial(4, &mut 1), 1);
-    assert_eq!(factorial(5, &mut 1), 120);
-    assert_eq!(factorial(10, &mut 1), 435_456_000);
-    assert_eq!(factorial(4, &mut 1), 1);
-
-    guard.with(|mock| {
-        mock.fallback_once = AtomicBool::new(true);
-    });
-    assert_eq!(factorial(4, &mut 1), 1);
-    assert_eq!(factorial(5, &mut 1), 5);
-    assert_eq!(factorial(10, &mut 1), 151200);
+    #[mock(using = "TypedMock::<Rc<u8>>")]
+    fn tag(tag: &str) -> usize {
+        tag.len()
+    }
 
-    drop(guard);
-    assert_eq!(factorial(4, &mut 1), 24);
+    let guard = TypedMock::<Rc<u8>>::default().set_as_mock();
+    assert_eq!(tag("test"), 0);
+    assert_eq!(tag("other"), 0);
+    assert_eq!(guard.into_inner().calls, ["test", "other"]);
+    assert_eq!(tag("test"), 4);
 }
 
-#[derive(Default, Mock)]
-#[cfg_attr(feature = "shared", mock(shared))]
-struct ValueMock(AtomicU32);
+#[cfg(not(feature = "shared"))]
+#[test]
+fn stacking_thread_local_mocks() {
+    #[derive(Mock)]
+    #[mock(auto_check)]
+    struct StackedMock(u32);
+
+    impl StackedMock {
+        fn answer(&self) -> u32 {
+            self.0
+        }
+    }
 
-impl ValueMock {
-    fn value(&self) -> u32 {
-        self.0.fetch_add(1, Ordering::SeqCst)
+    #[mock(using = "StackedMock")]
+    fn answer() -> u32 {
+        42
     }
-}
 
-impl mimicry::CheckRealCall for ValueMock {}
+    let mut outer_guard = StackedMock(1).set_as_mock();
+    assert_eq!(answer(), 1);
+    {
+        let _inner_guard = StackedMock(2).set_as_mock();
+        assert_eq!(answer(), 2);
+    }
+    assert_eq!(answer(), 1);
+    assert_eq!(outer_guard.call_count(), 2);
+
+    let inner_guard = StackedMock(3).set_as_mock();
+    assert_eq!(answer(), 3);
+    // The outer guard accesses its own state.
+    outer_guard.with(|state| state.0 = 5);
+    assert_eq!(answer(), 3);
+    assert_eq!(inner_guard.call_count(), 2);
+    assert_eq!(outer_guard.call_count(), 2);
+    assert_eq!(inner_guard.into_inner().0, 3);
+    assert_eq!(answer(), 5);
+    assert_eq!(outer_guard.call_count(), 3);
+    assert_eq!(outer_guard.into_inner().0, 5);
+    assert_eq!(answer(), 42);
+}
 
-#[mock(using = "ValueMock")]
-fn value() -> u32 {
-    0
+#[cfg(not(feature = "shared"))]
+#[test]
+#[should_panic(expected = "Guards must be dropped in the reverse order of their creation")]
+fn dropping_stacked_thread_local_mocks_out_of_order() {
+    #[derive(Mock)]
+    #[mock(auto_check)]
+    struct StackedMock;
+
+    let outer_guard = StackedMock.set_as_mock();
+    let inner_guard = StackedMock.set_as_mock();
+    drop(outer_guard);
+    drop(inner_guard);
 }
 
-#[cfg(feature = "shared")]
+#[cfg(not(feature = "shared"))]
+#[test]
+fn taking_stacked_thread_local_mocks_out_of_order() {
+    #[derive(Debug, Mock)]
+    #[mock(auto_check)]
+    struct StackedMock(u32);
+
+    let outer_guard = StackedMock(1).set_as_mock();
+    let inner_guard = StackedMock(2).set_as_mock();
+    let outer_guard = outer_guard.try_into_inner().unwrap_err();
+    assert_eq!(inner_guard.into_inner().0, 2);
+    assert_eq!(outer_guard.try_into_inner().unwrap().0, 1);
+    assert!(!StackedMock::is_mock_set());
+}
+
+#[test]
+fn scoped_mock_states() {
+    #[derive(Debug, PartialEq)]
+    struct Counter(u32);
+
+    #[mock(using = "SpyMock", scoped)]
+    impl Counter {
+        fn add(&mut self, value: u32) -> u32 {
+            self.0 += value;
+            self.0
+        }
+    }
+
+    #[mock(using = "SpyMock", scoped)]
+    fn tail(bytes: &mut [u8]) -> Option<&u8> {
+        bytes.first()
+    }
+
+    #[derive(Mock, CallReal)]
+    struct SpyMock<'a> {
+        calls: &'a Mutex<Vec<u32>>,
+        fallback: &'a [u8],
+        switch: RealCallSwitch,
+    }
+
+    impl SpyMock<'_> {
+        fn add(&self, counter: &mut Counter, value: u32) -> u32 {
+            self.calls.lock().unwrap().push(value);
+            self.call_real().scope(|| counter.add(value))
+        }
+
+        fn tail<'b>(&self, bytes: &'b mut [u8]) -> Option<&'b u8> {
+            // The mock cannot return `self.fallback`: the state lifetime may be shorter
+            // than the output lifetime.
+            let len = self.fallback.len().min(bytes.len());
+            bytes[..len].copy_from_slice(&self.fallback[..len]);
+            bytes.last()
+        }
+    }
+
+    let calls = Mutex::default();
+    let fallback = vec![1, 2];
+    let mut counter = Counter(0);
+    let state = SpyMock {
+        calls: &calls,
+        fallback: &fallback,
+        switch: RealCallSwitch::default(),
+    };
+    let ((), state) = mimicry::with_scoped_mock(state, || {
+        assert_eq!(counter.add(1), 1);
+        assert_eq!(counter.add(2), 3);
+
+        let mut bytes = *b"test";
+        assert_eq!(tail(&mut bytes), Some(&b't'));
+        assert_eq!(bytes, *b"\x01\x02st");
+    });
+    assert_eq!(*state.calls.lock().unwrap(), [1, 2]);
+    assert_eq!(counter, Counter(3));
+
+    // The mock is unset after the scope.
+    assert_eq!(counter.add(3), 6);
+    assert_eq!(tail(&mut [5, 6]), Some(&5));
+    assert_eq!(calls.into_inner().unwrap(), [1, 2]);
+}
+
+#[test]
+fn resetting_mock_state() {
+    #[mock(using = "CounterMock")]
+    fn count(_value: &str) -> usize {
+        0
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(mut, shared, reset))]
+    #[cfg_attr(not(feature = "shared"), mock(mut, reset))]
+    struct CounterMock {
+        count: usize,
+        values: Vec<String>,
+    }
+
+    impl CounterMock {
+        fn count(this: &Mut<Self>, value: &str) -> usize {
+            let mut this = this.borrow();
+            this.count += 1;
+            this.values.push(value.to_owned());
+            this.count
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[mock(auto_check, reset)]
+    struct TupleMock(AtomicU32, Vec<u8>);
+
+    let mut guard = CounterMock::default().set_as_mock();
+    assert_eq!(count("test"), 1);
+    assert_eq!(count("other"), 2);
+    guard.with(|state| {
+        assert_eq!(state.values, ["test", "other"]);
+        state.reset();
+    });
+    assert_eq!(count("test"), 1);
+    let state = guard.into_inner();
+    assert_eq!(state.count, 1);
+    assert_eq!(state.values, ["test"]);
+
+    let mut state = TupleMock(5.into(), vec![1, 2, 3]);
+    state.reset();
+    assert_eq!(state.0.into_inner(), 0);
+    assert!(state.1.is_empty());
+}
+
+#[test]
+fn enum_mock_state() {
+    #[derive(Debug, PartialEq)]
+    enum Phase {
+        Idle,
+        Recording(Vec<String>),
+        Replaying(usize),
+    }
+
+    #[derive(Debug, Mock)]
+    #[cfg_attr(feature = "shared", mock(mut, shared))]
+    #[cfg_attr(not(feature = "shared"), mock(mut))]
+    enum PhaseMock {
+        Active(Phase),
+        Finished,
+    }
+
+    impl PhaseMock {
+        fn load(this: &Mut<Self>, key: &str) -> Option<usize> {
+            let mut this = this.borrow();
+            match &mut *this {
+                Self::Active(Phase::Idle) => {
+                    *this = Self::Active(Phase::Recording(vec![key.to_owned()]));
+                    None
+                }
+                Self::Active(Phase::Recording(keys)) => {
+                    keys.push(key.to_owned());
+                    if keys.len() == 3 {
+                        *this = Self::Active(Phase::Replaying(0));
+                    }
+                    None
+                }
+                Self::Active(Phase::Replaying(count)) => {
+                    *count += 1;
+                    let count = *count;
+                    if count == 2 {
+                        *this = Self::Finished;
+                    }
+                    Some(count)
+                }
+                Self::Finished => Some(usize::MAX),
+            }
+        }
+    }
+
+    #[mock(using = "PhaseMock")]
+    fn load(_key: &str) -> Option<usize> {
+        Some(0)
+    }
+
+    let mut guard = PhaseMock::Active(Phase::Idle).set_as_mock();
+    assert_eq!(load("a"), None);
+    assert_eq!(load("b"), None);
+    guard.with(|state| {
+        let expected = Phase::Recording(vec!["a".to_owned(), "b".to_owned()]);
+        assert!(matches!(state, PhaseMock::Active(phase) if *phase == expected));
+    });
+    assert_eq!(load("c"), None);
+    assert_eq!(load("a"), Some(1));
+    assert_eq!(load("a"), Some(2));
+    assert_eq!(load("a"), Some(usize::MAX));
+    assert!(matches!(guard.into_inner(), PhaseMock::Finished));
+}
+
+#[test]
+fn named_mock_instances() {
+    struct Storage(Vec<u8>);
+
+    #[mock(using = "LenMock", instance = "storage")]
+    impl Storage {
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    #[mock(using = "LenMock")]
+    fn len(s: &str) -> usize {
+        s.len()
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct LenMock(usize);
+
+    impl LenMock {
+        fn len<T: ?Sized>(&self, _: &T) -> usize {
+            self.0
+        }
+    }
+
+    let storage = Storage(vec![1, 2, 3]);
+    let storage_guard = LenMock(42).set_as_named_mock("storage");
+    assert_eq!(storage.len(), 42);
+    assert_eq!(len("test"), 4);
+
+    let guard = LenMock(23).set_as_mock();
+    assert_eq!(storage.len(), 42);
+    assert_eq!(len("test"), 23);
+
+    drop(storage_guard);
+    assert_eq!(storage.len(), 3);
+    assert_eq!(len("test"), 23);
+    drop(guard);
+    assert_eq!(len("test"), 4);
+}
+
+mod test_utils {
+    pub use mimicry;
+}
+
+#[test]
+fn custom_crate_path() {
+    #[mock(using = "PathMock", crate = "test_utils::mimicry")]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared, crate = "test_utils::mimicry"))]
+    #[cfg_attr(not(feature = "shared"), mock(crate = "test_utils::mimicry"))]
+    struct PathMock {
+        switch: test_utils::mimicry::RealCallSwitch,
+    }
+
+    impl PathMock {
+        fn answer(&self) -> u32 {
+            self.call_real().scope(answer) + 1
+        }
+    }
+
+    let _guard = PathMock::default().set_as_mock();
+    assert_eq!(answer(), 43);
+}
+
+#[test]
+fn mock_for_diverging_function() {
+    #[mock(using = "AbortMock")]
+    fn abort(code: i32) -> ! {
+        std::process::exit(code)
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct AbortMock {
+        code: AtomicU32,
+    }
+
+    impl AbortMock {
+        fn abort(&self, code: i32) -> ! {
+            self.code.store(code as u32, Ordering::SeqCst);
+            panic!("aborted with code {code}");
+        }
+    }
+
+    let guard = AbortMock::default().set_as_mock();
+    let err = panic::catch_unwind(|| abort(3)).unwrap_err();
+    let err = err.downcast_ref::<String>().unwrap();
+    assert_eq!(err, "aborted with code 3");
+    assert_eq!(guard.into_inner().code.into_inner(), 3);
+}
+
+#[test]
+fn mock_for_extern_functions() {
+    #[mock(using = "CallbackMock")]
+    extern "C" fn callback(x: i32) -> i32 {
+        x + 1
+    }
+
+    #[mock(using = "CallbackMock")]
+    extern "Rust" fn rust_callback(x: i32) -> i32 {
+        x * 2
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct CallbackMock {
+        calls: Mutex<Vec<i32>>,
+        switch: RealCallSwitch,
+    }
+
+    impl CallbackMock {
+        fn callback(&self, x: i32) -> i32 {
+            self.calls.lock().unwrap().push(x);
+            self.call_real_with(|| callback(x))
+        }
+
+        fn rust_callback(&self, x: i32) -> i32 {
+            -x
+        }
+    }
+
+    fn apply(callback: extern "C" fn(i32) -> i32, x: i32) -> i32 {
+        callback(x)
+    }
+
+    assert_eq!(apply(callback, 3), 4);
+    let guard = CallbackMock::default().set_as_mock();
+    assert_eq!(apply(callback, 3), 4);
+    assert_eq!(apply(callback, -5), -4);
+    assert_eq!(rust_callback(3), -3);
+
+    let calls = guard.into_inner().calls.into_inner().unwrap();
+    assert_eq!(calls, [3, -5]);
+}
+
+#[test]
+fn mock_in_impl() {
+    struct Wrapper<T>(T);
+
+    impl<T: AsRef<str>> Wrapper<T> {
+        #[mock(using = "MockState")]
+        fn len(&self) -> usize {
+            self.0.as_ref().len()
+        }
+    }
+
+    #[mock(using = "MockState")]
+    impl Wrapper<String> {
+        fn push(&mut self, value: impl AsRef<str>) -> &mut Self {
+            self.0.push_str(value.as_ref());
+            self
+        }
+
+        #[mock(using = "MockState::mock_take")]
+        fn take(&mut self) -> String {
+            mem::take(&mut self.0)
+        }
+    }
+
+    #[derive(Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct MockState {
+        min_length: usize,
+        switch: RealCallSwitch,
+    }
+
+    impl MockState {
+        fn len<T: AsRef<str>>(&self, wrapper: &Wrapper<T>) -> usize {
+            if wrapper.0.as_ref() == "test" {
+                42
+            } else {
+                self.call_real().scope(|| wrapper.len())
+            }
+        }
+
+        fn push<'a>(
+            &self,
+            wrapper: &'a mut Wrapper<String>,
+            s: impl AsRef<str>,
+        ) -> &'a mut Wrapper<String> {
+            if s.as_ref().len() < self.min_length {
+                wrapper
+            } else {
+                self.call_real().scope(|| wrapper.push(s))
+            }
+        }
+
+        fn mock_take(&self, this: &mut Wrapper<String>) -> String {
+            this.0.pop().map_or_else(String::new, String::from)
+        }
+    }
+
+    let state = MockState {
+        min_length: 3,
+        switch: RealCallSwitch::default(),
+    };
+    let guard = state.set_as_mock();
+    assert_eq!(Wrapper("test!").len(), 5);
+    assert_eq!(Wrapper("test").len(), 42);
+    assert_eq!(Wrapper(String::from("test")).len(), 42);
+    assert_eq!(Wrapper("test??").len(), 6);
+
+    let mut wrapper = Wrapper(String::new());
+    wrapper.push("??").push("test").push("!").push("...");
+    assert_eq!(wrapper.0, "test...");
+
+    let taken = wrapper.take();
+    assert_eq!(taken, ".");
+    assert_eq!(wrapper.0, "test..");
+
+    drop(guard);
+    wrapper.push(":D");
+    assert_eq!(wrapper.0, "test..:D");
+}
+
+#[test]
+fn mock_in_impl_for_reference_and_box() {
+    use std::fmt;
+
+    struct Widget(&'static str);
+
+    trait Describe {
+        fn describe(&self) -> String;
+    }
+
+    #[mock(using = "DisplayMock")]
+    impl fmt::Display for &Widget {
+        fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+            formatter.write_str(self.0)
+        }
+    }
+
+    #[mock(using = "DisplayMock")]
+    impl Describe for Box<Widget> {
+        fn describe(&self) -> String {
+            format!("boxed {}", self.0)
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct DisplayMock;
+
+    impl DisplayMock {
+        fn fmt(&self, this: &&Widget, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(formatter, "<{}>", this.0)
+        }
+
+        fn describe(&self, this: &Widget) -> String {
+            format!("mocked {}", this.0)
+        }
+    }
+
+    let widget = Widget("test");
+    let boxed = Box::new(Widget("box"));
+    assert_eq!((&widget).to_string(), "test");
+    assert_eq!(boxed.describe(), "boxed box");
+
+    let _guard = DisplayMock.set_as_mock();
+    assert_eq!((&widget).to_string(), "<test>");
+    assert_eq!(boxed.describe(), "mocked box");
+}
+
+#[test]
+fn mock_in_impl_with_multiple_type_params() {
+    struct Cache<K, V> {
+        entries: HashMap<K, V>,
+    }
+
+    #[mock(using = "CacheMock")]
+    impl<K: Eq + Hash, V> Cache<K, V> {
+        fn get(&self, key: &K) -> Option<&V> {
+            self.entries.get(key)
+        }
+
+        fn insert(&mut self, key: K, value: V) -> Option<V> {
+            self.entries.insert(key, value)
+        }
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct CacheMock {
+        inserts: AtomicU32,
+        switch: RealCallSwitch,
+    }
+
+    impl CacheMock {
+        fn get<'a, K: Eq + Hash, V>(&self, cache: &'a Cache<K, V>, key: &K) -> Option<&'a V> {
+            if cache.entries.len() > 1 {
+                None
+            } else {
+                self.call_real().scope(|| cache.get(key))
+            }
+        }
+
+        fn insert<K: Eq + Hash, V>(&self, cache: &mut Cache<K, V>, key: K, value: V) -> Option<V> {
+            self.inserts.fetch_add(1, Ordering::Relaxed);
+            self.call_real().scope(|| cache.insert(key, value))
+        }
+    }
+
+    let mut cache = Cache {
+        entries: HashMap::new(),
+    };
+    let guard = CacheMock::default().set_as_mock();
+    assert_eq!(cache.insert("test", 42), None);
+    assert_eq!(cache.get(&"test"), Some(&42));
+    assert_eq!(cache.insert("other", 23), None);
+    assert_eq!(cache.get(&"test"), None);
+
+    let mut other_cache = Cache {
+        entries: HashMap::new(),
+    };
+    assert_eq!(other_cache.insert(1_u8, "value"), None);
+    assert_eq!(other_cache.get(&1), Some(&"value"));
+
+    let state = guard.into_inner();
+    assert_eq!(state.inserts.into_inner(), 3);
+    assert_eq!(cache.get(&"test"), Some(&42));
+}
+
+#[test]
+fn mock_for_method_with_distinct_output_lifetimes() {
+    struct Text(String);
+
+    #[mock(using = "SplitMock")]
+    impl Text {
+        fn split<'a, 'b>(&'a self, other: &'b str) -> (&'a str, &'b str) {
+            (&self.0[..1], &other[1..])
+        }
+
+        fn pick<'b>(&self, other: &'b str) -> (&str, &'b str) {
+            (&self.0, other)
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct SplitMock;
+
+    impl SplitMock {
+        fn split<'a, 'b>(&self, this: &'a Text, other: &'b str) -> (&'a str, &'b str) {
+            (&this.0, other)
+        }
+
+        fn pick<'s, 'b>(&self, this: &'s Text, other: &'b str) -> (&'s str, &'b str) {
+            (&this.0[..0], other)
+        }
+    }
+
+    let text = Text("hello".to_owned());
+    let other = String::from("world");
+    assert_eq!(text.split(&other), ("h", "orld"));
+
+    let _guard = SplitMock.set_as_mock();
+    assert_eq!(text.split(&other), ("hello", "world"));
+    assert_eq!(text.pick(&other), ("", "world"));
+    // The second output only borrows from `other`, so it can outlive `text`.
+    let tail = text.split(&other).1;
+    drop(text);
+    assert_eq!(tail, "world");
+}
+
+#[test]
+fn mock_for_methods_with_named_receiver_lifetimes() {
+    struct Store(Vec<String>);
+
+    #[mock(using = "StoreMock")]
+    impl Store {
+        fn get<'a>(&'a self, prefix: &str) -> &'a str {
+            self.0
+                .iter()
+                .find(|item| item.starts_with(prefix))
+                .map_or("", String::as_str)
+        }
+
+        #[allow(clippy::needless_arbitrary_self_type)]
+        fn first(self: &Self) -> &str {
+            &self.0[0]
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct StoreMock;
+
+    impl StoreMock {
+        fn get<'a>(&self, this: &'a Store, _prefix: &str) -> &'a str {
+            this.0.last().map_or("", String::as_str)
+        }
+
+        fn first<'a>(&self, this: &'a Store) -> &'a str {
+            &this.0[1]
+        }
+    }
+
+    let store = Store(vec!["foo".to_owned(), "bar".to_owned()]);
+    assert_eq!(store.get("b"), "bar");
+    assert_eq!(store.first(), "foo");
+
+    let _guard = StoreMock.set_as_mock();
+    let item = {
+        // The output only borrows from `self`, so it can outlive `prefix`.
+        let prefix = String::from("f");
+        store.get(&prefix)
+    };
+    assert_eq!(item, "bar");
+    assert_eq!(store.first(), "bar");
+}
+
+#[test]
+fn mock_for_method_returning_boxed_trait_object() {
+    struct Bytes(Vec<u8>);
+
+    #[mock(using = "BytesMock")]
+    fn split(bytes: &[u8]) -> Box<dyn Iterator<Item = &[u8]> + '_> {
+        Box::new(bytes.chunks(2))
+    }
+
+    #[mock(using = "BytesMock")]
+    impl Bytes {
+        fn parser(&self) -> Box<dyn Iterator<Item = u8> + '_> {
+            Box::new(self.0.iter().copied())
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct BytesMock;
+
+    impl BytesMock {
+        fn parser<'a>(&self, this: &'a Bytes) -> Box<dyn Iterator<Item = u8> + 'a> {
+            Box::new(this.0.iter().rev().copied())
+        }
+
+        fn split<'a>(&self, bytes: &'a [u8]) -> Box<dyn Iterator<Item = &'a [u8]> + 'a> {
+            Box::new(bytes.chunks(1))
+        }
+    }
+
+    let bytes = Bytes(vec![1, 2, 3]);
+    assert_eq!(bytes.parser().collect::<Vec<_>>(), [1, 2, 3]);
+    assert_eq!(split(&bytes.0).count(), 2);
+
+    let _guard = BytesMock.set_as_mock();
+    assert_eq!(bytes.parser().collect::<Vec<_>>(), [3, 2, 1]);
+    assert_eq!(split(&bytes.0).count(), 3);
+}
+
+#[test]
+fn mock_for_mut_method_returning_ref_into_other_arg() {
+    #[derive(Default)]
+    struct Buf(Vec<u8>);
+
+    #[mock(using = "MergeMock")]
+    impl Buf {
+        fn merge<'a>(&mut self, other: &'a mut Buf) -> &'a [u8] {
+            self.0.append(&mut other.0);
+            &other.0
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct MergeMock;
+
+    impl MergeMock {
+        fn merge<'a>(&self, this: &mut Buf, other: &'a mut Buf) -> &'a [u8] {
+            this.0.extend_from_slice(&other.0);
+            &other.0
+        }
+    }
+
+    let mut buf = Buf(vec![1]);
+    let mut other = Buf(vec![2, 3]);
+    assert!(buf.merge(&mut other).is_empty());
+    assert_eq!(buf.0, [1, 2, 3]);
+
+    let _guard = MergeMock.set_as_mock();
+    let mut other = Buf(vec![4]);
+    let merged = buf.merge(&mut other);
+    // `merged` only borrows from `other`, so `buf` can be accessed.
+    assert_eq!(buf.0, [1, 2, 3, 4]);
+    assert_eq!(merged, [4]);
+}
+
+#[test]
+fn mock_for_constructors() {
+    #[derive(Debug)]
+    struct Buffer {
+        data: Vec<u8>,
+    }
+
+    impl Buffer {
+        #[mock(using = "ConstructorMock::new_buffer")]
+        fn new(capacity: usize) -> Self {
+            Self {
+                data: Vec::with_capacity(capacity),
+            }
+        }
+    }
+
+    struct Filled<T>(Vec<T>);
+
+    impl<T: Clone> Filled<T> {
+        #[mock(using = "ConstructorMock::new_filled")]
+        fn new(value: T, len: usize) -> Self {
+            Self(vec![value; len])
+        }
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct ConstructorMock {
+        switch: RealCallSwitch,
+    }
+
+    impl ConstructorMock {
+        fn new_buffer(&self, capacity: usize) -> Buffer {
+            let mut buffer = self.call_real().scope(|| Buffer::new(capacity));
+            buffer.data.resize(capacity, 0xff);
+            buffer
+        }
+
+        fn new_filled<T: Clone>(&self, value: T, _len: usize) -> Filled<T> {
+            Filled(vec![value])
+        }
+    }
+
+    assert!(Buffer::new(4).data.is_empty());
+    let _guard = ConstructorMock::default().set_as_mock();
+    assert_eq!(Buffer::new(4).data, [0xff; 4]);
+    assert_eq!(Filled::new("test", 3).0, ["test"]);
+}
+
+#[test]
+fn mock_in_impl_with_cfg_gated_methods() {
+    struct Counter(u8);
+
+    #[mock(using = "CounterMock")]
+    impl Counter {
+        fn get(&self) -> u8 {
+            self.0
+        }
+
+        #[cfg(any())] // never compiled
+        fn removed(&self) -> u8 {
+            self.0 + 1
+        }
+
+        #[cfg(test)]
+        fn doubled(&self) -> u8 {
+            self.0 * 2
+        }
+
+        #[cfg_attr(test, mock(using = "CounterMock::mock_tripled"))]
+        fn tripled(&self) -> u8 {
+            self.0 * 3
+        }
+
+        #[cfg_attr(not(test), mock(using = "CounterMock::mock_tripled"))]
+        fn squared(&self) -> u8 {
+            self.0 * self.0
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct CounterMock;
+
+    impl CounterMock {
+        fn get(&self, _: &Counter) -> u8 {
+            10
+        }
+
+        fn doubled(&self, _: &Counter) -> u8 {
+            20
+        }
+
+        fn mock_tripled(&self, _: &Counter) -> u8 {
+            30
+        }
+
+        fn squared(&self, _: &Counter) -> u8 {
+            40
+        }
+    }
+
+    let counter = Counter(3);
+    let guard = CounterMock.set_as_mock();
+    assert_eq!(counter.get(), 10);
+    assert_eq!(counter.doubled(), 20);
+    assert_eq!(counter.tripled(), 30);
+    assert_eq!(counter.squared(), 40);
+    assert_eq!(guard.call_count(), 4);
+}
+
+#[test]
+fn mock_in_impl_trait() {
+    #[derive(Default)]
+    struct Flip {
+        state: u8,
+    }
+
+    #[mock(using = "IterMock", rename = "iter_{}")]
+    impl Iterator for Flip {
+        type Item = u8;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.state = 1 - self.state;
+            Some(self.state)
+        }
+    }
+
+    struct Const(u8);
+
+    impl Iterator for Const {
+        type Item = u8;
+
+        #[mock(using = "IterMock::iter_next")]
+        fn next(&mut self) -> Option<Self::Item> {
+            Some(self.0)
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct IterMock {
+        count: AtomicU32,
+    }
+
+    impl IterMock {
+        fn iter_next<I>(&self, _: &mut I) -> Option<u8> {
+            let count = self.count.fetch_add(1, Ordering::Relaxed);
+            u8::try_from(count).ok()
+        }
+    }
+
+    impl mimicry::CheckRealCall for IterMock {}
+
+    let mut flip = Flip::default();
+    assert_eq!(flip.by_ref().take(5).collect::<Vec<_>>(), [1, 0, 1, 0, 1]);
+
+    let guard = IterMock::default().set_as_mock();
+    assert_eq!(flip.by_ref().take(5).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+    let mut zero = Const(0);
+    assert_eq!(zero.by_ref().take(3).collect::<Vec<_>>(), [5, 6, 7]);
+
+    let mut chained = zero.take(2).chain(flip.take(2));
+    assert_eq!(chained.by_ref().take(3).collect::<Vec<_>>(), [8, 9, 10]);
+    drop(guard);
+    assert_eq!(chained.next(), Some(0)); // "real" next value from `flip`
+}
+
+#[test]
+fn mock_for_function_with_closure_args() {
+    #[mock(using = "RetryMock")]
+    fn retry<T, E>(mut op: impl FnMut() -> Result<T, E>, times: usize) -> Result<T, E> {
+        let mut result = op();
+        for _ in 1..times {
+            if result.is_ok() {
+                break;
+            }
+            result = op();
+        }
+        result
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct RetryMock {
+        attempts: AtomicU32,
+    }
+
+    impl RetryMock {
+        fn retry<T, E>(&self, mut op: impl FnMut() -> Result<T, E>, _times: usize) -> Result<T, E> {
+            // Always make exactly 2 attempts.
+            self.attempts.fetch_add(2, Ordering::Relaxed);
+            op().or_else(|_| op())
+        }
+    }
+
+    let mut calls = 0;
+    let mut failing_op = || {
+        calls += 1;
+        if calls < 3 {
+            Err(calls)
+        } else {
+            Ok(())
+        }
+    };
+    assert_eq!(retry(&mut failing_op, 5), Ok(()));
+
+    let guard = RetryMock::default().set_as_mock();
+    let mut calls = 0;
+    let op = || {
+        calls += 1;
+        if calls < 3 {
+            Err(calls)
+        } else {
+            Ok(())
+        }
+    };
+    assert_eq!(retry(op, 5), Err(2));
+    assert_eq!(calls, 2);
+    assert_eq!(guard.into_inner().attempts.into_inner(), 2);
+}
+
+#[test]
+fn mock_for_functions_with_associated_type_bounds() {
+    use std::{fmt, ops, str::FromStr};
+
+    #[mock(using = "ParseMock")]
+    fn parse_all<T>(items: &[&str]) -> Vec<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Debug,
+    {
+        items.iter().map(|item| item.parse().unwrap()).collect()
+    }
+
+    // `T` cannot be inferred from args or the return type, so it's passed to the mock impl
+    // explicitly.
+    #[mock(using = "ParseMock")]
+    fn parse_error<T>(item: &str) -> Option<String>
+    where
+        T: FromStr,
+        T::Err: fmt::Debug,
+    {
+        item.parse::<T>().err().map(|err| format!("{err:?}"))
+    }
+
+    // `T` is only mentioned in a projection, so it cannot be inferred from the return type.
+    #[mock(using = "ParseMock")]
+    fn parse_sum<T>(items: &[&str]) -> T::Output
+    where
+        T: FromStr + ops::Add,
+        T::Err: fmt::Debug,
+    {
+        let mut items = items.iter().map(|item| item.parse::<T>().unwrap());
+        items.next().unwrap() + items.next().unwrap()
+    }
+
+    // Explicit generic args are passed together with `impl Trait` args.
+    #[mock(using = "ParseMock")]
+    fn parse_len<T>(item: impl AsRef<str>) -> usize
+    where
+        T: FromStr,
+        T::Err: fmt::Debug,
+    {
+        item.as_ref()
+            .parse::<T>()
+            .map_or(0, |_| item.as_ref().len())
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct ParseMock;
+
+    impl ParseMock {
+        fn parse_all<T>(&self, items: &[&str]) -> Vec<T>
+        where
+            T: FromStr,
+            T::Err: fmt::Debug,
+        {
+            items.iter().filter_map(|item| item.parse().ok()).collect()
+        }
+
+        fn parse_error<T>(&self, item: &str) -> Option<String>
+        where
+            T: FromStr,
+            T::Err: fmt::Debug,
+        {
+            item.parse::<T>().err().map(|_| "mock error".to_owned())
+        }
+
+        fn parse_sum<T>(&self, items: &[&str]) -> T::Output
+        where
+            T: FromStr + ops::Add,
+            T::Err: fmt::Debug,
+        {
+            let first = items.first().unwrap().parse::<T>().unwrap();
+            first + items.last().unwrap().parse::<T>().unwrap()
+        }
+
+        fn parse_len<T>(&self, item: impl AsRef<str>) -> usize
+        where
+            T: FromStr,
+            T::Err: fmt::Debug,
+        {
+            usize::from(item.as_ref().parse::<T>().is_ok())
+        }
+    }
+
+    assert_eq!(parse_all::<u8>(&["1", "2"]), [1, 2]);
+    assert_eq!(parse_sum::<u8>(&["1", "2", "3"]), 3);
+    assert_eq!(parse_len::<u8>("42"), 2);
+    let _guard = ParseMock.set_as_mock();
+    assert_eq!(parse_all::<u8>(&["1", "??", "2"]), [1, 2]);
+    assert_eq!(parse_error::<u8>("1"), None);
+    assert_eq!(parse_error::<u8>("??").unwrap(), "mock error");
+    assert_eq!(parse_sum::<u8>(&["1", "2", "3"]), 4);
+    assert_eq!(parse_len::<u8>("42"), 1);
+}
+
+#[test]
+fn mock_for_trait_object() {
+    trait Handler {
+        fn handle(&self, value: u32) -> u32;
+        fn name(&self) -> String;
+    }
+
+    struct Doubler;
+
+    #[mock(using = "HandlerMock", methods(handle))]
+    impl Handler for Doubler {
+        fn handle(&self, value: u32) -> u32 {
+            value * 2
+        }
+
+        // Not mocked, so `HandlerMock` doesn't need an impl for it.
+        fn name(&self) -> String {
+            "doubler".to_owned()
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct HandlerMock;
+
+    impl HandlerMock {
+        fn handle(&self, _: &Doubler, value: u32) -> u32 {
+            value + 1
+        }
+    }
+
+    fn process(handler: &dyn Handler, value: u32) -> String {
+        format!("{}: {}", handler.name(), handler.handle(value))
+    }
+
+    assert_eq!(process(&Doubler, 5), "doubler: 10");
+    let _guard = HandlerMock.set_as_mock();
+    assert_eq!(process(&Doubler, 5), "doubler: 6");
+    let boxed: Box<dyn Handler> = Box::new(Doubler);
+    assert_eq!(boxed.handle(3), 4);
+}
+
+#[test]
+fn mock_for_method_with_self_sized_bound() {
+    trait Shape {
+        fn area(&self) -> f64;
+
+        fn boxed(self) -> Box<dyn Shape>
+        where
+            Self: Sized + 'static;
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Square(f64);
+
+    #[mock(using = "ShapeMock")]
+    impl Shape for Square {
+        fn area(&self) -> f64 {
+            self.0 * self.0
+        }
+
+        fn boxed(self) -> Box<dyn Shape>
+        where
+            Self: Sized + 'static,
+        {
+            Box::new(self)
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(mut, shared))]
+    #[cfg_attr(not(feature = "shared"), mock(mut))]
+    struct ShapeMock {
+        boxed: Vec<Square>,
+    }
+
+    impl ShapeMock {
+        fn area(this: &Mut<Self>, square: &Square) -> f64 {
+            this.call_real().scope(|| square.area()) + 1.0
+        }
+
+        fn boxed(this: &Mut<Self>, square: Square) -> Box<dyn Shape> {
+            this.borrow().boxed.push(square);
+            Box::new(Square(square.0 * 2.0))
+        }
+    }
+
+    let shape = Square(2.0).boxed();
+    assert!((shape.area() - 4.0).abs() < f64::EPSILON);
+
+    let guard = ShapeMock::default().set_as_mock();
+    let shape = Square(2.0).boxed();
+    assert!((shape.area() - 17.0).abs() < f64::EPSILON);
+    assert_eq!(guard.into_inner().boxed, [Square(2.0)]);
+}
+
+#[test]
+fn recursive_fn() {
+    #[mock(using = "FactorialMock")]
+    fn factorial(n: u64, acc: &mut u64) -> u64 {
+        if n <= 1 {
+            *acc
+        } else {
+            *acc = acc.overflowing_mul(n).0;
+            factorial(n - 1, acc)
+        }
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct FactorialMock {
+        fallback_once: AtomicBool,
+        switch: RealCallSwitch,
+    }
+
+    impl FactorialMock {
+        fn factorial(&self, n: u64, acc: &mut u64) -> u64 {
+            if n < 5 {
+                *acc // finish the recursion early
+            } else if self.fallback_once.load(Ordering::Relaxed) {
+                self.call_real_once().scope(|| factorial(n, acc))
+            } else {
+                // Fallback should be applied to both calls here
+                let _guard = self.call_real();
+                factorial(n, acc) * factorial(n - 5, &mut 1)
+            }
+        }
+    }
+
+    assert_eq!(factorial(4, &mut 1), 24);
+
+    let mut guard = FactorialMock::default().set_as_mock();
+    assert_eq!(factorial(4, &mut 1), 1);
+    assert_eq!(factorial(5, &mut 1), 120);
+    assert_eq!(factorial(10, &mut 1), 435_456_000);
+    assert_eq!(factorial(4, &mut 1), 1);
+
+    guard.with(|mock| {
+        mock.fallback_once = AtomicBool::new(true);
+    });
+    assert_eq!(factorial(4, &mut 1), 1);
+    assert_eq!(factorial(5, &mut 1), 5);
+    assert_eq!(factorial(10, &mut 1), 151200);
+
+    drop(guard);
+    assert_eq!(factorial(4, &mut 1), 24);
+}
+
+#[test]
+fn delegating_several_calls() {
+    #[mock(using = "FactorialMock")]
+    fn factorial(n: u64) -> u64 {
+        if n <= 1 {
+            1
+        } else {
+            n * factorial(n - 1)
+        }
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct FactorialMock {
+        real_calls: usize,
+        delegated: AtomicBool,
+        switch: RealCallSwitch,
+    }
+
+    impl FactorialMock {
+        fn factorial(&self, n: u64) -> u64 {
+            if self.delegated.swap(true, Ordering::SeqCst) {
+                1 // cut the recursion short
+            } else {
+                let value = self.call_real_n(self.real_calls).scope(|| factorial(n));
+                self.delegated.store(false, Ordering::SeqCst);
+                value
+            }
+        }
+    }
+
+    let mut guard = FactorialMock::default().set_as_mock();
+    assert_eq!(factorial(5), 1);
+    guard.with(|mock| mock.real_calls = 2);
+    assert_eq!(factorial(5), 5 * 4);
+    guard.with(|mock| mock.real_calls = 3);
+    assert_eq!(factorial(5), 5 * 4 * 3);
+    guard.with(|mock| mock.real_calls = 5);
+    assert_eq!(factorial(5), 120);
+    assert_eq!(factorial(10), 10 * 9 * 8 * 7 * 6);
+}
+
+#[test]
+fn checking_real_call_switch_state() {
+    #[mock(using = "CheckedMock")]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct CheckedMock {
+        switch: RealCallSwitch,
+    }
+
+    impl CheckedMock {
+        fn answer(&self) -> u32 {
+            assert!(!self.is_real_active());
+            let guard = self.call_real_once();
+            assert!(self.is_real_active());
+            assert!(self.is_real_active()); // the check does not consume the delegated call
+            guard.scope(|| {
+                let real_answer = answer();
+                assert!(!self.is_real_active());
+                real_answer + 1
+            })
+        }
+    }
+
+    let _guard = CheckedMock::default().set_as_mock();
+    assert_eq!(answer(), 43);
+    assert_eq!(answer(), 43);
+}
+
+#[test]
+fn nested_real_call_guards() {
+    #[mock(using = "NestedMock")]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct NestedMock {
+        entered: AtomicBool,
+        switch: RealCallSwitch,
+    }
+
+    impl NestedMock {
+        fn answer(&self) -> u32 {
+            if self.entered.swap(true, Ordering::SeqCst) {
+                return 0; // the innermost guard is exhausted
+            }
+            let outer_guard = self.call_real_n(2);
+            let inner_answer = self.call_real_once().scope(|| answer() + answer());
+            assert_eq!(inner_answer, 42);
+            assert!(self.is_real_active());
+            let outer_answer = outer_guard.scope(|| answer() + answer() + answer());
+            assert_eq!(outer_answer, 42 * 2);
+            self.entered.store(false, Ordering::SeqCst);
+            inner_answer + outer_answer
+        }
+    }
+
+    let _guard = NestedMock::default().set_as_mock();
+    assert_eq!(answer(), 42 * 3);
+}
+
+#[test]
+#[should_panic(expected = "prohibited for strict switches")]
+fn nested_real_call_guards_with_strict_switch() {
+    #[mock(using = "StrictMock")]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[derive(Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct StrictMock {
+        switch: RealCallSwitch,
+    }
+
+    impl StrictMock {
+        fn answer(&self) -> u32 {
+            let _guard = self.call_real();
+            self.call_real_once().scope(answer)
+        }
+    }
+
+    let state = StrictMock {
+        switch: RealCallSwitch::strict(),
+    };
+    let _guard = state.set_as_mock();
+    answer();
+}
+
+#[test]
+fn counting_mock_calls() {
+    #[mock(using = "CountedMock")]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[mock(using = "MutCountedMock")]
+    fn mut_answer() -> u32 {
+        42
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared, count))]
+    #[cfg_attr(not(feature = "shared"), mock(count))]
+    struct CountedMock {
+        switch: RealCallSwitch,
+    }
+
+    impl CountedMock {
+        fn answer(&self) -> u32 {
+            // The count includes the current call.
+            if Self::call_count() % 2 == 0 {
+                // The delegated call is not counted.
+                self.call_real().scope(answer)
+            } else {
+                0
+            }
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(mut, shared, count))]
+    #[cfg_attr(not(feature = "shared"), mock(mut, count))]
+    struct MutCountedMock {
+        calls: usize,
+    }
+
+    impl MutCountedMock {
+        fn mut_answer(this: &Mut<Self>) -> u32 {
+            this.borrow().calls += 1;
+            0
+        }
+    }
+
+    let guard = CountedMock::default().set_as_mock();
+    assert_eq!(answer(), 0);
+    assert_eq!(answer(), 42);
+    assert_eq!(answer(), 0);
+    assert_eq!(guard.call_count(), 3);
+    drop(guard);
+
+    let guard = MutCountedMock::default().set_as_mock();
+    assert_eq!(mut_answer(), 0);
+    assert_eq!(mut_answer(), 0);
+    assert_eq!(guard.call_count(), 2);
+    assert_eq!(MutCountedMock::call_count(), 2);
+    assert_eq!(guard.into_inner().calls, 2);
+}
+
+#[async_std::test]
+async fn collecting_dispatch_stats() {
+    #[mock(using = "StatsMock")]
+    fn answer(value: u32) -> u32 {
+        value
+    }
+
+    #[mock(using = "StatsMock")]
+    async fn async_answer(value: u32) -> u32 {
+        value
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared, stats))]
+    #[cfg_attr(not(feature = "shared"), mock(stats))]
+    struct StatsMock {
+        switch: RealCallSwitch,
+    }
+
+    impl StatsMock {
+        fn answer(&self, value: u32) -> u32 {
+            if value == 0 {
+                self.call_real().scope(|| answer(value))
+            } else {
+                value + 1
+            }
+        }
+
+        #[async_recursion]
+        async fn async_answer(this: MockRef<Self>, value: u32) -> u32 {
+            if value == 0 {
+                this.call_real().async_scope(async_answer(value)).await
+            } else {
+                value + 1
+            }
+        }
+    }
+
+    assert_eq!(answer(0), 0);
+    let guard = StatsMock::default().set_as_mock();
+    assert_eq!(answer(0), 0);
+    assert_eq!(answer(1), 2);
+    assert_eq!(async_answer(0).await, 0);
+    assert_eq!(async_answer(1).await, 2);
+    assert_eq!(async_answer(2).await, 3);
+
+    let stats = guard.dispatch_stats();
+    assert_eq!(stats.mock_calls("answer"), 2);
+    assert_eq!(stats.real_calls("answer"), 1);
+    assert_eq!(
+        stats.mock_calls("collecting_dispatch_stats::async_answer"),
+        3
+    );
+    assert_eq!(stats.real_calls("async_answer"), 1);
+    assert_eq!(StatsMock::dispatch_stats().mock_calls("answer"), 2);
+    let state = guard.into_inner();
+    assert_eq!(StatsMock::dispatch_stats().mock_calls("answer"), 0);
+
+    // Calls after the state is unset are not recorded; stats are collected separately
+    // each time the state is set.
+    assert_eq!(answer(1), 1);
+    let guard = state.set_as_mock();
+    assert_eq!(guard.dispatch_stats().mock_calls("answer"), 0);
+    assert_eq!(answer(1), 2);
+    let stats = StatsMock::dispatch_stats();
+    assert_eq!(stats.mock_calls("answer"), 1);
+    assert_eq!(stats.real_calls("answer"), 0);
+}
+
+#[test]
+fn collecting_dispatch_stats_for_methods() {
+    struct Foo;
+
+    #[mock(using = "StatsMock")]
+    impl Foo {
+        fn size() -> u32 {
+            1
+        }
+    }
+
+    struct Bar<T>(T);
+
+    #[mock(using = "StatsMock")]
+    impl<T> Bar<T> {
+        fn size() -> u32 {
+            2
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, stats, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(stats, auto_check))]
+    struct StatsMock;
+
+    impl StatsMock {
+        fn size(&self) -> u32 {
+            0
+        }
+    }
+
+    let guard = StatsMock.set_as_mock();
+    assert_eq!(Foo::size(), 0);
+    assert_eq!(Bar::<u8>::size(), 0);
+    assert_eq!(Bar::<String>::size(), 0);
+
+    let stats = guard.dispatch_stats();
+    assert_eq!(stats.mock_calls("size"), 3);
+    assert_eq!(stats.mock_calls("Foo::size"), 1);
+    assert_eq!(stats.mock_calls("Bar::size"), 2);
+    assert_eq!(
+        stats.mock_calls("collecting_dispatch_stats_for_methods::Bar::size"),
+        2
+    );
+}
+
+#[test]
+fn calling_real_impl_with_closures() {
+    #[mock(using = "CountdownMock")]
+    fn countdown(n: u32) -> u32 {
+        if n == 0 {
+            0
+        } else {
+            countdown(n - 1) + 1
+        }
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct CountdownMock {
+        switch: RealCallSwitch,
+    }
+
+    impl CountdownMock {
+        fn countdown(&self, n: u32) -> u32 {
+            match n {
+                0 => 100,
+                1..=5 => self.call_real_once_with(|| countdown(n)),
+                _ => self.call_real_with(|| countdown(n)),
+            }
+        }
+    }
+
+    let guard = CountdownMock::default().set_as_mock();
+    // All nested calls are delegated to the real impl.
+    assert_eq!(countdown(10), 10);
+    // Only the first call is delegated; the nested call is mocked, and so on.
+    assert_eq!(countdown(3), 103);
+    assert_eq!(guard.call_count(), 5);
+}
+
+#[test]
+fn spying_on_real_impl() {
+    #[mock(using = "SpyMock")]
+    fn countdown(n: u32) -> u32 {
+        if n == 0 {
+            0
+        } else {
+            countdown(n - 1) + 1
+        }
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct SpyMock {
+        outputs: Mutex<Vec<u32>>,
+        switch: RealCallSwitch,
+    }
+
+    impl SpyMock {
+        fn countdown(&self, n: u32) -> u32 {
+            self.spy(
+                || countdown(n),
+                |&output| self.outputs.lock().unwrap().push(output),
+            )
+        }
+    }
+
+    let guard = SpyMock::default().set_as_mock();
+    assert_eq!(countdown(3), 3);
+    assert_eq!(countdown(0), 0);
+    assert_eq!(countdown(5), 5);
+    // Nested calls are delegated to the real impl and are not recorded.
+    assert_eq!(guard.call_count(), 3);
+    let outputs = guard.into_inner().outputs.into_inner().unwrap();
+    assert_eq!(outputs, [3, 0, 5]);
+}
+
+#[test]
+fn storing_mock_guards_in_struct() {
+    #[mock(using = "HarnessMock")]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct HarnessMock(u32);
+
+    impl HarnessMock {
+        fn answer(&self) -> u32 {
+            self.0
+        }
+    }
+
+    struct Harness<T: Mock> {
+        guard: MockGuard<T>,
+        label: &'static str,
+    }
+
+    impl<T: Mock> Harness<T> {
+        fn new(state: T) -> Self {
+            Self {
+                guard: state.set_as_mock(),
+                label: "test",
+            }
+        }
+    }
+
+    let mut harness = Harness::new(HarnessMock(5));
+    assert_eq!(answer(), 5);
+    harness.guard.with(|state| state.0 = 23);
+    assert_eq!(answer(), 23);
+    assert_eq!(harness.guard.call_count(), 2);
+    assert_eq!(harness.label, "test");
+
+    drop(harness);
+    assert_eq!(answer(), 42);
+}
+
+#[test]
+fn strict_mode() {
+    #[mock(using = "StrictMock", strict)]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct StrictMock {
+        switch: RealCallSwitch,
+    }
+
+    impl StrictMock {
+        fn answer(&self) -> u32 {
+            if self.is_real_active() {
+                self.call_real().scope(answer)
+            } else {
+                self.call_real_once().scope(answer) + 1
+            }
+        }
+    }
+
+    // Without strict mode, calls are delegated to the real impl as usual.
+    assert_eq!(answer(), 42);
+    let guard = StrictMock::default().set_as_mock();
+    assert_eq!(answer(), 43);
+
+    let strict = mimicry::enable_strict_mode();
+    let err = panic::catch_unwind(answer).unwrap_err();
+    let err = err.downcast_ref::<String>().unwrap();
+    assert!(err.contains("`integration::answer`"), "{err}");
+    drop(guard);
+    panic::catch_unwind(answer).unwrap_err();
+
+    drop(strict);
+    assert_eq!(answer(), 42);
+}
+
+#[test]
+fn bypassing_mocks() {
+    #[mock(using = "BypassMock")]
+    fn answer(value: u32) -> u32 {
+        value
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct BypassMock {
+        switch: RealCallSwitch,
+    }
+
+    impl BypassMock {
+        fn answer(&self, value: u32) -> u32 {
+            match value {
+                0 => self.call_real().scope(|| answer(value) + 1),
+                1 => mimicry::bypass_mocks(|| answer(0)),
+                _ => value * 2,
+            }
+        }
+    }
+
+    let guard = BypassMock::default().set_as_mock();
+    assert_eq!(answer(0), 1);
+    assert_eq!(answer(1), 0);
+    assert_eq!(answer(5), 10);
+    assert_eq!(guard.call_count(), 3);
+
+    let output = mimicry::bypass_mocks(|| {
+        let nested = mimicry::bypass_mocks(|| answer(0) + answer(1));
+        nested + answer(5)
+    });
+    assert_eq!(output, 6);
+    assert_eq!(guard.call_count(), 3);
+
+    // Mocks must be restored after a panic.
+    panic::catch_unwind(|| mimicry::bypass_mocks(|| panic!("oops"))).unwrap_err();
+    assert_eq!(answer(5), 10);
+    assert_eq!(guard.call_count(), 4);
+}
+
+#[mock(using = "FixtureMock")]
+fn fixture_answer() -> u32 {
+    42
+}
+
+#[derive(Default, Mock)]
+#[cfg_attr(feature = "shared", mock(shared, auto_check))]
+#[cfg_attr(not(feature = "shared"), mock(auto_check))]
+struct FixtureMock {
+    value: AtomicU32,
+}
+
+impl FixtureMock {
+    fn fixture_answer(&self) -> u32 {
+        self.value.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+fn fixture_mock() -> MockGuard<FixtureMock> {
+    FixtureMock::default().set_as_mock()
+}
+
+#[test]
+fn using_mock_guard_as_fixture() {
+    for start in [0, 5] {
+        let mut fixture_mock = fixture_mock();
+        fixture_mock.with(|mock| *mock.value.get_mut() = start);
+        assert_eq!(fixture_answer(), start);
+        assert_eq!(fixture_answer(), start + 1);
+        assert_eq!(fixture_mock.into_inner().value.into_inner(), start + 2);
+        assert_eq!(fixture_answer(), 42);
+    }
+}
+
+#[test]
+fn delegating_calls_with_predicate() {
+    #[mock(using = "DoubleMock")]
+    fn double(value: u32) -> u32 {
+        value * 2
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct DoubleMock {
+        real_enabled: Arc<AtomicBool>,
+        delegated: AtomicBool,
+        switch: RealCallSwitch,
+    }
+
+    impl DoubleMock {
+        fn double(&self, value: u32) -> u32 {
+            if self.delegated.swap(true, Ordering::SeqCst) {
+                0 // the predicate returned `false`
+            } else {
+                let real_enabled = Arc::clone(&self.real_enabled);
+                let value = self
+                    .call_real_if(move || real_enabled.load(Ordering::SeqCst))
+                    .scope(|| double(value));
+                self.delegated.store(false, Ordering::SeqCst);
+                value
+            }
+        }
+    }
+
+    let real_enabled = Arc::<AtomicBool>::default();
+    let state = DoubleMock {
+        real_enabled: Arc::clone(&real_enabled),
+        ..DoubleMock::default()
+    };
+    let _guard = state.set_as_mock();
+    assert_eq!(double(21), 0);
+    real_enabled.store(true, Ordering::SeqCst);
+    assert_eq!(double(21), 42);
+    real_enabled.store(false, Ordering::SeqCst);
+    assert_eq!(double(21), 0);
+}
+
+#[derive(Default, Mock)]
+#[cfg_attr(feature = "shared", mock(shared))]
+struct ValueMock(AtomicU32);
+
+impl ValueMock {
+    fn value(&self) -> u32 {
+        self.0.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+impl mimicry::CheckRealCall for ValueMock {}
+
+#[mock(using = "ValueMock")]
+fn value() -> u32 {
+    0
+}
+
+#[cfg(feature = "shared")]
 #[test]
 #[allow(clippy::needless_collect)] // needed for threads to be spawned concurrently
 fn single_shared_mock_in_multi_thread_env() {
@@ -437,8 +2437,431 @@ fn single_shared_mock_in_multi_thread_env() {
         .sum::<u32>();
     assert_eq!(sum, 49 * 50 / 2);
 
-    let count = guard.into_inner().0.into_inner();
-    assert_eq!(count, 50);
+    let count = guard.into_inner().0.into_inner();
+    assert_eq!(count, 50);
+}
+
+#[cfg(feature = "shared")]
+#[test]
+#[allow(clippy::needless_collect)] // needed for threads to be spawned concurrently
+fn spying_in_multi_thread_env() {
+    #[mock(using = "SpyMock")]
+    fn add(x: u32, y: u32) -> u32 {
+        x + y
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[mock(shared)]
+    struct SpyMock {
+        calls: AtomicU32,
+        switch: RealCallSwitch,
+    }
+
+    impl SpyMock {
+        fn add(&self, x: u32, y: u32) -> u32 {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.call_real_once().scope(|| add(x, y))
+        }
+    }
+
+    let guard = SpyMock::default().set_as_mock();
+    let thread_handles: Vec<_> = (0..5)
+        .map(|i| thread::spawn(move || (0..10).map(|j| add(i, j)).sum::<u32>()))
+        .collect();
+    let sums: Vec<_> = thread_handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect();
+    assert_eq!(sums, [45, 55, 65, 75, 85]);
+
+    let calls = guard.into_inner().calls.into_inner();
+    assert_eq!(calls, 50);
+}
+
+#[cfg(feature = "shared")]
+#[test]
+fn snapshotting_shared_mock_in_multi_thread_env() {
+    #[mock(using = "LogMock")]
+    fn log(_value: u32) {
+        // Do nothing
+    }
+
+    #[derive(Default, Clone, Mock)]
+    #[mock(mut, shared)]
+    struct LogMock {
+        sum: u32,
+        count: u32,
+    }
+
+    impl LogMock {
+        fn log(this: &Mut<Self>, value: u32) {
+            let mut this = this.borrow();
+            this.sum += value;
+            this.count += 1;
+        }
+    }
+
+    let guard = LogMock::default().set_as_mock();
+    let thread_handles: Vec<_> = (0..5)
+        .map(|_| thread::spawn(|| (0..100).for_each(|_| log(2))))
+        .collect();
+    let mut prev_count = 0;
+    for _ in 0..10 {
+        let snapshot = guard.snapshot();
+        // The state is never observed partially updated.
+        assert_eq!(snapshot.sum, 2 * snapshot.count);
+        assert!(snapshot.count >= prev_count);
+        prev_count = snapshot.count;
+    }
+    for handle in thread_handles {
+        handle.join().unwrap();
+    }
+
+    let snapshot = guard.snapshot();
+    assert_eq!((snapshot.sum, snapshot.count), (1_000, 500));
+    let state = guard.into_inner();
+    assert_eq!((state.sum, state.count), (1_000, 500));
+}
+
+#[cfg(feature = "shared")]
+#[test]
+#[allow(clippy::needless_collect)] // needed for threads to be spawned concurrently
+fn concurrent_access_to_rw_mock() {
+    use std::sync::Barrier;
+
+    const THREAD_COUNT: usize = 5;
+
+    #[mock(using = "BarrierMock")]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[derive(Mock, CallReal)]
+    #[mock(shared = "rw")]
+    struct BarrierMock {
+        barrier: Barrier,
+        switch: RealCallSwitch,
+    }
+
+    impl BarrierMock {
+        fn answer(&self) -> u32 {
+            // Would deadlock if mock calls were serialized.
+            self.barrier.wait();
+            self.call_real().scope(answer)
+        }
+    }
+
+    let state = BarrierMock {
+        barrier: Barrier::new(THREAD_COUNT),
+        switch: RealCallSwitch::default(),
+    };
+    let _guard = state.set_as_mock();
+    let thread_handles: Vec<_> = (0..THREAD_COUNT).map(|_| thread::spawn(answer)).collect();
+    for handle in thread_handles {
+        assert_eq!(handle.join().unwrap(), 42);
+    }
+}
+
+#[test]
+#[allow(clippy::needless_collect)] // needed for threads to be spawned concurrently
+fn global_mock_in_multi_thread_env() {
+    #[mock(using = "GlobalMock")]
+    fn add(x: u32, y: u32) -> u32 {
+        x + y
+    }
+
+    #[derive(Default, Mock)]
+    #[mock(mut, global)]
+    struct GlobalMock {
+        args: Vec<(u32, u32)>,
+    }
+
+    impl GlobalMock {
+        fn add(this: &Mut<Self>, x: u32, y: u32) -> u32 {
+            this.borrow().args.push((x, y));
+            x * y
+        }
+    }
+
+    let guard = GlobalMock::default().set_as_mock();
+    let thread_handles: Vec<_> = (0..5)
+        .map(|i| thread::spawn(move || add(i, i + 1)))
+        .collect();
+    let products: Vec<_> = thread_handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect();
+    assert_eq!(products, [0, 2, 6, 12, 20]);
+
+    let mut args = guard.into_inner().args;
+    args.sort_unstable();
+    assert_eq!(args, [(0, 1), (1, 2), (2, 3), (3, 4), (4, 5)]);
+}
+
+#[test]
+fn checking_whether_mock_is_set() {
+    #[mock(using = "SetMock")]
+    fn answer() -> bool {
+        false
+    }
+
+    #[mock(using = "GlobalSetMock")]
+    fn global_answer() -> bool {
+        false
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct SetMock;
+
+    impl SetMock {
+        fn answer(&self) -> bool {
+            Self::is_mock_set()
+        }
+    }
+
+    #[derive(Default, Mock)]
+    #[mock(global, auto_check)]
+    struct GlobalSetMock;
+
+    impl GlobalSetMock {
+        fn global_answer(&self) -> bool {
+            Self::is_mock_set()
+        }
+    }
+
+    assert!(!SetMock::is_mock_set());
+    assert!(!answer());
+    let mut guard = SetMock.set_as_mock();
+    assert!(SetMock::is_mock_set());
+    assert!(answer());
+    assert!(guard.with(|_| SetMock::is_mock_set()));
+    drop(guard);
+    assert!(!SetMock::is_mock_set());
+
+    assert!(!GlobalSetMock::is_mock_set());
+    let guard = GlobalSetMock.set_as_mock();
+    assert!(GlobalSetMock::is_mock_set());
+    assert!(global_answer()); // must not deadlock
+    assert!(thread::spawn(GlobalSetMock::is_mock_set).join().unwrap());
+    drop(guard);
+    assert!(!GlobalSetMock::is_mock_set());
+}
+
+#[test]
+fn debug_impl_for_mock_state() {
+    #[derive(Default, Mock)]
+    #[mock(debug, auto_check)]
+    struct NamedMock {
+        value: u32,
+        filter: Option<Box<dyn Fn(u32) -> bool + Send>>,
+        answers: Answers<u32>,
+    }
+
+    #[derive(Default, Mock)]
+    #[mock(debug, auto_check)]
+    struct TupleMock(Option<Box<dyn Fn(u32) -> bool + Send>>, &'static str);
+
+    #[derive(Default, Mock)]
+    #[mock(debug, auto_check)]
+    struct GenericMock<T: Send> {
+        value: u32,
+        items: Vec<T>,
+    }
+
+    #[derive(Mock)]
+    #[mock(debug, auto_check)]
+    struct UnitMock;
+
+    let state = NamedMock::default();
+    assert_eq!(
+        format!("{state:?}"),
+        "NamedMock { value: 0, answers: Answers { calls: [], .. }, .. }"
+    );
+    let state = TupleMock(None, "test");
+    assert_eq!(format!("{state:?}"), "TupleMock(_, \"test\")");
+    let state = GenericMock {
+        value: 1,
+        items: vec![2_u32],
+    };
+    assert_eq!(format!("{state:?}"), "GenericMock { value: 1, .. }");
+    assert_eq!(format!("{:?}", UnitMock), "UnitMock");
+
+    let guard = NamedMock::default().set_as_mock();
+    let state = guard.into_inner();
+    assert!(format!("{state:#?}").contains("value: 0"));
+}
+
+#[test]
+fn stubbing_functions_with_closures() {
+    #[mock(using = "ClosureMock::call0")]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[mock(using = "ClosureMock::call1")]
+    fn double(value: u32) -> u32 {
+        value * 2
+    }
+
+    #[mock(using = "ClosureMock::call3")]
+    fn join(first: String, second: String, sep: char) -> String {
+        format!("{first}{sep}{second}")
+    }
+
+    let calls = Arc::new(AtomicU32::new(0));
+    let calls_in_stub = Arc::clone(&calls);
+    let guard = ClosureMock::default()
+        .stub("answer", || 23_u32)
+        .stub("integration::double", move |value: u32| {
+            calls_in_stub.fetch_add(1, Ordering::Relaxed);
+            value + 1
+        })
+        .stub("join", |first: String, _: String, _: char| first)
+        .stub("join", |_: String, second: String, _: char| second)
+        .set_as_mock();
+
+    assert_eq!(answer(), 23);
+    assert_eq!(double(3), 4);
+    assert_eq!(double(5), 6);
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+    assert_eq!(join("a".into(), "b".into(), '-'), "b");
+    drop(guard);
+    assert_eq!(double(3), 6);
+
+    let _guard = ClosureMock::default()
+        .stub("answer", || "wrong type")
+        .set_as_mock();
+    let err = panic::catch_unwind(answer).unwrap_err();
+    let err = err.downcast_ref::<String>().unwrap();
+    assert!(err.contains("has a different signature"), "{err}");
+    let err = panic::catch_unwind(|| double(1)).unwrap_err();
+    let err = err.downcast_ref::<String>().unwrap();
+    assert!(err.starts_with("no stub for function"), "{err}");
+}
+
+#[test]
+fn leaking_mock_guard() {
+    #[mock(using = "LeakedMock")]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[derive(Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct LeakedMock(u32);
+
+    impl LeakedMock {
+        fn answer(&self) -> u32 {
+            self.0
+        }
+    }
+
+    #[allow(clippy::needless_collect)] // needed for threads to be spawned concurrently
+    fn check_answers() -> Vec<u32> {
+        let handles: Vec<_> = (0..3).map(|_| thread::spawn(answer)).collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    }
+
+    assert_eq!(check_answers(), [42; 3]);
+    LeakedMock(23).set_as_mock().leak();
+    assert_eq!(answer(), 23);
+    assert!(LeakedMock::is_mock_set());
+
+    let expected = if cfg!(feature = "shared") { 23 } else { 42 };
+    assert_eq!(check_answers(), [expected; 3]);
+
+    // The leaked guard does not hold the exclusive lock on the state.
+    #[cfg(feature = "shared")]
+    {
+        let _lock = LeakedMock::lock();
+        assert_eq!(answer(), 23);
+        assert_eq!(check_answers(), [23; 3]);
+    }
+}
+
+#[test]
+fn setting_mock_for_next_call() {
+    #[mock(using = "OneShotMock")]
+    fn answer(value: u32) -> u32 {
+        value
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(mut, shared))]
+    #[cfg_attr(not(feature = "shared"), mock(mut))]
+    struct OneShotMock {
+        calls: Vec<u32>,
+    }
+
+    impl OneShotMock {
+        fn answer(this: &Mut<Self>, value: u32) -> u32 {
+            this.borrow().calls.push(value);
+            if value == 0 {
+                this.call_real().scope(|| answer(value))
+            } else {
+                value + 1
+            }
+        }
+    }
+
+    let guard = OneShotMock::default().set_for_next_call();
+    assert!(OneShotMock::is_mock_set());
+    assert_eq!(answer(1), 2);
+    assert!(!OneShotMock::is_mock_set());
+    assert_eq!(answer(2), 2);
+    assert_eq!(guard.call_count(), 1);
+    assert_eq!(guard.into_inner().calls, [1]);
+
+    // The nested call delegated to the real impl does not count towards the limit.
+    let guard = OneShotMock::default().set_for_next_call();
+    assert_eq!(answer(0), 0);
+    assert_eq!(guard.call_count(), 1);
+    assert_eq!(answer(3), 3);
+    assert_eq!(guard.into_inner().calls, [0]);
+
+    // Setting the state again lifts the limit.
+    let guard = OneShotMock::default().set_as_mock();
+    assert_eq!(answer(1), 2);
+    assert_eq!(answer(2), 3);
+    assert_eq!(guard.into_inner().calls, [1, 2]);
+}
+
+#[cfg(not(feature = "shared"))]
+#[test]
+fn stacking_mock_for_next_call() {
+    #[mock(using = "OneShotMock")]
+    fn answer(value: u32) -> u32 {
+        value
+    }
+
+    #[derive(Mock)]
+    #[mock(auto_check)]
+    struct OneShotMock(u32);
+
+    impl OneShotMock {
+        fn answer(&self, value: u32) -> u32 {
+            value + self.0
+        }
+    }
+
+    let outer_guard = OneShotMock(1).set_as_mock();
+    assert_eq!(answer(1), 2);
+    let inner_guard = OneShotMock(10).set_for_next_call();
+    assert_eq!(answer(1), 11);
+    assert_eq!(answer(1), 1);
+    assert_eq!(inner_guard.call_count(), 1);
+    drop(inner_guard);
+
+    // The outer state is not limited.
+    assert_eq!(answer(1), 2);
+    assert_eq!(answer(2), 3);
+    assert_eq!(outer_guard.call_count(), 3);
 }
 
 #[test]
@@ -488,6 +2911,369 @@ fn locking_shared_mocks() {
     second_test_handle.join().unwrap();
 }
 
+#[cfg(feature = "shared")]
+#[test]
+fn trying_to_lock_shared_mocks() {
+    #[derive(Mock)]
+    #[mock(shared, auto_check)]
+    struct LockedMock;
+
+    let guard = LockedMock.set_as_mock();
+    assert!(LockedMock::try_lock().is_none());
+    let locked = thread::spawn(|| LockedMock::try_lock().is_some());
+    assert!(!locked.join().unwrap());
+    drop(guard);
+
+    let guard = LockedMock::try_lock().unwrap();
+    assert!(LockedMock::try_lock().is_none());
+    drop(guard);
+    assert!(LockedMock::try_lock().is_some());
+}
+
+#[async_std::test]
+async fn mocking_stream_returning_method() {
+    use async_std::stream::{self, Stream, StreamExt};
+
+    use std::{pin::Pin, slice};
+
+    type BoxedStream<'a> = Pin<Box<dyn Stream<Item = &'a str> + Send + 'a>>;
+
+    struct EventSource {
+        events: Vec<String>,
+    }
+
+    // The mock impl must return the same type as the real impl. Here, streams are boxed
+    // in both impls, while iterators have a concrete type.
+    #[mock(using = "StreamMock")]
+    impl EventSource {
+        fn events(&self) -> impl Stream<Item = &str> + Send + '_ {
+            Box::pin(stream::from_iter(self.events.iter().map(String::as_str)))
+        }
+
+        fn last_events(&mut self, count: usize) -> impl Iterator<Item = &String> + Clone {
+            let start = self.events.len().saturating_sub(count);
+            self.events[start..].iter()
+        }
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct StreamMock {
+        switch: RealCallSwitch,
+    }
+
+    impl StreamMock {
+        fn events<'a>(&self, source: &'a EventSource) -> BoxedStream<'a> {
+            let real_events = self.call_real().scope(|| source.events());
+            Box::pin(stream::once("start").chain(real_events))
+        }
+
+        fn last_events<'a>(
+            &self,
+            source: &'a mut EventSource,
+            count: usize,
+        ) -> slice::Iter<'a, String> {
+            let end = count.min(source.events.len());
+            source.events[..end].iter()
+        }
+    }
+
+    fn push_event<'a>(mut events: Vec<&'a str>, event: &'a str) -> Vec<&'a str> {
+        events.push(event);
+        events
+    }
+
+    fn assert_send<T: Send>(value: T) -> T {
+        value
+    }
+
+    let mut source = EventSource {
+        events: vec!["first".to_owned(), "second".to_owned()],
+    };
+    let events = assert_send(source.events()).fold(vec![], push_event).await;
+    assert_eq!(events, ["first", "second"]);
+    let last_events = source.last_events(1);
+    assert_eq!(last_events.clone().collect::<Vec<_>>(), ["second"]);
+    assert_eq!(last_events.count(), 1);
+
+    let _guard = StreamMock::default().set_as_mock();
+    let events = source.events().fold(vec![], push_event).await;
+    assert_eq!(events, ["start", "first", "second"]);
+    let last_events: Vec<_> = source.last_events(1).cloned().collect();
+    assert_eq!(last_events, ["first"]);
+}
+
+#[test]
+fn mock_for_function_returning_wrapped_impl_trait() {
+    use std::{num::ParseIntError, str::Chars, vec};
+
+    // Outputs are not matched by the container name, so aliases work as well.
+    type ParseResult<T> = Result<T, ParseIntError>;
+
+    #[mock(using = "ParseMock")]
+    fn parse(s: &str) -> ParseResult<impl Iterator<Item = &str>> {
+        let count: usize = s.split(':').next().unwrap_or_default().parse()?;
+        Ok(s.split(',').take(count).collect::<Vec<_>>().into_iter())
+    }
+
+    #[mock(using = "ParseMock")]
+    fn first_word(s: &str) -> Option<impl Iterator<Item = char> + '_> {
+        s.split_whitespace().next().map(str::chars)
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct ParseMock {
+        switch: RealCallSwitch,
+    }
+
+    impl ParseMock {
+        fn parse<'a>(&self, s: &'a str) -> ParseResult<vec::IntoIter<&'a str>> {
+            if s.is_empty() {
+                Ok(vec!["empty"].into_iter())
+            } else {
+                let items: Vec<_> = self.call_real().scope(|| parse(s))?.collect();
+                Ok(items.into_iter().rev().collect::<Vec<_>>().into_iter())
+            }
+        }
+
+        fn first_word<'a>(&self, s: &'a str) -> Option<Chars<'a>> {
+            s.split_whitespace().last().map(str::chars)
+        }
+    }
+
+    let items: Vec<_> = parse("2:a,b,c").unwrap().collect();
+    assert_eq!(items, ["2:a", "b"]);
+    assert!(parse("??").is_err());
+    let word: String = first_word("hello world").unwrap().collect();
+    assert_eq!(word, "hello");
+
+    let _guard = ParseMock::default().set_as_mock();
+    let items: Vec<_> = parse("2:a,b,c").unwrap().collect();
+    assert_eq!(items, ["b", "2:a"]);
+    let items: Vec<_> = parse("").unwrap().collect();
+    assert_eq!(items, ["empty"]);
+    assert!(parse("??").is_err());
+    let word: String = first_word("hello world").unwrap().collect();
+    assert_eq!(word, "world");
+    assert!(first_word("").is_none());
+}
+
+#[async_std::test]
+async fn selecting_mock_variants() {
+    #[mock(using = "VariantMock", variants(doubled))]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[mock(using = "VariantMock", variants(failing, doubled))]
+    async fn fetch(key: &str) -> Result<usize, String> {
+        Ok(key.len())
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(mut, shared))]
+    #[cfg_attr(not(feature = "shared"), mock(mut))]
+    struct VariantMock {
+        variant: Option<&'static str>,
+        calls: usize,
+    }
+
+    impl MockVariant for VariantMock {
+        fn mock_variant(&self) -> Option<&'static str> {
+            self.variant
+        }
+    }
+
+    impl VariantMock {
+        fn answer(this: &Mut<Self>) -> u32 {
+            this.borrow().calls += 1;
+            this.call_real().scope(answer)
+        }
+
+        fn answer_doubled(this: &Mut<Self>) -> u32 {
+            Self::answer(this) * 2
+        }
+
+        async fn fetch(this: MockRef<Self>, key: &str) -> Result<usize, String> {
+            this.with_mut(|this| this.calls += 1);
+            Ok(key.len() + 1)
+        }
+
+        async fn fetch_failing(_: MockRef<Self>, key: &str) -> Result<usize, String> {
+            Err(format!("cannot fetch {key}"))
+        }
+
+        async fn fetch_doubled(this: MockRef<Self>, key: &str) -> Result<usize, String> {
+            Self::fetch(this, key).await.map(|value| value * 2)
+        }
+    }
+
+    let mut guard = VariantMock::default().set_as_mock();
+    assert_eq!(answer(), 42);
+    assert_eq!(fetch("test").await, Ok(5));
+
+    guard.with(|state| state.variant = Some("doubled"));
+    assert_eq!(answer(), 84);
+    assert_eq!(fetch("test").await, Ok(10));
+
+    guard.with(|state| state.variant = Some("failing"));
+    assert_eq!(answer(), 42); // `answer` has no `failing` variant
+    assert_eq!(fetch("test").await.unwrap_err(), "cannot fetch test");
+
+    assert_eq!(guard.into_inner().calls, 5);
+}
+
+#[test]
+fn mock_output_is_coerced_to_function_output() {
+    use std::{fmt, str::FromStr};
+
+    #[mock(using = "CoercedMock")]
+    fn counter(start: u32) -> Box<dyn Fn(u32) -> u32> {
+        Box::new(move |x| start + x)
+    }
+
+    #[mock(using = "CoercedMock")]
+    fn first_item(items: &mut [String]) -> &String {
+        &items[0]
+    }
+
+    #[mock(using = "CoercedMock")]
+    fn parse_len(s: &str) -> usize {
+        s.len()
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct CoercedMock;
+
+    impl CoercedMock {
+        // Unsized coercion: `Box<impl Fn(u32) -> u32>` -> `Box<dyn Fn(u32) -> u32>`.
+        fn counter(&self, start: u32) -> Box<impl Fn(u32) -> u32> {
+            Box::new(move |x| start * x)
+        }
+
+        // Reborrowing: `&mut String` -> `&String`.
+        fn first_item<'a>(&self, items: &'a mut [String]) -> &'a mut String {
+            let last = items.len() - 1;
+            &mut items[last]
+        }
+
+        // The output type is inferred from the function output.
+        fn parse_len<T: FromStr>(&self, s: &str) -> T
+        where
+            T::Err: fmt::Debug,
+        {
+            s.trim().parse().unwrap()
+        }
+    }
+
+    let _guard = CoercedMock.set_as_mock();
+    assert_eq!(counter(3)(5), 15);
+    let mut items = vec!["first".to_owned(), "last".to_owned()];
+    assert_eq!(first_item(&mut items), "last");
+    assert_eq!(parse_len(" 42 "), 42);
+}
+
+#[test]
+fn generated_idents_do_not_collide_with_function_body() {
+    fn call_once(value: u32) -> u32 {
+        value + 1
+    }
+
+    #[mock(using = "HygieneMock")]
+    fn values(instance: u32, mock_ref: u32) -> impl Iterator<Item = u32> {
+        let output = call_once(instance);
+        vec![output, mock_ref].into_iter()
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct HygieneMock;
+
+    impl HygieneMock {
+        fn values(&self, instance: u32, _: u32) -> std::vec::IntoIter<u32> {
+            vec![instance].into_iter()
+        }
+    }
+
+    assert_eq!(values(1, 5).collect::<Vec<_>>(), [2, 5]);
+    let _guard = HygieneMock.set_as_mock();
+    assert_eq!(values(1, 5).collect::<Vec<_>>(), [1]);
+}
+
+#[async_std::test]
+async fn accessing_mock_state_via_detached_ref() {
+    #[mock(using = "DetachedMock")]
+    async fn answer() -> u32 {
+        42
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(mut, shared))]
+    #[cfg_attr(not(feature = "shared"), mock(mut))]
+    struct DetachedMock {
+        calls: u32,
+        refs: Vec<MockRef<Self>>,
+    }
+
+    impl DetachedMock {
+        async fn answer(r: MockRef<Self>) -> u32 {
+            r.with_mut(|this| {
+                this.calls += 1;
+                this.refs.push(r);
+            });
+            0
+        }
+    }
+
+    let mut guard = DetachedMock::default().set_as_mock();
+    assert_eq!(answer().await, 0);
+    assert_eq!(answer().await, 0);
+    let detached_ref = guard.with(|state| state.refs[0]);
+    assert_eq!(detached_ref.try_with_mut(|this| this.calls), Some(2));
+
+    let state = guard.into_inner();
+    assert_eq!(state.calls, 2);
+    assert_eq!(detached_ref.try_with_mut(|this| this.calls), None);
+}
+
+#[test]
+fn panic_messages_name_mock_state() {
+    #[mock(using = "ReentrantMock")]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[derive(Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(mut, shared))]
+    #[cfg_attr(not(feature = "shared"), mock(mut))]
+    struct ReentrantMock;
+
+    impl ReentrantMock {
+        fn answer(this: &Mut<Self>) -> u32 {
+            let _state = this.borrow();
+            answer() // reentrant call while the state is borrowed
+        }
+    }
+
+    let guard = ReentrantMock.set_as_mock();
+    let err = panic::catch_unwind(answer).unwrap_err();
+    let err = err.downcast_ref::<String>().unwrap();
+    assert!(err.contains("`integration::panic_messages_name_mock_state::ReentrantMock`"));
+    assert!(err.contains("is already borrowed"), "{err}");
+    drop(guard);
+
+    let detached_ref = MockRef::<ReentrantMock>::new(ReentrantMock::instance());
+    let err =
+        panic::catch_unwind(panic::AssertUnwindSafe(|| detached_ref.with_mut(|_| ()))).unwrap_err();
+    let err = err.downcast_ref::<String>().unwrap();
+    assert!(err.contains("`integration::panic_messages_name_mock_state::ReentrantMock`"));
+    assert!(err.contains("is gone"), "{err}");
+}
+
 #[async_std::test]
 async fn mocking_async_function() {
     #[derive(Debug, Default, Mock)]
@@ -512,6 +3298,91 @@ async fn mocking_async_function() {
     assert_eq!(tested().await, 1);
 }
 
+#[async_std::test]
+async fn accessing_called_function_in_mock_impl() {
+    #[mock(using = "CallLogMock::log")]
+    fn answer() -> u32 {
+        42
+    }
+
+    #[mock(using = "CallLogMock::log_async")]
+    async fn async_answer() -> u32 {
+        answer()
+    }
+
+    #[derive(Default, Mock, CallReal)]
+    #[cfg_attr(feature = "shared", mock(shared))]
+    struct CallLogMock {
+        calls: Mutex<Vec<&'static str>>,
+        switch: RealCallSwitch,
+    }
+
+    impl CallLogMock {
+        fn log(&self) -> u32 {
+            let function = mimicry::called_function().unwrap();
+            self.calls.lock().unwrap().push(function);
+            0
+        }
+
+        async fn log_async(this: MockRef<Self>) -> u32 {
+            async_std::task::yield_now().await;
+            let function = mimicry::called_function().unwrap();
+            this.with(|this| this.calls.lock().unwrap().push(function));
+            let output = answer();
+            assert_eq!(mimicry::called_function(), Some(function));
+            output + 1
+        }
+    }
+
+    let guard = CallLogMock::default().set_as_mock();
+    assert_eq!(answer(), 0);
+    assert_eq!(async_answer().await, 1);
+    assert_eq!(mimicry::called_function(), None);
+
+    let calls = guard.into_inner().calls.into_inner().unwrap();
+    assert_eq!(
+        calls,
+        [
+            "integration::answer",
+            "integration::async_answer",
+            "integration::answer"
+        ]
+    );
+}
+
+#[async_std::test]
+async fn adjusting_mock_state_asynchronously() {
+    #[derive(Debug, Default, Mock)]
+    #[cfg_attr(feature = "shared", mock(shared, auto_check))]
+    #[cfg_attr(not(feature = "shared"), mock(auto_check))]
+    struct AsyncValueMock {
+        value: u32,
+    }
+
+    impl AsyncValueMock {
+        async fn tested(r: MockRef<Self>) -> u32 {
+            r.with(|this| this.value)
+        }
+    }
+
+    #[mock(using = "AsyncValueMock")]
+    async fn tested() -> u32 {
+        42
+    }
+
+    let mut guard = AsyncValueMock::default().set_as_mock();
+    assert_eq!(tested().await, 0);
+    let sum = guard
+        .with_async(|state| {
+            state.value = 5;
+            // The state is not borrowed when the future is polled.
+            async { tested().await + tested().await }
+        })
+        .await;
+    assert_eq!(sum, 10);
+    assert_eq!(guard.into_inner().value, 5);
+}
+
 #[async_std::test]
 async fn mocking_async_function_with_mutable_state() {
     #[derive(Debug, Default, Mock)]
@@ -541,3 +3412,48 @@ async fn mocking_async_function_with_mutable_state() {
     assert_eq!(tested().await, 42);
     assert_eq!(guard.into_inner().0, 42);
 }
+
+#[async_std::test]
+async fn mock_scopes() {
+    #[derive(Debug, Default, Mock)]
+    #[mock(mut)]
+    struct CounterMock(u32);
+
+    impl CounterMock {
+        fn sync_counter(this: &Mut<Self>) -> u32 {
+            this.borrow().0 += 1;
+            this.borrow().0
+        }
+
+        async fn async_counter(r: MockRef<Self>) -> u32 {
+            r.with_mut(|this| {
+                this.0 += 1;
+                this.0
+            })
+        }
+    }
+
+    #[mock(using = "CounterMock")]
+    fn sync_counter() -> u32 {
+        0
+    }
+
+    #[mock(using = "CounterMock")]
+    async fn async_counter() -> u32 {
+        0
+    }
+
+    let (sum, state) =
+        mimicry::with_mock(CounterMock::default(), || sync_counter() + sync_counter());
+    assert_eq!(sum, 3);
+    assert_eq!(state.0, 2);
+    assert_eq!(sync_counter(), 0);
+
+    let scope = state.async_mock_scope(async { async_counter().await + async_counter().await });
+    // The mock is not set until the future is polled.
+    assert_eq!(sync_counter(), 0);
+    let (sum, state) = scope.await;
+    assert_eq!(sum, 7);
+    assert_eq!(state.0, 4);
+    assert_eq!(async_counter().await, 0);
+}
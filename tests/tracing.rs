@@ -0,0 +1,119 @@
+//! Tests for the `tracing` integration.
+
+#![cfg(feature = "tracing")]
+
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use mimicry::{mock, CallReal, Mock, RealCallSwitch};
+
+/// Recorded `tracing` event.
+#[derive(Debug, Default)]
+struct RecordedEvent {
+    message: String,
+    function: String,
+    state: String,
+}
+
+impl Visit for RecordedEvent {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "function" => self.function = value.to_owned(),
+            "state" => self.state = value.to_owned(),
+            _ => { /* do nothing */ }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        }
+    }
+}
+
+/// Subscriber recording events emitted by `mimicry`.
+#[derive(Debug, Clone, Default)]
+struct RecordingSubscriber {
+    events: Arc<Mutex<Vec<RecordedEvent>>>,
+}
+
+impl Subscriber for RecordingSubscriber {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target().starts_with("mimicry")
+    }
+
+    fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(1)
+    }
+
+    fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {
+        // Do nothing
+    }
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {
+        // Do nothing
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        let mut recorded = RecordedEvent::default();
+        event.record(&mut recorded);
+        self.events.lock().unwrap().push(recorded);
+    }
+
+    fn enter(&self, _span: &span::Id) {
+        // Do nothing
+    }
+
+    fn exit(&self, _span: &span::Id) {
+        // Do nothing
+    }
+}
+
+#[mock(using = "ValueMock")]
+fn answer() -> usize {
+    42
+}
+
+#[derive(Default, Mock, CallReal)]
+struct ValueMock {
+    switch: RealCallSwitch,
+}
+
+impl ValueMock {
+    fn answer(&self) -> usize {
+        self.call_real().scope(answer) + 1
+    }
+}
+
+#[test]
+fn tracing_mock_dispatch() {
+    let subscriber = RecordingSubscriber::default();
+    let events = Arc::clone(&subscriber.events);
+    tracing::subscriber::with_default(subscriber, || {
+        assert_eq!(answer(), 42);
+        let _guard = ValueMock::default().set_as_mock();
+        assert_eq!(answer(), 43);
+    });
+
+    let events = events.lock().unwrap();
+    let messages: Vec<_> = events.iter().map(|event| event.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "calling real impl",
+            "dispatching call to mock impl",
+            "calling real impl"
+        ]
+    );
+    for event in events.iter() {
+        assert_eq!(event.function, "tracing::answer");
+        assert!(event.state.ends_with("ValueMock"), "{event:?}");
+    }
+}